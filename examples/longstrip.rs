@@ -1,6 +1,6 @@
 use anyhow::Result;
-use colorgrad::Color;
 use ddp_rs::connection;
+use ddp_rs::effects::Palette;
 use ddp_rs::protocol;
 
 // Testing a longer LED strip with offset
@@ -13,7 +13,7 @@ fn main() -> Result<()> {
         std::net::UdpSocket::bind("0.0.0.0:4048").unwrap(),
     )?;
 
-    let clr = argen(1200)?;
+    let clr = argen(1200);
 
     loop {
         conn.write_offset(&clr, 100)?;
@@ -21,21 +21,10 @@ fn main() -> Result<()> {
     }
 }
 
-fn argen(length: u32) -> Result<Vec<u8>> {
-    let mut vec = Vec::new();
-    let g = colorgrad::CustomGradient::new()
-        .colors(&[
-            Color::from_rgba8(255, 0, 0, 255),
-            Color::from_rgba8(0, 255, 0, 255),
-        ])
-        .build()?;
+fn argen(length: u32) -> Vec<u8> {
+    let palette = Palette::new(&[[255, 0, 0], [0, 255, 0]]);
 
-    for i in 0..length {
-        let color = g.at(i as f64 / length as f64).to_rgba8();
-        vec.push(color[0] as u8);
-        vec.push(color[1] as u8);
-        vec.push(color[2] as u8);
-    }
-
-    Ok(vec)
+    (0..length)
+        .flat_map(|i| palette.at(i as f32 / length as f32))
+        .collect()
 }
@@ -0,0 +1,37 @@
+//! DDP Sniffer Example
+//!
+//! Listens on port 4048 and prints every packet it receives, fully decoded.
+//!
+//! Usage:
+//! ```
+//! cargo run --example sniffer [-- 0.0.0.0:4048]
+//! ```
+//!
+//! Pass `--short` to print one line per packet instead.
+
+use anyhow::Result;
+use ddp_rs::packet::Packet;
+use std::net::UdpSocket;
+
+fn main() -> Result<()> {
+    let short = std::env::args().any(|a| a == "--short");
+    let addr = std::env::args()
+        .skip(1)
+        .find(|a| !a.starts_with("--"))
+        .unwrap_or_else(|| "0.0.0.0:4048".to_string());
+
+    let socket = UdpSocket::bind(&addr)?;
+    println!("Listening on {}", socket.local_addr()?);
+
+    let mut buf = [0u8; 1500];
+    loop {
+        let (size, from) = socket.recv_from(&mut buf)?;
+        let packet = Packet::from_bytes(&buf[..size]);
+
+        if short {
+            println!("{} {}", from, packet);
+        } else {
+            println!("--- {} ({} bytes)\n{:#}\n", from, size, packet);
+        }
+    }
+}
@@ -0,0 +1,544 @@
+//! Playing GIFs and image sequences on matrix displays.
+//!
+//! An [`Animation`] is decoded up front: every frame is scaled to the display with a
+//! [`Fit`], converted to the target [`PixelConfig`] and mapped through a
+//! [`Layout`] into strip order, so playback only has to send bytes on time.
+//!
+//! Requires the `image` feature.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use ddp_rs::animation::{Animation, Fit};
+//! use ddp_rs::connection::DDPConnection;
+//! use ddp_rs::layout::MatrixLayout;
+//! use ddp_rs::protocol::{PixelConfig, ID};
+//! use std::net::UdpSocket;
+//! use std::time::Duration;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut conn = DDPConnection::try_new(
+//!     "192.168.1.40:4048",
+//!     PixelConfig::default(),
+//!     ID::Default,
+//!     UdpSocket::bind("0.0.0.0:4048")?
+//! )?;
+//!
+//! let layout = MatrixLayout::new(32, 16).serpentine(true);
+//! let animation = Animation::open("nyan.gif", &layout, conn.pixel_config, Fit::Cover)?
+//!     .looping(true);
+//!
+//! animation.play_for(&mut conn, Duration::from_secs(60))?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::connection::DDPConnection;
+use crate::error::DDPError;
+use crate::layout::Layout;
+use crate::protocol::{DataType, PixelConfig};
+use image::codecs::gif::GifDecoder;
+use image::imageops::{self, FilterType};
+use image::{AnimationDecoder, ImageFormat, RgbaImage};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Frame rate used for image sequences and still images.
+pub const DEFAULT_FPS: f32 = 25.0;
+
+/// GIFs asking for less than this get the 100ms browsers use instead.
+const MIN_GIF_DELAY: Duration = Duration::from_millis(20);
+
+/// How an image is fitted to the display when their sizes differ.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum Fit {
+    /// Scale to exactly the display size, ignoring aspect ratio
+    Stretch,
+    /// Scale to fit entirely inside the display, leaving black bars
+    #[default]
+    Contain,
+    /// Scale to fill the display, cropping what sticks out
+    Cover,
+    /// No scaling, centered and cropped or padded
+    Center,
+}
+
+/// Scales `image` onto a `layout` sized display and returns the pixels in strip
+/// order, in the format given by `pixel_config`.
+///
+/// Transparent areas are blended onto black.
+pub fn render<L: Layout>(
+    image: &RgbaImage,
+    layout: &L,
+    pixel_config: PixelConfig,
+    fit: Fit,
+) -> Vec<u8> {
+    let (width, height) = (layout.width(), layout.height());
+    let channels = pixel_config.data_type.channels();
+    let mut out = vec![0u8; layout.len() * channels];
+
+    if width == 0 || height == 0 || image.width() == 0 || image.height() == 0 {
+        return out;
+    }
+
+    let (iw, ih) = (image.width() as f32, image.height() as f32);
+    let (sw, sh) = match fit {
+        Fit::Stretch => (width as u32, height as u32),
+        Fit::Center => (image.width(), image.height()),
+        Fit::Contain | Fit::Cover => {
+            let (sx, sy) = (width as f32 / iw, height as f32 / ih);
+            let scale = if fit == Fit::Contain {
+                sx.min(sy)
+            } else {
+                sx.max(sy)
+            };
+            (
+                ((iw * scale).round() as u32).max(1),
+                ((ih * scale).round() as u32).max(1),
+            )
+        }
+    };
+
+    let scaled;
+    let source = if (sw, sh) == image.dimensions() {
+        image
+    } else {
+        scaled = imageops::resize(image, sw, sh, FilterType::Triangle);
+        &scaled
+    };
+
+    // Top left of the scaled image relative to the display, negative when cropped
+    let ox = (width as i64 - sw as i64) / 2;
+    let oy = (height as i64 - sh as i64) / 2;
+
+    for y in 0..height {
+        for x in 0..width {
+            let (src_x, src_y) = (x as i64 - ox, y as i64 - oy);
+            if src_x < 0 || src_y < 0 || src_x >= sw as i64 || src_y >= sh as i64 {
+                continue;
+            }
+            let Some(i) = layout.index(x, y) else {
+                continue;
+            };
+
+            let px = source.get_pixel(src_x as u32, src_y as u32).0;
+            convert(
+                px,
+                pixel_config.data_type,
+                &mut out[i * channels..(i + 1) * channels],
+            );
+        }
+    }
+
+    out
+}
+
+/// Converts one RGBA pixel into `out`, which holds one pixel of `data_type`.
+fn convert([r, g, b, a]: [u8; 4], data_type: DataType, out: &mut [u8]) {
+    let blend = |c: u8| ((c as u16 * a as u16 + 127) / 255) as u8;
+    let (r, g, b) = (blend(r), blend(g), blend(b));
+
+    match data_type {
+        DataType::RGB | DataType::Undefined => out.copy_from_slice(&[r, g, b]),
+        DataType::RGBW => {
+            let w = r.min(g).min(b);
+            out.copy_from_slice(&[r - w, g - w, b - w, w]);
+        }
+        DataType::Grayscale => {
+            // Rec. 601 luma
+            let luma = (299 * r as u32 + 587 * g as u32 + 114 * b as u32 + 500) / 1000;
+            out[0] = luma as u8;
+        }
+        DataType::HSL => out.copy_from_slice(&rgb_to_hsl(r, g, b)),
+    }
+}
+
+fn rgb_to_hsl(r: u8, g: u8, b: u8) -> [u8; 3] {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+    let d = max - min;
+
+    if d == 0.0 {
+        return [0, 0, (l * 255.0).round() as u8];
+    }
+
+    let s = d / (1.0 - (2.0 * l - 1.0).abs());
+    let h = if max == r {
+        ((g - b) / d).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / d + 2.0
+    } else {
+        (r - g) / d + 4.0
+    } / 6.0;
+
+    [
+        (h * 255.0).round() as u8,
+        (s * 255.0).round() as u8,
+        (l * 255.0).round() as u8,
+    ]
+}
+
+/// A decoded animation, ready to send.
+#[derive(Debug, Clone)]
+pub struct Animation {
+    frames: Vec<Vec<u8>>,
+    delays: Vec<Duration>,
+    fps: Option<f32>,
+    looping: bool,
+}
+
+impl Animation {
+    /// Builds an animation from already decoded images and how long each is shown.
+    pub fn from_images<L, I>(images: I, layout: &L, pixel_config: PixelConfig, fit: Fit) -> Self
+    where
+        L: Layout,
+        I: IntoIterator<Item = (RgbaImage, Duration)>,
+    {
+        let (frames, delays) = images
+            .into_iter()
+            .map(|(image, delay)| (render(&image, layout, pixel_config, fit), delay))
+            .unzip();
+
+        Animation {
+            frames,
+            delays,
+            fps: None,
+            looping: false,
+        }
+    }
+
+    /// Opens a GIF, using the delays it was saved with, or any other supported image
+    /// as a single frame.
+    pub fn open<P: AsRef<Path>, L: Layout>(
+        path: P,
+        layout: &L,
+        pixel_config: PixelConfig,
+        fit: Fit,
+    ) -> Result<Self, DDPError> {
+        let path = path.as_ref();
+
+        if ImageFormat::from_path(path).ok() == Some(ImageFormat::Gif) {
+            let reader = BufReader::new(File::open(path)?);
+            return Self::from_gif(reader, layout, pixel_config, fit);
+        }
+
+        Self::open_sequence([path], layout, pixel_config, fit)
+    }
+
+    /// Decodes every frame of a GIF.
+    pub fn from_gif<R: BufRead + Seek, L: Layout>(
+        reader: R,
+        layout: &L,
+        pixel_config: PixelConfig,
+        fit: Fit,
+    ) -> Result<Self, DDPError> {
+        let frames = GifDecoder::new(reader)?.into_frames().collect_frames()?;
+
+        let images = frames.into_iter().map(|frame| {
+            let delay = Duration::from(frame.delay());
+            let delay = if delay < MIN_GIF_DELAY {
+                Duration::from_millis(100)
+            } else {
+                delay
+            };
+            (frame.into_buffer(), delay)
+        });
+
+        Ok(Self::from_images(images, layout, pixel_config, fit))
+    }
+
+    /// Loads one image file per frame, shown at [`DEFAULT_FPS`] unless changed with
+    /// [`fps`](Self::fps).
+    ///
+    /// Frames are played in the order given, so sort directory listings first.
+    pub fn open_sequence<I, P, L>(
+        paths: I,
+        layout: &L,
+        pixel_config: PixelConfig,
+        fit: Fit,
+    ) -> Result<Self, DDPError>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+        L: Layout,
+    {
+        let delay = Duration::from_secs_f64(1.0 / DEFAULT_FPS as f64);
+        let images = paths
+            .into_iter()
+            .map(|path| Ok((image::open(path)?.into_rgba8(), delay)))
+            .collect::<Result<Vec<_>, DDPError>>()?;
+
+        Ok(Self::from_images(images, layout, pixel_config, fit))
+    }
+
+    /// Plays at a fixed frame rate instead of the per-frame delays.
+    pub fn fps(mut self, fps: f32) -> Self {
+        self.fps = Some(fps.max(0.001));
+        self
+    }
+
+    /// Starts over from the first frame after the last one.
+    pub fn looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    /// Number of frames.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Whether there are no frames.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Pixel data of frame `index` in strip order.
+    pub fn frame(&self, index: usize) -> Option<&[u8]> {
+        self.frames.get(index).map(Vec::as_slice)
+    }
+
+    /// How long frame `index` is shown for.
+    pub fn delay(&self, index: usize) -> Option<Duration> {
+        match self.fps {
+            Some(fps) if index < self.len() => Some(Duration::from_secs_f64(1.0 / fps as f64)),
+            _ => self.delays.get(index).copied(),
+        }
+    }
+
+    /// Time to play every frame once.
+    pub fn duration(&self) -> Duration {
+        (0..self.len()).filter_map(|i| self.delay(i)).sum()
+    }
+
+    /// Plays the animation once, or forever when looping.
+    pub fn play(&self, conn: &mut DDPConnection) -> Result<(), DDPError> {
+        self.play_until(conn, None)
+    }
+
+    /// Plays like [`play`](Self::play) but stops after `duration`.
+    pub fn play_for(&self, conn: &mut DDPConnection, duration: Duration) -> Result<(), DDPError> {
+        self.play_until(conn, Some(Instant::now() + duration))
+    }
+
+    fn play_until(
+        &self,
+        conn: &mut DDPConnection,
+        deadline: Option<Instant>,
+    ) -> Result<(), DDPError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        // Schedule against the start rather than sleeping per frame, so send time
+        // doesn't make the animation drift
+        let mut due = Instant::now();
+
+        loop {
+            for (i, frame) in self.frames.iter().enumerate() {
+                if let Some(deadline) = deadline.filter(|d| due >= *d) {
+                    std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+                    return Ok(());
+                }
+                std::thread::sleep(due.saturating_duration_since(Instant::now()));
+
+                conn.write(frame)?;
+                due += self.delay(i).unwrap_or_default();
+            }
+
+            if !self.looping {
+                break;
+            }
+        }
+
+        // Hold the last frame for its delay before returning
+        let end = deadline.map_or(due, |d| d.min(due));
+        std::thread::sleep(end.saturating_duration_since(Instant::now()));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::MatrixLayout;
+    use crate::protocol::ID;
+    use image::codecs::gif::GifEncoder;
+    use image::{Delay, Frame, Rgba};
+    use std::io::Cursor;
+    use std::net::UdpSocket;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+
+    fn pixels(data: &[u8], channels: usize) -> Vec<&[u8]> {
+        data.chunks(channels).collect()
+    }
+
+    #[test]
+    fn test_render_stretch_and_layout() {
+        // Left half red, right half blue
+        let image = RgbaImage::from_fn(2, 1, |x, _| if x == 0 { RED } else { BLUE });
+        let layout = MatrixLayout::new(4, 2).serpentine(true);
+
+        let data = render(&image, &layout, PixelConfig::default(), Fit::Stretch);
+        let data = pixels(&data, 3);
+
+        assert_eq!(data.len(), 8);
+        // First row runs left to right, second right to left
+        assert_eq!(data[0], &[255, 0, 0]);
+        assert_eq!(data[3], &[0, 0, 255]);
+        assert_eq!(data[4], &[0, 0, 255]);
+        assert_eq!(data[7], &[255, 0, 0]);
+    }
+
+    #[test]
+    fn test_render_contain_cover_center() {
+        let image = RgbaImage::from_pixel(2, 2, RED);
+        let layout = MatrixLayout::new(4, 2);
+        let config = PixelConfig::default();
+
+        // Scaled to 2x2 and centered, black bars left and right
+        let contain = render(&image, &layout, config, Fit::Contain);
+        let contain = pixels(&contain, 3);
+        assert_eq!(contain[0], &[0, 0, 0]);
+        assert_eq!(contain[1], &[255, 0, 0]);
+        assert_eq!(contain[2], &[255, 0, 0]);
+        assert_eq!(contain[3], &[0, 0, 0]);
+
+        // Scaled to 4x4 and cropped, everything red
+        let cover = render(&image, &layout, config, Fit::Cover);
+        assert!(pixels(&cover, 3).iter().all(|p| p == &[255, 0, 0]));
+
+        // 6x6 unscaled, center cropped
+        let big = RgbaImage::from_fn(6, 6, |x, _| if x == 1 { BLUE } else { RED });
+        let center = render(&big, &layout, config, Fit::Center);
+        let center = pixels(&center, 3);
+        assert_eq!(center[0], &[0, 0, 255]);
+        assert_eq!(center[1], &[255, 0, 0]);
+    }
+
+    #[test]
+    fn test_convert_formats() {
+        let mut out = [0u8; 4];
+        convert([255, 128, 64, 255], DataType::RGBW, &mut out);
+        assert_eq!(out, [191, 64, 0, 64]);
+
+        let mut out = [0u8; 3];
+        convert([255, 255, 255, 128], DataType::RGB, &mut out);
+        assert_eq!(out, [128, 128, 128]);
+
+        let mut out = [0u8; 1];
+        convert([255, 255, 255, 255], DataType::Grayscale, &mut out);
+        assert_eq!(out, [255]);
+
+        assert_eq!(rgb_to_hsl(255, 0, 0), [0, 255, 128]);
+        assert_eq!(rgb_to_hsl(0, 0, 255), [170, 255, 128]);
+        assert_eq!(rgb_to_hsl(128, 128, 128), [0, 0, 128]);
+    }
+
+    fn gif(frames: &[(Rgba<u8>, u32)]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut bytes);
+            for (color, ms) in frames {
+                let frame = Frame::from_parts(
+                    RgbaImage::from_pixel(2, 2, *color),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(*ms, 1),
+                );
+                encoder.encode_frame(frame).unwrap();
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_from_gif() {
+        let bytes = gif(&[(RED, 50), (BLUE, 0)]);
+        let layout = MatrixLayout::new(2, 2);
+        let animation = Animation::from_gif(
+            Cursor::new(bytes),
+            &layout,
+            PixelConfig::default(),
+            Fit::Stretch,
+        )
+        .unwrap();
+
+        assert_eq!(animation.len(), 2);
+        assert_eq!(&animation.frame(0).unwrap()[..3], &[255, 0, 0]);
+        assert_eq!(&animation.frame(1).unwrap()[..3], &[0, 0, 255]);
+        assert_eq!(animation.delay(0), Some(Duration::from_millis(50)));
+        // Zero delay is played like browsers do
+        assert_eq!(animation.delay(1), Some(Duration::from_millis(100)));
+        assert_eq!(animation.duration(), Duration::from_millis(150));
+
+        let animation = animation.fps(10.0);
+        assert_eq!(animation.delay(0), Some(Duration::from_millis(100)));
+        assert_eq!(animation.delay(2), None);
+    }
+
+    #[test]
+    fn test_open_sequence() {
+        let dir = std::env::temp_dir().join(format!("ddp-rs-animation-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = [dir.join("0.png"), dir.join("1.png")];
+        RgbaImage::from_pixel(1, 1, RED).save(&paths[0]).unwrap();
+        RgbaImage::from_pixel(1, 1, BLUE).save(&paths[1]).unwrap();
+
+        let layout = MatrixLayout::new(1, 1);
+        let config = PixelConfig {
+            data_type: DataType::Grayscale,
+            ..PixelConfig::default()
+        };
+        let animation = Animation::open_sequence(&paths, &layout, config, Fit::Contain);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let animation = animation.unwrap();
+
+        assert_eq!(animation.frame(0), Some(&[76u8][..]));
+        assert_eq!(animation.frame(1), Some(&[29u8][..]));
+        assert_eq!(
+            animation.delay(0),
+            Some(Duration::from_secs_f64(1.0 / DEFAULT_FPS as f64))
+        );
+    }
+
+    #[test]
+    fn test_play() {
+        let display = UdpSocket::bind("127.0.0.1:0").unwrap();
+        display
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let mut conn = DDPConnection::try_new(
+            display.local_addr().unwrap(),
+            PixelConfig::default(),
+            ID::Default,
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+        )
+        .unwrap();
+
+        let layout = MatrixLayout::new(1, 1);
+        let animation = Animation::from_images(
+            [RED, BLUE].map(|c| (RgbaImage::from_pixel(1, 1, c), Duration::from_millis(20))),
+            &layout,
+            PixelConfig::default(),
+            Fit::Stretch,
+        );
+
+        let start = Instant::now();
+        animation.play(&mut conn).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(40));
+
+        let mut buf = [0u8; 1500];
+        let (size, _) = display.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[10..size], &[255, 0, 0]);
+        let (size, _) = display.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[10..size], &[0, 0, 255]);
+    }
+}
@@ -0,0 +1,749 @@
+//! `ddp` - talk to DDP displays from the command line.
+//!
+//! Built with the `cli` feature:
+//!
+//! ```text
+//! cargo install ddp-rs --features cli
+//! ddp discover
+//! ddp status 192.168.1.40
+//! ddp status fd00::40
+//! ddp discover --broadcast '[ff02::1]:4048'
+//! ddp fill 192.168.1.40 '#ff8000' --pixels 300
+//! ddp --format json config get 192.168.1.40
+//! ```
+
+use clap::{Parser, Subcommand, ValueEnum};
+use ddp_rs::connection::DDPConnection;
+use ddp_rs::discovery;
+use ddp_rs::packet::Packet;
+use ddp_rs::protocol::message::{ConfigRoot, Message};
+use ddp_rs::protocol::{DataType, PixelConfig, PixelFormat, ID};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+#[derive(Parser)]
+#[command(
+    name = "ddp",
+    version,
+    about = "Talk to Distributed Display Protocol devices"
+)]
+struct Cli {
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Human, global = true)]
+    format: Format,
+
+    /// Local address to send from, any address of the display's IP version by default
+    #[arg(long, global = true)]
+    bind: Option<SocketAddr>,
+
+    /// How long to wait for replies, in milliseconds
+    #[arg(long, default_value_t = 1000, global = true)]
+    timeout: u64,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Human,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Find displays on the local network
+    Discover {
+        /// Address the STATUS query is broadcast to, such as `[ff02::1]:4048` for IPv6
+        #[arg(long, default_value = discovery::BROADCAST)]
+        broadcast: String,
+    },
+
+    /// Show the status of a display
+    Status {
+        /// Display address, the port defaults to 4048
+        host: String,
+    },
+
+    /// Read or change the configuration of a display
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Send pixel data
+    Send {
+        /// Display address, the port defaults to 4048
+        host: String,
+
+        /// Pixel colors, one per pixel (#rrggbb, #rrggbbww, `r,g,b[,w]` or a name)
+        #[arg(long, num_args = 1.., required_unless_present = "file", conflicts_with = "file")]
+        color: Vec<Color>,
+
+        /// File with raw pixel data
+        #[arg(long)]
+        file: Option<PathBuf>,
+
+        /// Byte offset to write at
+        #[arg(long, default_value_t = 0)]
+        offset: u32,
+    },
+
+    /// Set a range of pixels to one color
+    Fill {
+        /// Display address, the port defaults to 4048
+        host: String,
+
+        /// Color (#rrggbb, #rrggbbww, `r,g,b[,w]` or a name)
+        color: Color,
+
+        /// Number of pixels
+        #[arg(long, short)]
+        pixels: usize,
+
+        /// First pixel
+        #[arg(long, default_value_t = 0)]
+        start: usize,
+    },
+
+    /// Play a test pattern
+    TestPattern {
+        /// Display address, the port defaults to 4048
+        host: String,
+
+        /// Number of pixels
+        #[arg(long, short)]
+        pixels: usize,
+
+        /// Pattern to play
+        #[arg(long, value_enum, default_value_t = Pattern::Rgb)]
+        pattern: Pattern,
+
+        /// Frames per second
+        #[arg(long, default_value_t = 30.0)]
+        fps: f32,
+
+        /// Stop after this many seconds instead of running forever
+        #[arg(long)]
+        duration: Option<f32>,
+    },
+
+    /// Print DDP packets received on a port
+    Sniff {
+        /// Address to listen on
+        #[arg(default_value = "0.0.0.0:4048")]
+        listen: String,
+
+        /// Stop after this many packets
+        #[arg(long, short)]
+        count: Option<usize>,
+
+        /// Print every header field and the full payload
+        #[arg(long, short)]
+        verbose: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the configuration
+    Get {
+        /// Display address, the port defaults to 4048
+        host: String,
+    },
+
+    /// Change the configuration
+    ///
+    /// Fields that are not given are read from the display first and written back
+    /// unchanged. The config is read again afterwards to check it was applied, and
+    /// the fields that changed are printed.
+    Set {
+        /// Display address, the port defaults to 4048
+        host: String,
+
+        /// IP address
+        #[arg(long)]
+        ip: Option<Ipv4Addr>,
+
+        /// Netmask
+        #[arg(long)]
+        nm: Option<Ipv4Addr>,
+
+        /// Gateway
+        #[arg(long)]
+        gw: Option<Ipv4Addr>,
+
+        /// Output port to change the start, length or string type of
+        #[arg(long, requires = "port_settings")]
+        port: Option<u32>,
+
+        /// First pixel of the port
+        #[arg(long, requires = "port", group = "port_settings")]
+        start: Option<u32>,
+
+        /// Number of pixels on the port
+        #[arg(long, requires = "port", group = "port_settings")]
+        length: Option<u32>,
+
+        /// Pixel string type of the port
+        #[arg(long, requires = "port", group = "port_settings")]
+        string_type: Option<u32>,
+
+        /// JSON file with the complete configuration to write
+        #[arg(long, conflicts_with_all = ["ip", "nm", "gw", "port"])]
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Pattern {
+    /// Whole strip red, green, blue, one second each
+    Rgb,
+    /// A single white pixel running down the strip
+    Chase,
+    /// A moving rainbow
+    Rainbow,
+    /// Full white
+    White,
+}
+
+/// A pixel color with 3 (RGB) or 4 (RGBW) channels.
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Color(Vec<u8>);
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let named = match s.to_ascii_lowercase().as_str() {
+            "black" | "off" => Some(vec![0, 0, 0]),
+            "white" => Some(vec![255, 255, 255]),
+            "red" => Some(vec![255, 0, 0]),
+            "green" => Some(vec![0, 255, 0]),
+            "blue" => Some(vec![0, 0, 255]),
+            "yellow" => Some(vec![255, 255, 0]),
+            "cyan" => Some(vec![0, 255, 255]),
+            "magenta" => Some(vec![255, 0, 255]),
+            _ => None,
+        };
+        if let Some(c) = named {
+            return Ok(Color(c));
+        }
+
+        let channels: Vec<u8> = if let Some(hex) = s.strip_prefix('#') {
+            if !hex.is_ascii() || (hex.len() != 6 && hex.len() != 8) {
+                return Err(format!("expected #rrggbb or #rrggbbww, got {s}"));
+            }
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+                .collect::<std::result::Result<_, _>>()
+                .map_err(|e| format!("invalid hex color {s}: {e}"))?
+        } else {
+            s.split(',')
+                .map(|c| c.trim().parse::<u8>())
+                .collect::<std::result::Result<_, _>>()
+                .map_err(|e| format!("invalid color {s}: {e}"))?
+        };
+
+        match channels.len() {
+            3 | 4 => Ok(Color(channels)),
+            n => Err(format!("expected 3 or 4 channels, got {n}")),
+        }
+    }
+}
+
+fn pixel_config(channels: usize) -> PixelConfig {
+    if channels == 4 {
+        PixelConfig {
+            data_type: DataType::RGBW,
+            data_size: PixelFormat::Pixel32Bits,
+            customer_defined: false,
+        }
+    } else {
+        PixelConfig::default()
+    }
+}
+
+fn with_port(host: &str) -> String {
+    if host.parse::<Ipv6Addr>().is_ok() {
+        format!("[{host}]:{}", discovery::PORT)
+    } else if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{host}:{}", discovery::PORT)
+    }
+}
+
+fn message_json(message: &Message) -> Value {
+    match message {
+        Message::Unparsed((_, s)) => Value::String(s.clone()),
+        m => {
+            let bytes: Vec<u8> = m.clone().try_into().unwrap_or_default();
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null)
+        }
+    }
+}
+
+fn reply_json(packet: &Packet) -> Value {
+    match &packet.parsed {
+        Some(m) => message_json(m),
+        None => Value::String(String::from_utf8_lossy(&packet.data).into_owned()),
+    }
+}
+
+/// Prints a JSON object as indented `key: value` lines.
+fn print_human(value: &Value, indent: usize) {
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map {
+                match v {
+                    Value::Object(_) | Value::Array(_) => {
+                        println!("{:indent$}{k}:", "");
+                        print_human(v, indent + 2);
+                    }
+                    v => println!("{:indent$}{k}: {}", "", scalar(v)),
+                }
+            }
+        }
+        Value::Array(items) => {
+            for (i, v) in items.iter().enumerate() {
+                println!("{:indent$}[{i}]", "");
+                print_human(v, indent + 2);
+            }
+        }
+        v => println!("{:indent$}{}", "", scalar(v)),
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => "-".to_string(),
+        v => v.to_string(),
+    }
+}
+
+/// Config fields to change, from `config set`.
+struct ConfigEdit {
+    ip: Option<Ipv4Addr>,
+    nm: Option<Ipv4Addr>,
+    gw: Option<Ipv4Addr>,
+    port: Option<u32>,
+    start: Option<u32>,
+    length: Option<u32>,
+    string_type: Option<u32>,
+}
+
+struct App {
+    format: Format,
+    bind: Option<SocketAddr>,
+    timeout: Duration,
+}
+
+impl App {
+    fn connect(&self, host: &str, pixel_config: PixelConfig) -> Result<DDPConnection> {
+        let mut builder = DDPConnection::builder(with_port(host)).pixel_config(pixel_config);
+        if let Some(bind) = self.bind {
+            builder = builder.bind(bind);
+        }
+        Ok(builder.build()?)
+    }
+
+    fn output(&self, value: &Value, human: impl FnOnce()) {
+        match self.format {
+            Format::Json => println!("{value}"),
+            Format::Human => human(),
+        }
+    }
+
+    fn query(&self, host: &str, id: ID) -> Result<Packet> {
+        let mut conn = self.connect(host, PixelConfig::default())?;
+        Ok(conn.query(id, self.timeout)?)
+    }
+
+    fn discover(&self, broadcast: &str) -> Result<()> {
+        let bind = match self.bind {
+            Some(bind) => bind,
+            None => {
+                let addr = broadcast.to_socket_addrs()?.next();
+                if addr.is_some_and(|a| a.is_ipv6()) {
+                    SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+                } else {
+                    SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+                }
+            }
+        };
+        let socket = UdpSocket::bind(bind)?;
+        let devices = discovery::discover(&socket, broadcast, self.timeout)?;
+
+        let value: Value = devices
+            .iter()
+            .map(|d| {
+                json!({
+                    "addr": d.addr.to_string(),
+                    "status": d.message.as_ref().map(message_json),
+                })
+            })
+            .collect();
+
+        self.output(&value, || {
+            if devices.is_empty() {
+                println!("no displays found");
+            }
+            for d in &devices {
+                let field = |f: Option<&String>| f.cloned().unwrap_or_else(|| "-".to_string());
+                let status = d.status();
+                println!(
+                    "{:<22} {:<12} {:<16} {}",
+                    d.addr,
+                    field(status.and_then(|s| s.man.as_ref())),
+                    field(status.and_then(|s| s.model.as_ref())),
+                    field(status.and_then(|s| s.ver.as_ref())),
+                );
+            }
+        });
+        Ok(())
+    }
+
+    fn status(&self, host: &str) -> Result<()> {
+        let reply = reply_json(&self.query(host, ID::Status)?);
+        self.output(&reply, || {
+            print_human(reply.get("status").unwrap_or(&reply), 0)
+        });
+        Ok(())
+    }
+
+    fn config_get(&self, host: &str) -> Result<()> {
+        let reply = reply_json(&self.query(host, ID::Config)?);
+        self.output(&reply, || {
+            print_human(reply.get("config").unwrap_or(&reply), 0)
+        });
+        Ok(())
+    }
+
+    fn config_set(&self, host: &str, edit: ConfigEdit, file: Option<PathBuf>) -> Result<()> {
+        let mut conn = self.connect(host, PixelConfig::default())?;
+
+        let file: Option<ConfigRoot> = match file {
+            Some(path) => Some(serde_json::from_slice(&std::fs::read(path)?)?),
+            None => None,
+        };
+
+        let diff = conn.edit_config(self.timeout, |config| {
+            if let Some(file) = file {
+                *config = file.config;
+                return;
+            }
+            if let Some(ip) = edit.ip {
+                config.set_ip(ip);
+            }
+            if let Some(nm) = edit.nm {
+                config.set_netmask(nm);
+            }
+            if let Some(gw) = edit.gw {
+                config.set_gateway(gw);
+            }
+            if let Some(port) = edit.port {
+                let port = config.port_mut(port);
+                port.ss = edit.start.unwrap_or(port.ss);
+                port.l = edit.length.unwrap_or(port.l);
+                port.ts = edit.string_type.unwrap_or(port.ts);
+            }
+        })?;
+
+        let value: Value = diff
+            .changes
+            .iter()
+            .map(|c| json!({ "field": c.field, "old": c.old, "new": c.new }))
+            .collect();
+        self.output(&value, || {
+            if diff.is_empty() {
+                println!("config of {} already up to date", with_port(host));
+                return;
+            }
+            println!("changed config of {}", with_port(host));
+            for change in &diff.changes {
+                println!("  {change}");
+            }
+        });
+        Ok(())
+    }
+
+    fn send(
+        &self,
+        host: &str,
+        colors: Vec<Color>,
+        file: Option<PathBuf>,
+        offset: u32,
+    ) -> Result<()> {
+        let (data, channels) = match file {
+            Some(path) => (std::fs::read(path)?, 3),
+            None => {
+                let channels = colors.iter().map(|c| c.0.len()).max().unwrap_or(3);
+                // Pad RGB colors when mixed with RGBW ones
+                let data = colors
+                    .iter()
+                    .flat_map(|c| {
+                        let mut c = c.0.clone();
+                        c.resize(channels, 0);
+                        c
+                    })
+                    .collect();
+                (data, channels)
+            }
+        };
+
+        let mut conn = self.connect(host, pixel_config(channels))?;
+        let sent = conn.write_offset(&data, offset)?;
+        self.report_sent(sent, data.len());
+        Ok(())
+    }
+
+    fn fill(&self, host: &str, color: Color, pixels: usize, start: usize) -> Result<()> {
+        let channels = color.0.len();
+        let data = color.0.repeat(pixels);
+
+        let mut conn = self.connect(host, pixel_config(channels))?;
+        let sent = conn.write_offset(&data, (start * channels) as u32)?;
+        self.report_sent(sent, data.len());
+        Ok(())
+    }
+
+    fn report_sent(&self, sent: usize, pixel_bytes: usize) {
+        let value = json!({ "sent": sent, "pixel_bytes": pixel_bytes });
+        self.output(&value, || {
+            println!("sent {pixel_bytes} bytes of pixel data ({sent} bytes on the wire)")
+        });
+    }
+
+    fn test_pattern(
+        &self,
+        host: &str,
+        pixels: usize,
+        pattern: Pattern,
+        fps: f32,
+        duration: Option<f32>,
+    ) -> Result<()> {
+        let mut conn = self.connect(host, PixelConfig::default())?;
+        let interval = Duration::from_secs_f32(1.0 / fps.max(0.1));
+        let start = Instant::now();
+        let mut frame = vec![0u8; pixels * 3];
+        let mut frames = 0usize;
+
+        while duration.is_none_or(|d| start.elapsed().as_secs_f32() < d) {
+            let t = start.elapsed().as_secs_f32();
+
+            match pattern {
+                Pattern::Rgb => {
+                    let mut color = [0u8; 3];
+                    color[t as usize % 3] = 255;
+                    for px in frame.chunks_mut(3) {
+                        px.copy_from_slice(&color);
+                    }
+                }
+                Pattern::Chase => {
+                    frame.fill(0);
+                    if pixels > 0 {
+                        let i = frames % pixels;
+                        frame[i * 3..i * 3 + 3].fill(255);
+                    }
+                }
+                Pattern::Rainbow => {
+                    for (i, px) in frame.chunks_mut(3).enumerate() {
+                        let hue = (i as f32 / pixels.max(1) as f32 + t * 0.25).fract();
+                        px.copy_from_slice(&hue_to_rgb(hue));
+                    }
+                }
+                Pattern::White => frame.fill(255),
+            }
+
+            conn.write(&frame)?;
+            frames += 1;
+            std::thread::sleep(interval);
+        }
+
+        let value = json!({ "frames": frames });
+        self.output(&value, || println!("sent {frames} frames"));
+        Ok(())
+    }
+
+    fn sniff(&self, listen: &str, count: Option<usize>, verbose: bool) -> Result<()> {
+        let socket = UdpSocket::bind(listen)?;
+        let mut buf = [0u8; 1500];
+        let mut seen = 0;
+
+        if self.format == Format::Human {
+            eprintln!("listening on {}", socket.local_addr()?);
+        }
+
+        while count.is_none_or(|c| seen < c) {
+            let (size, from) = socket.recv_from(&mut buf)?;
+            let packet = Packet::from_bytes(&buf[..size]);
+            seen += 1;
+
+            let h = &packet.header;
+            let value = json!({
+                "from": from.to_string(),
+                "sequence": h.sequence_number,
+                "id": u8::from(h.id),
+                "pixel_config": h.pixel_config.to_string(),
+                "version": h.packet_type.version,
+                "storage": h.packet_type.storage,
+                "offset": h.offset,
+                "length": h.length,
+                "push": h.packet_type.push,
+                "query": h.packet_type.query,
+                "reply": h.packet_type.reply,
+                "timecode": h.time_code.0,
+                "message": packet.parsed.as_ref().map(message_json),
+            });
+
+            self.output(&value, || {
+                if verbose {
+                    println!("--- {from} ({size} bytes)\n{packet:#}\n");
+                } else {
+                    println!("{from} {packet}");
+                }
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn hue_to_rgb(hue: f32) -> [u8; 3] {
+    let h = hue * 6.0;
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+    let (r, g, b) = match h as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    [(r * 255.0) as u8, (g * 255.0) as u8, (b * 255.0) as u8]
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let app = App {
+        format: cli.format,
+        bind: cli.bind,
+        timeout: Duration::from_millis(cli.timeout),
+    };
+
+    let result = match cli.command {
+        Command::Discover { broadcast } => app.discover(&broadcast),
+        Command::Status { host } => app.status(&host),
+        Command::Config { action } => match action {
+            ConfigAction::Get { host } => app.config_get(&host),
+            ConfigAction::Set {
+                host,
+                ip,
+                nm,
+                gw,
+                port,
+                start,
+                length,
+                string_type,
+                file,
+            } => {
+                let edit = ConfigEdit {
+                    ip,
+                    nm,
+                    gw,
+                    port,
+                    start,
+                    length,
+                    string_type,
+                };
+                app.config_set(&host, edit, file)
+            }
+        },
+        Command::Send {
+            host,
+            color,
+            file,
+            offset,
+        } => app.send(&host, color, file, offset),
+        Command::Fill {
+            host,
+            color,
+            pixels,
+            start,
+        } => app.fill(&host, color, pixels, start),
+        Command::TestPattern {
+            host,
+            pixels,
+            pattern,
+            fps,
+            duration,
+        } => app.test_pattern(&host, pixels, pattern, fps, duration),
+        Command::Sniff {
+            listen,
+            count,
+            verbose,
+        } => app.sniff(&listen, count, verbose),
+    };
+
+    if let Err(e) = result {
+        match app.format {
+            Format::Json => println!("{}", json!({ "error": e.to_string() })),
+            Format::Human => eprintln!("error: {e}"),
+        }
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_color_from_str() {
+        assert_eq!("red".parse(), Ok(Color(vec![255, 0, 0])));
+        assert_eq!("OFF".parse(), Ok(Color(vec![0, 0, 0])));
+        assert_eq!("#0080ff".parse(), Ok(Color(vec![0, 128, 255])));
+        assert_eq!("#0080FF20".parse(), Ok(Color(vec![0, 128, 255, 32])));
+        assert_eq!("1, 2,3".parse(), Ok(Color(vec![1, 2, 3])));
+        assert_eq!("1,2,3,4".parse(), Ok(Color(vec![1, 2, 3, 4])));
+    }
+
+    #[test]
+    fn test_color_from_str_malformed() {
+        for s in [
+            "",
+            "purple",
+            "#fff",
+            "#0080ff2",
+            "#00g0ff",
+            "#aéaaa",
+            "1,2",
+            "1,2,3,4,5",
+            "1,2,256",
+            "1,,3",
+            "-1,2,3",
+        ] {
+            assert!(s.parse::<Color>().is_err(), "{s:?} parsed");
+        }
+    }
+
+    #[test]
+    fn test_with_port() {
+        assert_eq!(with_port("10.0.0.5"), "10.0.0.5:4048");
+        assert_eq!(with_port("10.0.0.5:4049"), "10.0.0.5:4049");
+        assert_eq!(with_port("display.local"), "display.local:4048");
+        assert_eq!(with_port("display.local:80"), "display.local:80");
+        assert_eq!(with_port("::1"), "[::1]:4048");
+        assert_eq!(with_port("fe80::1"), "[fe80::1]:4048");
+        assert_eq!(with_port("[::1]:4049"), "[::1]:4049");
+    }
+}
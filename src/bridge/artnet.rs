@@ -0,0 +1,155 @@
+//! Art-Net ArtDmx packets.
+//!
+//! Only the ArtDmx operation is handled, which carries up to 512 channels of DMX
+//! data for one 15-bit port address (universe).
+
+/// UDP port Art-Net nodes listen on.
+pub const PORT: u16 = 6454;
+
+const ID: &[u8; 8] = b"Art-Net\0";
+const OP_DMX: u16 = 0x5000;
+const PROTOCOL_VERSION: u16 = 14;
+const HEADER_LEN: usize = 18;
+
+/// An ArtDmx packet.
+///
+/// # Examples
+///
+/// ```
+/// use ddp_rs::bridge::artnet::ArtDmx;
+///
+/// let packet = ArtDmx {
+///     sequence: 1,
+///     physical: 0,
+///     universe: 3,
+///     data: vec![255, 0, 0, 0, 255, 0],
+/// };
+///
+/// let bytes = packet.to_bytes();
+/// assert_eq!(ArtDmx::from_bytes(&bytes), Some(packet));
+/// ```
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct ArtDmx {
+    /// Sequence number (1-255), or 0 if sequencing is disabled
+    pub sequence: u8,
+
+    /// Physical input port the data originated from (informational)
+    pub physical: u8,
+
+    /// 15-bit port address: net, sub-net and universe
+    pub universe: u16,
+
+    /// DMX channel data, up to 512 bytes (no start code)
+    pub data: Vec<u8>,
+}
+
+impl ArtDmx {
+    /// Parses an ArtDmx packet, returning `None` for anything else.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN || &bytes[0..8] != ID {
+            return None;
+        }
+
+        // OpCode is the only little-endian field in the packet
+        if u16::from_le_bytes([bytes[8], bytes[9]]) != OP_DMX {
+            return None;
+        }
+
+        let sequence = bytes[12];
+        let physical = bytes[13];
+        let universe = u16::from_le_bytes([bytes[14], bytes[15] & 0x7f]);
+        let length = u16::from_be_bytes([bytes[16], bytes[17]]) as usize;
+
+        let end = (HEADER_LEN + length.min(512)).min(bytes.len());
+        Some(ArtDmx {
+            sequence,
+            physical,
+            universe,
+            data: bytes[HEADER_LEN..end].to_vec(),
+        })
+    }
+
+    /// Serializes the packet.
+    ///
+    /// Data longer than 512 bytes is truncated, and odd lengths are padded with a
+    /// zero as the specification requires an even length.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = &self.data[..];
+        if data.len() > 512 {
+            data = &data[..512];
+        }
+        let length = data.len() + data.len() % 2;
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + length);
+        bytes.extend_from_slice(ID);
+        bytes.extend_from_slice(&OP_DMX.to_le_bytes());
+        bytes.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+        bytes.push(self.sequence);
+        bytes.push(self.physical);
+        bytes.extend_from_slice(&(self.universe & 0x7fff).to_le_bytes());
+        bytes.extend_from_slice(&(length as u16).to_be_bytes());
+        bytes.extend_from_slice(data);
+        bytes.resize(HEADER_LEN + length, 0);
+
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_artdmx() {
+        let mut bytes = b"Art-Net\0".to_vec();
+        bytes.extend_from_slice(&[0x00, 0x50, 0x00, 0x0e, 0x05, 0x00, 0x12, 0x03, 0x00, 0x04]);
+        bytes.extend_from_slice(&[1, 2, 3, 4]);
+
+        let packet = ArtDmx::from_bytes(&bytes).unwrap();
+        assert_eq!(packet.sequence, 5);
+        assert_eq!(packet.universe, 0x0312);
+        assert_eq!(packet.data, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_reject_other_packets() {
+        assert_eq!(ArtDmx::from_bytes(b"Art-Net\0"), None);
+
+        // ArtPoll
+        let mut bytes = b"Art-Net\0".to_vec();
+        bytes.extend_from_slice(&[0x00, 0x20, 0x00, 0x0e, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(ArtDmx::from_bytes(&bytes), None);
+
+        let mut bytes = b"Not-Art\0".to_vec();
+        bytes.extend_from_slice(&[0x00, 0x50, 0x00, 0x0e, 0, 0, 0, 0, 0, 2, 1, 1]);
+        assert_eq!(ArtDmx::from_bytes(&bytes), None);
+    }
+
+    #[test]
+    fn test_odd_length_padded() {
+        let packet = ArtDmx {
+            sequence: 0,
+            physical: 0,
+            universe: 1,
+            data: vec![1, 2, 3],
+        };
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes.len(), 18 + 4);
+        assert_eq!(&bytes[16..18], &[0, 4]);
+    }
+
+    #[test]
+    fn test_truncated_data() {
+        let packet = ArtDmx {
+            sequence: 0,
+            physical: 0,
+            universe: 1,
+            data: vec![7; 10],
+        };
+        let bytes = packet.to_bytes();
+
+        // Length field claims more than was received
+        let parsed = ArtDmx::from_bytes(&bytes[..20]).unwrap();
+        assert_eq!(parsed.data, vec![7, 7]);
+    }
+}
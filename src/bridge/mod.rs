@@ -0,0 +1,474 @@
+//! Bridging Art-Net and sACN (E1.31) to DDP.
+//!
+//! A lot of lighting software only speaks the DMX-over-ethernet protocols. The
+//! [`Bridge`] listens for Art-Net ArtDmx and E1.31 data packets, copies the channels
+//! of each configured universe into a frame at a DDP byte offset, and forwards the
+//! merged frame through a [`DDPConnection`].
+//!
+//! The [`output`] module goes the other way, sending received DDP frames to
+//! Art-Net and sACN nodes, and [`opc`] bridges Open Pixel Control in both
+//! directions.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use ddp_rs::bridge::{Bridge, BridgeConfig};
+//! use ddp_rs::connection::DDPConnection;
+//! use ddp_rs::protocol::{PixelConfig, ID};
+//! use std::net::UdpSocket;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! // Two Art-Net universes of 170 RGB pixels, back to back on the display
+//! let config: BridgeConfig = serde_json::from_str(r#"{
+//!     "mappings": [
+//!         {"protocol": "artnet", "universe": 0, "channels": 510, "offset": 0},
+//!         {"protocol": "artnet", "universe": 1, "channels": 510, "offset": 510}
+//!     ]
+//! }"#)?;
+//!
+//! let mut conn = DDPConnection::try_new(
+//!     "192.168.1.40:4048",
+//!     PixelConfig::default(),
+//!     ID::Default,
+//!     UdpSocket::bind("0.0.0.0:4048")?
+//! )?;
+//!
+//! let mut bridge = Bridge::new(config);
+//! bridge.listen_artnet("0.0.0.0:6454")?;
+//! bridge.run(&mut conn)?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod artnet;
+pub mod opc;
+pub mod output;
+pub mod sacn;
+
+use crate::connection::DDPConnection;
+use crate::error::DDPError;
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How long listener threads block before checking whether they should stop.
+const LISTEN_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// DMX-over-ethernet protocol a universe is received with.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    /// Art-Net (ArtDmx)
+    ArtNet,
+    /// sACN / ANSI E1.31
+    Sacn,
+}
+
+/// Maps the channels of one universe onto a range of the DDP frame.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+pub struct UniverseMapping {
+    /// Protocol the universe arrives on
+    pub protocol: Protocol,
+
+    /// Universe number (Art-Net port address or E1.31 universe)
+    pub universe: u16,
+
+    /// First DMX channel to copy, counted from 0
+    #[serde(default)]
+    pub channel: u16,
+
+    /// Number of channels to copy
+    #[serde(default = "default_channels")]
+    pub channels: u16,
+
+    /// Byte offset in the DDP frame the channels are copied to
+    pub offset: u32,
+}
+
+fn default_channels() -> u16 {
+    512
+}
+
+fn default_frame_timeout() -> u64 {
+    100
+}
+
+/// Configuration for a [`Bridge`], usually loaded from JSON.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+pub struct BridgeConfig {
+    /// Universe to frame mappings
+    pub mappings: Vec<UniverseMapping>,
+
+    /// Forward a partially updated frame if not every universe has been received
+    /// within this many milliseconds
+    #[serde(default = "default_frame_timeout")]
+    pub frame_timeout_ms: u64,
+}
+
+impl Default for BridgeConfig {
+    fn default() -> Self {
+        BridgeConfig {
+            mappings: Vec::new(),
+            frame_timeout_ms: default_frame_timeout(),
+        }
+    }
+}
+
+/// DMX data received by one of the listeners.
+#[derive(Debug)]
+struct Universe {
+    protocol: Protocol,
+    universe: u16,
+    data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct Listener {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+/// Merges Art-Net and sACN universes into a DDP frame.
+///
+/// Data is received on background threads started with
+/// [`listen_artnet`](Self::listen_artnet) and [`listen_sacn`](Self::listen_sacn),
+/// and merged and forwarded on the thread calling [`poll`](Self::poll) or
+/// [`run`](Self::run). A frame is forwarded as soon as every mapped universe has
+/// been refreshed, or after `frame_timeout_ms` if some universes are missing.
+#[derive(Debug)]
+pub struct Bridge {
+    config: BridgeConfig,
+    frame: Vec<u8>,
+    fresh: Vec<bool>,
+    last_forward: Instant,
+
+    sender: Sender<Universe>,
+    receiver: Receiver<Universe>,
+    listeners: Vec<Listener>,
+}
+
+impl Bridge {
+    /// Creates a bridge. Nothing is received until a listener is started.
+    pub fn new(config: BridgeConfig) -> Self {
+        let len = config
+            .mappings
+            .iter()
+            .map(|m| m.offset as usize + m.channels as usize)
+            .max()
+            .unwrap_or(0);
+        let fresh = vec![false; config.mappings.len()];
+        let (sender, receiver) = unbounded();
+
+        Bridge {
+            config,
+            frame: vec![0u8; len],
+            fresh,
+            last_forward: Instant::now(),
+            sender,
+            receiver,
+            listeners: Vec::new(),
+        }
+    }
+
+    /// The merged frame as it will be forwarded.
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /// Local addresses of all running listeners.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners.iter().map(|l| l.addr).collect()
+    }
+
+    /// Starts receiving Art-Net on `addr` (usually `0.0.0.0:6454`).
+    ///
+    /// Returns the bound address, which is useful when binding to port 0.
+    pub fn listen_artnet<A: ToSocketAddrs>(&mut self, addr: A) -> Result<SocketAddr, DDPError> {
+        let socket = UdpSocket::bind(addr)?;
+        self.spawn_listener(socket, Protocol::ArtNet)
+    }
+
+    /// Starts receiving sACN on `addr` (usually `0.0.0.0:5568`).
+    ///
+    /// When bound to the unspecified IPv4 address, the multicast group of every
+    /// mapped sACN universe is joined. Returns the bound address.
+    pub fn listen_sacn<A: ToSocketAddrs>(&mut self, addr: A) -> Result<SocketAddr, DDPError> {
+        let socket = UdpSocket::bind(addr)?;
+
+        if socket.local_addr()?.ip() == IpAddr::V4(Ipv4Addr::UNSPECIFIED) {
+            for m in self
+                .config
+                .mappings
+                .iter()
+                .filter(|m| m.protocol == Protocol::Sacn)
+            {
+                let group = sacn::multicast_addr(m.universe);
+                if let Err(e) = socket.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED) {
+                    log::warn!("could not join sACN multicast group {}: {}", group, e);
+                }
+            }
+        }
+
+        self.spawn_listener(socket, Protocol::Sacn)
+    }
+
+    fn spawn_listener(
+        &mut self,
+        socket: UdpSocket,
+        protocol: Protocol,
+    ) -> Result<SocketAddr, DDPError> {
+        let addr = socket.local_addr()?;
+        socket.set_read_timeout(Some(LISTEN_TIMEOUT))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let sender = self.sender.clone();
+
+        let handle = std::thread::spawn(move || {
+            let mut buf = [0u8; 1500];
+
+            while !thread_stop.load(Ordering::Relaxed) {
+                let size = match socket.recv_from(&mut buf) {
+                    Ok((size, _)) => size,
+                    Err(e)
+                        if e.kind() == std::io::ErrorKind::WouldBlock
+                            || e.kind() == std::io::ErrorKind::TimedOut =>
+                    {
+                        continue
+                    }
+                    Err(e) => {
+                        log::error!("bridge listener on {} stopped: {}", addr, e);
+                        break;
+                    }
+                };
+
+                let universe = match protocol {
+                    Protocol::ArtNet => {
+                        artnet::ArtDmx::from_bytes(&buf[..size]).map(|p| Universe {
+                            protocol,
+                            universe: p.universe,
+                            data: p.data,
+                        })
+                    }
+                    Protocol::Sacn => sacn::DataPacket::from_bytes(&buf[..size])
+                        .filter(|p| {
+                            p.options & (sacn::OPTION_PREVIEW | sacn::OPTION_STREAM_TERMINATED) == 0
+                        })
+                        .map(|p| Universe {
+                            protocol,
+                            universe: p.universe,
+                            data: p.data,
+                        }),
+                };
+
+                if let Some(universe) = universe {
+                    if sender.send(universe).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        self.listeners.push(Listener {
+            addr,
+            stop,
+            handle: Some(handle),
+        });
+
+        Ok(addr)
+    }
+
+    /// Copies received DMX data into the frame for every mapping it matches.
+    ///
+    /// Returns `true` once every mapped universe has been refreshed since the last
+    /// forwarded frame.
+    pub fn merge(&mut self, protocol: Protocol, universe: u16, data: &[u8]) -> bool {
+        for (i, m) in self.config.mappings.iter().enumerate() {
+            if m.protocol != protocol || m.universe != universe {
+                continue;
+            }
+
+            let start = (m.channel as usize).min(data.len());
+            let end = (m.channel as usize + m.channels as usize).min(data.len());
+            let offset = m.offset as usize;
+
+            self.frame[offset..offset + (end - start)].copy_from_slice(&data[start..end]);
+            self.fresh[i] = true;
+        }
+
+        self.fresh.iter().all(|f| *f)
+    }
+
+    /// Sends the current frame and starts collecting the next one.
+    pub fn forward(&mut self, conn: &mut DDPConnection) -> Result<usize, DDPError> {
+        self.fresh.fill(false);
+        self.last_forward = Instant::now();
+        conn.write(&self.frame)
+    }
+
+    /// Merges received data for up to `timeout`, forwarding a frame when it is complete.
+    ///
+    /// Returns `true` if a frame was forwarded.
+    pub fn poll(&mut self, conn: &mut DDPConnection, timeout: Duration) -> Result<bool, DDPError> {
+        let deadline = Instant::now() + timeout;
+        let frame_timeout = Duration::from_millis(self.config.frame_timeout_ms);
+
+        loop {
+            let wait = deadline.saturating_duration_since(Instant::now());
+
+            match self.receiver.recv_timeout(wait) {
+                Ok(u) => {
+                    if self.merge(u.protocol, u.universe, &u.data) {
+                        self.forward(conn)?;
+                        return Ok(true);
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => break,
+                // We hold a sender ourselves, so this can't happen
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        // Don't hold partial updates back forever if a source goes quiet
+        if self.fresh.iter().any(|f| *f) && self.last_forward.elapsed() >= frame_timeout {
+            self.forward(conn)?;
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    /// Forwards frames until an error occurs.
+    pub fn run(&mut self, conn: &mut DDPConnection) -> Result<(), DDPError> {
+        let timeout = Duration::from_millis(self.config.frame_timeout_ms.max(1));
+        loop {
+            self.poll(conn, timeout)?;
+        }
+    }
+}
+
+impl Drop for Bridge {
+    fn drop(&mut self) {
+        for l in &self.listeners {
+            l.stop.store(true, Ordering::Relaxed);
+        }
+        for l in &mut self.listeners {
+            if let Some(handle) = l.handle.take() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{PixelConfig, ID};
+
+    fn config() -> BridgeConfig {
+        serde_json::from_str(
+            r#"{
+                "mappings": [
+                    {"protocol": "artnet", "universe": 0, "channels": 6, "offset": 0},
+                    {"protocol": "sacn", "universe": 1, "channel": 3, "channels": 3, "offset": 6}
+                ],
+                "frame_timeout_ms": 20
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_config_defaults() {
+        let config: BridgeConfig = serde_json::from_str(
+            r#"{"mappings": [{"protocol": "sacn", "universe": 7, "offset": 30}]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.frame_timeout_ms, 100);
+        assert_eq!(config.mappings[0].channel, 0);
+        assert_eq!(config.mappings[0].channels, 512);
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut bridge = Bridge::new(config());
+        assert_eq!(bridge.frame().len(), 9);
+
+        assert!(!bridge.merge(Protocol::ArtNet, 0, &[1, 2, 3, 4, 5, 6, 7, 8]));
+        // Unmapped universe
+        assert!(!bridge.merge(Protocol::ArtNet, 1, &[9; 8]));
+        assert!(bridge.merge(Protocol::Sacn, 1, &[0, 0, 0, 10, 11, 12, 13]));
+
+        assert_eq!(bridge.frame(), &[1, 2, 3, 4, 5, 6, 10, 11, 12]);
+    }
+
+    #[test]
+    fn test_merge_short_universe() {
+        let mut bridge = Bridge::new(config());
+        bridge.merge(Protocol::ArtNet, 0, &[1, 2]);
+        assert_eq!(bridge.frame(), &[1, 2, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_bridge_forwards_from_udp() {
+        let display = UdpSocket::bind("127.0.0.1:0").unwrap();
+        display
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let mut conn = DDPConnection::try_new(
+            display.local_addr().unwrap(),
+            PixelConfig::default(),
+            ID::Default,
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+        )
+        .unwrap();
+
+        let mut bridge = Bridge::new(config());
+        let artnet_addr = bridge.listen_artnet("127.0.0.1:0").unwrap();
+        let sacn_addr = bridge.listen_sacn("127.0.0.1:0").unwrap();
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let artdmx = artnet::ArtDmx {
+            sequence: 1,
+            physical: 0,
+            universe: 0,
+            data: vec![1, 2, 3, 4, 5, 6],
+        };
+        sender.send_to(&artdmx.to_bytes(), artnet_addr).unwrap();
+        let e131 = sacn::DataPacket::new(1, vec![0, 0, 0, 7, 8, 9]);
+        sender.send_to(&e131.to_bytes(), sacn_addr).unwrap();
+
+        assert!(bridge.poll(&mut conn, Duration::from_millis(500)).unwrap());
+
+        let mut buf = [0u8; 1500];
+        let (size, _) = display.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[10..size], &[1, 2, 3, 4, 5, 6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn test_bridge_forwards_partial_after_timeout() {
+        let display = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut conn = DDPConnection::try_new(
+            display.local_addr().unwrap(),
+            PixelConfig::default(),
+            ID::Default,
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+        )
+        .unwrap();
+
+        let mut bridge = Bridge::new(config());
+        bridge.merge(Protocol::ArtNet, 0, &[1; 6]);
+
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(bridge.poll(&mut conn, Duration::from_millis(1)).unwrap());
+        // Nothing new since
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(!bridge.poll(&mut conn, Duration::from_millis(1)).unwrap());
+    }
+}
@@ -0,0 +1,564 @@
+//! Open Pixel Control (OPC) to DDP, and back.
+//!
+//! OPC is the TCP protocol spoken by Fadecandy and many generative art tools. Every
+//! message is a 4 byte header (channel, command and a big-endian length) followed
+//! by the payload; command 0 ("set pixel colors") carries 8-bit RGB data.
+//!
+//! [`OpcServer`] accepts OPC clients and streams their pixel data out through one
+//! or more [`DDPConnection`]s, with each OPC channel mapped to a destination and
+//! byte offset. [`OpcClient`] goes the other way, forwarding DDP frames to OPC-only
+//! hardware.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use ddp_rs::bridge::opc::{OpcConfig, OpcServer};
+//! use ddp_rs::connection::DDPConnection;
+//! use ddp_rs::protocol::{PixelConfig, ID};
+//! use std::net::UdpSocket;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! // OPC channel 1 drives the first controller, channel 2 the second half of it
+//! // and channel 3 another controller
+//! let config: OpcConfig = serde_json::from_str(r#"{
+//!     "mappings": [
+//!         {"channel": 1, "destination": 0, "offset": 0},
+//!         {"channel": 2, "destination": 0, "offset": 1536},
+//!         {"channel": 3, "destination": 1, "offset": 0}
+//!     ]
+//! }"#)?;
+//!
+//! let destinations = vec![
+//!     DDPConnection::try_new("192.168.1.40:4048", PixelConfig::default(), ID::Default, UdpSocket::bind("0.0.0.0:0")?)?,
+//!     DDPConnection::try_new("192.168.1.41:4048", PixelConfig::default(), ID::Default, UdpSocket::bind("0.0.0.0:0")?)?,
+//! ];
+//!
+//! let mut server = OpcServer::new(config, destinations);
+//! server.listen("0.0.0.0:7890")?;
+//! server.run()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::connection::DDPConnection;
+use crate::error::DDPError;
+use crate::receiver::DDPReceiver;
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use serde::{Deserialize, Serialize};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// TCP port OPC servers listen on.
+pub const PORT: u16 = 7890;
+
+/// "Set pixel colors" command.
+pub const SET_PIXEL_COLORS: u8 = 0;
+
+/// System exclusive command, used for vendor extensions.
+pub const SYSTEM_EXCLUSIVE: u8 = 255;
+
+/// Channel 0 addresses every channel.
+pub const BROADCAST_CHANNEL: u8 = 0;
+
+/// How long the accept thread sleeps between checks for new clients.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// An OPC message.
+///
+/// # Examples
+///
+/// ```
+/// use ddp_rs::bridge::opc::Message;
+///
+/// let message = Message::set_pixel_colors(1, vec![255, 0, 0]);
+/// let bytes = message.to_bytes();
+/// assert_eq!(bytes, vec![1, 0, 0, 3, 255, 0, 0]);
+///
+/// let parsed = Message::read_from(&mut &bytes[..]).unwrap();
+/// assert_eq!(parsed, Some(message));
+/// ```
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct Message {
+    /// Channel, 0 for all channels
+    pub channel: u8,
+
+    /// Command, see [`SET_PIXEL_COLORS`]
+    pub command: u8,
+
+    /// Payload, up to 65535 bytes
+    pub data: Vec<u8>,
+}
+
+impl Message {
+    /// Creates a "set pixel colors" message.
+    pub fn set_pixel_colors(channel: u8, data: Vec<u8>) -> Self {
+        Message {
+            channel,
+            command: SET_PIXEL_COLORS,
+            data,
+        }
+    }
+
+    /// Reads one message from a stream.
+    ///
+    /// Returns `Ok(None)` if the stream ended cleanly before a new message.
+    pub fn read_from<R: Read>(reader: &mut R) -> std::io::Result<Option<Self>> {
+        let mut header = [0u8; 4];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let mut data = vec![0u8; len];
+        reader.read_exact(&mut data)?;
+
+        Ok(Some(Message {
+            channel: header[0],
+            command: header[1],
+            data,
+        }))
+    }
+
+    /// Serializes the message. Payloads longer than 65535 bytes are truncated.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let data = &self.data[..self.data.len().min(u16::MAX as usize)];
+
+        let mut bytes = Vec::with_capacity(4 + data.len());
+        bytes.push(self.channel);
+        bytes.push(self.command);
+        bytes.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(data);
+
+        bytes
+    }
+}
+
+/// Maps an OPC channel onto a DDP destination.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+pub struct OpcMapping {
+    /// OPC channel (1-255)
+    pub channel: u8,
+
+    /// Index of the connection in the list passed to [`OpcServer::new`]
+    #[serde(default)]
+    pub destination: usize,
+
+    /// Byte offset in the destination's display buffer
+    #[serde(default)]
+    pub offset: u32,
+}
+
+/// Configuration for an [`OpcServer`], usually loaded from JSON.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Default)]
+pub struct OpcConfig {
+    /// Channel to destination mappings
+    pub mappings: Vec<OpcMapping>,
+}
+
+/// Accepts OPC clients over TCP and streams their pixel data out over DDP.
+///
+/// Clients are read on background threads started by [`listen`](Self::listen);
+/// messages are sent on the thread calling [`poll`](Self::poll) or
+/// [`run`](Self::run). Messages on the broadcast channel go to every mapping.
+#[derive(Debug)]
+pub struct OpcServer {
+    config: OpcConfig,
+    destinations: Vec<DDPConnection>,
+
+    sender: Sender<Message>,
+    receiver: Receiver<Message>,
+    stop: Arc<AtomicBool>,
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+    listeners: Vec<JoinHandle<()>>,
+}
+
+impl OpcServer {
+    /// Creates a server sending to `destinations`. Nothing is received until
+    /// [`listen`](Self::listen) is called.
+    pub fn new(config: OpcConfig, destinations: Vec<DDPConnection>) -> Self {
+        let (sender, receiver) = unbounded();
+
+        OpcServer {
+            config,
+            destinations,
+            sender,
+            receiver,
+            stop: Arc::new(AtomicBool::new(false)),
+            clients: Arc::new(Mutex::new(Vec::new())),
+            listeners: Vec::new(),
+        }
+    }
+
+    /// The connections messages are sent to.
+    pub fn destinations(&mut self) -> &mut [DDPConnection] {
+        &mut self.destinations
+    }
+
+    /// Starts accepting clients on `addr` (usually `0.0.0.0:7890`).
+    ///
+    /// Returns the bound address, which is useful when binding to port 0.
+    pub fn listen<A: ToSocketAddrs>(&mut self, addr: A) -> Result<SocketAddr, DDPError> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        listener.set_nonblocking(true)?;
+
+        let stop = self.stop.clone();
+        let clients = self.clients.clone();
+        let sender = self.sender.clone();
+
+        let handle = std::thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                let stream = match listener.accept() {
+                    Ok((stream, _)) => stream,
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        std::thread::sleep(ACCEPT_INTERVAL);
+                        continue;
+                    }
+                    Err(e) => {
+                        log::error!("OPC listener on {} stopped: {}", addr, e);
+                        break;
+                    }
+                };
+
+                if let Err(e) = spawn_client(stream, &clients, sender.clone()) {
+                    log::warn!("could not accept OPC client: {}", e);
+                }
+            }
+        });
+        self.listeners.push(handle);
+
+        Ok(addr)
+    }
+
+    /// Sends the pixel data of `message` to every destination mapped to its channel.
+    ///
+    /// Returns the number of bytes sent. Commands other than "set pixel colors" are
+    /// ignored.
+    pub fn handle(&mut self, message: &Message) -> Result<usize, DDPError> {
+        if message.command != SET_PIXEL_COLORS {
+            return Ok(0);
+        }
+
+        let mut sent = 0;
+        for m in &self.config.mappings {
+            if message.channel != BROADCAST_CHANNEL && message.channel != m.channel {
+                continue;
+            }
+
+            match self.destinations.get_mut(m.destination) {
+                Some(conn) => sent += conn.write_offset(&message.data, m.offset)?,
+                None => log::warn!(
+                    "OPC channel {} mapped to missing destination {}",
+                    m.channel,
+                    m.destination
+                ),
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// Handles received messages for up to `timeout`.
+    ///
+    /// Returns `true` if at least one message was handled.
+    pub fn poll(&mut self, timeout: Duration) -> Result<bool, DDPError> {
+        let message = match self.receiver.recv_timeout(timeout) {
+            Ok(m) => m,
+            Err(RecvTimeoutError::Timeout) => return Ok(false),
+            // We hold a sender ourselves, so this can't happen
+            Err(RecvTimeoutError::Disconnected) => return Ok(false),
+        };
+        self.handle(&message)?;
+
+        // Drain whatever else queued up meanwhile
+        while let Ok(message) = self.receiver.try_recv() {
+            self.handle(&message)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Handles messages until an error occurs.
+    pub fn run(&mut self) -> Result<(), DDPError> {
+        loop {
+            self.poll(Duration::from_secs(1))?;
+        }
+    }
+}
+
+fn spawn_client(
+    stream: TcpStream,
+    clients: &Mutex<Vec<TcpStream>>,
+    sender: Sender<Message>,
+) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+
+    // Kept so the server can shut the stream down and unblock the reader
+    if let Ok(mut clients) = clients.lock() {
+        clients.retain(|c| c.peer_addr().is_ok());
+        clients.push(stream.try_clone()?);
+    }
+
+    let peer = stream.peer_addr()?;
+    std::thread::spawn(move || {
+        let mut reader = std::io::BufReader::new(stream);
+        loop {
+            match Message::read_from(&mut reader) {
+                Ok(Some(message)) => {
+                    if sender.send(message).is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    log::debug!("OPC client {} disconnected: {}", peer, e);
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+impl Drop for OpcServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Ok(clients) = self.clients.lock() {
+            for c in clients.iter() {
+                let _ = c.shutdown(Shutdown::Both);
+            }
+        }
+        for handle in self.listeners.drain(..) {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// Sends pixel data to an OPC server.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ddp_rs::bridge::opc::OpcClient;
+/// use ddp_rs::receiver::DDPReceiver;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// // Forward everything sent to us over DDP to a Fadecandy on channel 0
+/// let mut client = OpcClient::connect("127.0.0.1:7890")?;
+/// let mut receiver = DDPReceiver::bind("0.0.0.0:4048")?;
+/// client.run(&mut receiver, 0)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct OpcClient {
+    stream: TcpStream,
+}
+
+impl OpcClient {
+    /// Connects to an OPC server.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, DDPError> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(OpcClient { stream })
+    }
+
+    /// Sends a message.
+    pub fn send(&mut self, message: &Message) -> Result<usize, DDPError> {
+        let bytes = message.to_bytes();
+        self.stream.write_all(&bytes)?;
+        Ok(bytes.len())
+    }
+
+    /// Sets the pixel colors of `channel`.
+    pub fn write(&mut self, channel: u8, data: &[u8]) -> Result<usize, DDPError> {
+        self.send(&Message::set_pixel_colors(channel, data.to_vec()))
+    }
+
+    /// Forwards every frame received over DDP to `channel` until an error occurs.
+    pub fn run(&mut self, receiver: &mut DDPReceiver, channel: u8) -> Result<(), DDPError> {
+        loop {
+            let frame = receiver.recv_frame()?;
+            let bytes = Message::set_pixel_colors(channel, frame.to_vec()).to_bytes();
+            self.stream.write_all(&bytes)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{PixelConfig, ID};
+    use std::net::UdpSocket;
+
+    fn destination() -> (DDPConnection, UdpSocket) {
+        let display = UdpSocket::bind("127.0.0.1:0").unwrap();
+        display
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let conn = DDPConnection::try_new(
+            display.local_addr().unwrap(),
+            PixelConfig::default(),
+            ID::Default,
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+        )
+        .unwrap();
+        (conn, display)
+    }
+
+    fn recv(display: &UdpSocket) -> (u32, Vec<u8>) {
+        let mut buf = [0u8; 1500];
+        let (size, _) = display.recv_from(&mut buf).unwrap();
+        let offset = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        (offset, buf[10..size].to_vec())
+    }
+
+    #[test]
+    fn test_message_stream() {
+        let mut bytes = Message::set_pixel_colors(1, vec![1, 2, 3]).to_bytes();
+        bytes.extend(Message::set_pixel_colors(2, vec![]).to_bytes());
+        let mut reader = &bytes[..];
+
+        assert_eq!(
+            Message::read_from(&mut reader).unwrap().unwrap().data,
+            vec![1, 2, 3]
+        );
+        assert_eq!(Message::read_from(&mut reader).unwrap().unwrap().channel, 2);
+        assert_eq!(Message::read_from(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_truncated_message() {
+        let bytes = [1, 0, 0, 6, 1, 2, 3];
+        assert!(Message::read_from(&mut &bytes[..]).is_err());
+    }
+
+    #[test]
+    fn test_handle_mappings() {
+        let (a, display_a) = destination();
+        let (b, display_b) = destination();
+        let config = OpcConfig {
+            mappings: vec![
+                OpcMapping {
+                    channel: 1,
+                    destination: 0,
+                    offset: 0,
+                },
+                OpcMapping {
+                    channel: 2,
+                    destination: 0,
+                    offset: 30,
+                },
+                OpcMapping {
+                    channel: 3,
+                    destination: 1,
+                    offset: 6,
+                },
+            ],
+        };
+        let mut server = OpcServer::new(config, vec![a, b]);
+
+        server
+            .handle(&Message::set_pixel_colors(2, vec![1, 2, 3]))
+            .unwrap();
+        assert_eq!(recv(&display_a), (30, vec![1, 2, 3]));
+
+        server
+            .handle(&Message::set_pixel_colors(3, vec![4, 5, 6]))
+            .unwrap();
+        assert_eq!(recv(&display_b), (6, vec![4, 5, 6]));
+
+        // Unmapped channel and other commands do nothing
+        assert_eq!(
+            server
+                .handle(&Message::set_pixel_colors(9, vec![1]))
+                .unwrap(),
+            0
+        );
+        let sysex = Message {
+            channel: 1,
+            command: SYSTEM_EXCLUSIVE,
+            data: vec![0, 1],
+        };
+        assert_eq!(server.handle(&sysex).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_broadcast_channel() {
+        let (a, display_a) = destination();
+        let config = OpcConfig {
+            mappings: vec![
+                OpcMapping {
+                    channel: 1,
+                    destination: 0,
+                    offset: 0,
+                },
+                OpcMapping {
+                    channel: 2,
+                    destination: 0,
+                    offset: 3,
+                },
+            ],
+        };
+        let mut server = OpcServer::new(config, vec![a]);
+        server
+            .handle(&Message::set_pixel_colors(0, vec![7, 7, 7]))
+            .unwrap();
+
+        let mut offsets = vec![recv(&display_a).0, recv(&display_a).0];
+        offsets.sort();
+        assert_eq!(offsets, vec![0, 3]);
+    }
+
+    #[test]
+    fn test_server_over_tcp() {
+        let (a, display_a) = destination();
+        let config: OpcConfig =
+            serde_json::from_str(r#"{"mappings": [{"channel": 1, "offset": 3}]}"#).unwrap();
+        let mut server = OpcServer::new(config, vec![a]);
+        let addr = server.listen("127.0.0.1:0").unwrap();
+
+        let mut client = OpcClient::connect(addr).unwrap();
+        client.write(1, &[255, 0, 0]).unwrap();
+
+        assert!(server.poll(Duration::from_millis(500)).unwrap());
+        assert_eq!(recv(&display_a), (3, vec![255, 0, 0]));
+    }
+
+    #[test]
+    fn test_client_forwards_ddp() {
+        let opc = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = OpcClient::connect(opc.local_addr().unwrap()).unwrap();
+        let (mut opc_stream, _) = opc.accept().unwrap();
+
+        let receiver = DDPReceiver::bind("127.0.0.1:0").unwrap();
+        let mut conn = DDPConnection::try_new(
+            receiver.local_addr().unwrap(),
+            PixelConfig::default(),
+            ID::Default,
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+        )
+        .unwrap();
+        conn.write(&[1, 2, 3, 4, 5, 6]).unwrap();
+
+        // run() never returns, so drive it from a thread
+        std::thread::spawn(move || {
+            let mut receiver = receiver;
+            let _ = client.run(&mut receiver, 4);
+        });
+
+        let message = Message::read_from(&mut opc_stream).unwrap().unwrap();
+        assert_eq!(
+            message,
+            Message::set_pixel_colors(4, vec![1, 2, 3, 4, 5, 6])
+        );
+    }
+}
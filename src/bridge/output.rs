@@ -0,0 +1,483 @@
+//! Re-emitting DDP frames as Art-Net or sACN (E1.31) universes.
+//!
+//! This is the reverse of the [`Bridge`](super::Bridge): an [`Output`] takes frames,
+//! usually assembled by a [`DDPReceiver`], splits them into DMX universes and sends
+//! them to legacy nodes.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use ddp_rs::bridge::output::{Output, OutputConfig};
+//! use ddp_rs::receiver::DDPReceiver;
+//! use std::net::UdpSocket;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! // 170 RGB pixels per universe, starting at universe 1, sent to one node
+//! let config: OutputConfig = serde_json::from_str(r#"{
+//!     "protocol": "artnet",
+//!     "destination": "192.168.1.50:6454",
+//!     "universe": 1,
+//!     "channels_per_universe": 510
+//! }"#)?;
+//!
+//! let mut output = Output::new(config, UdpSocket::bind("0.0.0.0:0")?)?;
+//! let mut receiver = DDPReceiver::bind("0.0.0.0:4048")?;
+//! output.run(&mut receiver)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use super::{artnet, sacn, Protocol};
+use crate::error::DDPError;
+use crate::receiver::DDPReceiver;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs, UdpSocket};
+
+/// Maps a range of the DDP frame onto consecutive DMX channels.
+///
+/// Ranges longer than the space left in `universe` continue in the following
+/// universes.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+pub struct OutputMapping {
+    /// Byte offset in the DDP frame
+    pub offset: u32,
+
+    /// Number of bytes to copy
+    pub length: u32,
+
+    /// Universe the first byte is sent on
+    pub universe: u16,
+
+    /// DMX channel the first byte is sent on, counted from 0
+    #[serde(default)]
+    pub channel: u16,
+}
+
+fn default_channels_per_universe() -> u16 {
+    512
+}
+
+fn default_source_name() -> String {
+    "ddp-rs".to_string()
+}
+
+/// Configuration for an [`Output`], usually loaded from JSON.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+pub struct OutputConfig {
+    /// Protocol universes are sent with
+    pub protocol: Protocol,
+
+    /// Node to send to. Without one, Art-Net is broadcast and sACN is sent to the
+    /// multicast group of each universe.
+    #[serde(default)]
+    pub destination: Option<String>,
+
+    /// Universe the frame starts on when no mappings are given. Defaults to 1 for
+    /// sACN, whose universes start at 1, and 0 for Art-Net.
+    #[serde(default)]
+    pub universe: Option<u16>,
+
+    /// Channels used in each universe, up to 512. 510 keeps RGB pixels from being
+    /// split across universes.
+    #[serde(default = "default_channels_per_universe")]
+    pub channels_per_universe: u16,
+
+    /// Frame ranges to send. When empty, the whole frame is sent starting at
+    /// `universe`.
+    #[serde(default)]
+    pub mappings: Vec<OutputMapping>,
+
+    /// Source name for sACN packets
+    #[serde(default = "default_source_name")]
+    pub source_name: String,
+
+    /// sACN priority, 0 to 200
+    #[serde(default = "default_priority")]
+    pub priority: u8,
+}
+
+fn default_priority() -> u8 {
+    100
+}
+
+impl OutputConfig {
+    /// Creates a config sending whole frames from `universe` on, with defaults for
+    /// everything else.
+    pub fn new(protocol: Protocol, universe: u16) -> Self {
+        OutputConfig {
+            protocol,
+            destination: None,
+            universe: Some(universe),
+            channels_per_universe: default_channels_per_universe(),
+            mappings: Vec::new(),
+            source_name: default_source_name(),
+            priority: default_priority(),
+        }
+    }
+
+    /// Universe the frame starts on when no mappings are given.
+    pub fn first_universe(&self) -> u16 {
+        self.universe.unwrap_or(match self.protocol {
+            Protocol::ArtNet => 0,
+            Protocol::Sacn => 1,
+        })
+    }
+}
+
+/// Range of universes `protocol` can address: 15-bit port addresses for Art-Net
+/// and 1 to 63999 for sACN.
+fn universe_range(protocol: Protocol) -> std::ops::RangeInclusive<u16> {
+    match protocol {
+        Protocol::ArtNet => 0..=0x7fff,
+        Protocol::Sacn => 1..=63999,
+    }
+}
+
+/// Sends frames as Art-Net or sACN universes.
+#[derive(Debug)]
+pub struct Output {
+    config: OutputConfig,
+    socket: UdpSocket,
+    destination: Option<SocketAddr>,
+    sequences: BTreeMap<u16, u8>,
+}
+
+impl Output {
+    /// Creates an output sending from `socket`.
+    ///
+    /// Fails with [`DDPError::InvalidBridge`] if a universe is out of range for the
+    /// protocol. Broadcast is enabled on the socket when Art-Net is sent without a
+    /// destination.
+    pub fn new(config: OutputConfig, socket: UdpSocket) -> Result<Self, DDPError> {
+        let range = universe_range(config.protocol);
+        let name = match config.protocol {
+            Protocol::ArtNet => "Art-Net",
+            Protocol::Sacn => "sACN",
+        };
+        let first = std::iter::once(config.first_universe()).filter(|_| config.mappings.is_empty());
+        for universe in first.chain(config.mappings.iter().map(|m| m.universe)) {
+            if !range.contains(&universe) {
+                return Err(DDPError::InvalidBridge(format!(
+                    "{} universe {} is outside {}-{}",
+                    name,
+                    universe,
+                    range.start(),
+                    range.end()
+                )));
+            }
+        }
+
+        let destination = match &config.destination {
+            Some(addr) => Some(
+                addr.to_socket_addrs()?
+                    .next()
+                    .ok_or(DDPError::NoValidSocketAddr)?,
+            ),
+            None => None,
+        };
+
+        if destination.is_none() && config.protocol == Protocol::ArtNet {
+            socket.set_broadcast(true)?;
+        }
+
+        Ok(Output {
+            config,
+            socket,
+            destination,
+            sequences: BTreeMap::new(),
+        })
+    }
+
+    /// Splits `frame` into universes according to the mappings.
+    ///
+    /// Each universe is as long as the highest channel written to it. Data past the
+    /// last universe of the protocol is dropped.
+    pub fn universes(&self, frame: &[u8]) -> BTreeMap<u16, Vec<u8>> {
+        let per_universe = self.config.channels_per_universe.clamp(1, 512) as usize;
+        let last = *universe_range(self.config.protocol).end() as usize;
+        let whole_frame = [OutputMapping {
+            offset: 0,
+            length: frame.len() as u32,
+            universe: self.config.first_universe(),
+            channel: 0,
+        }];
+        let mappings = if self.config.mappings.is_empty() {
+            &whole_frame[..]
+        } else {
+            &self.config.mappings[..]
+        };
+
+        let mut universes: BTreeMap<u16, Vec<u8>> = BTreeMap::new();
+        for m in mappings {
+            let start = (m.offset as usize).min(frame.len());
+            let end = (m.offset as usize + m.length as usize).min(frame.len());
+
+            // Absolute channel position, counted from the mapping's universe
+            for (pos, &byte) in (m.channel as usize..).zip(&frame[start..end]) {
+                let universe = m.universe as usize + pos / per_universe;
+                if universe > last {
+                    break;
+                }
+
+                let channel = pos % per_universe;
+                let data = universes.entry(universe as u16).or_default();
+                if data.len() <= channel {
+                    data.resize(channel + 1, 0);
+                }
+                data[channel] = byte;
+            }
+        }
+
+        universes
+    }
+
+    /// Sends `frame`, returning the number of universes sent.
+    pub fn send(&mut self, frame: &[u8]) -> Result<usize, DDPError> {
+        let universes = self.universes(frame);
+
+        for (universe, data) in &universes {
+            let sequence = self.sequences.entry(*universe).or_insert(0);
+
+            let (bytes, addr) = match self.config.protocol {
+                Protocol::ArtNet => {
+                    // Art-Net reserves 0 for "sequencing disabled"
+                    *sequence = sequence.wrapping_add(1).max(1);
+                    let packet = artnet::ArtDmx {
+                        sequence: *sequence,
+                        physical: 0,
+                        universe: *universe,
+                        data: data.clone(),
+                    };
+                    let addr = self.destination.unwrap_or(SocketAddr::V4(SocketAddrV4::new(
+                        Ipv4Addr::BROADCAST,
+                        artnet::PORT,
+                    )));
+                    (packet.to_bytes(), addr)
+                }
+                Protocol::Sacn => {
+                    *sequence = sequence.wrapping_add(1);
+                    let mut packet = sacn::DataPacket::new(*universe, data.clone());
+                    packet.source_name = self.config.source_name.clone();
+                    packet.priority = self.config.priority;
+                    packet.sequence = *sequence;
+                    let addr = self.destination.unwrap_or(SocketAddr::V4(SocketAddrV4::new(
+                        sacn::multicast_addr(*universe),
+                        sacn::PORT,
+                    )));
+                    (packet.to_bytes(), addr)
+                }
+            };
+
+            self.socket.send_to(&bytes, addr)?;
+        }
+
+        Ok(universes.len())
+    }
+
+    /// Sends every frame received until an error occurs.
+    pub fn run(&mut self, receiver: &mut DDPReceiver) -> Result<(), DDPError> {
+        loop {
+            let frame = receiver.recv_frame()?;
+            self.send(frame)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn output(config: OutputConfig) -> Output {
+        Output::new(config, UdpSocket::bind("127.0.0.1:0").unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_config_defaults() {
+        let config: OutputConfig = serde_json::from_str(r#"{"protocol": "sacn"}"#).unwrap();
+        assert_eq!(
+            config,
+            OutputConfig {
+                universe: None,
+                ..OutputConfig::new(Protocol::Sacn, 1)
+            }
+        );
+        assert_eq!(config.first_universe(), 1);
+
+        let config: OutputConfig = serde_json::from_str(r#"{"protocol": "artnet"}"#).unwrap();
+        assert_eq!(config.first_universe(), 0);
+    }
+
+    #[test]
+    fn test_universe_ranges() {
+        let socket = || UdpSocket::bind("127.0.0.1:0").unwrap();
+        for (protocol, universe) in [
+            (Protocol::Sacn, 0),
+            (Protocol::Sacn, 64000),
+            (Protocol::ArtNet, 0x8000),
+        ] {
+            assert!(matches!(
+                Output::new(OutputConfig::new(protocol, universe), socket()),
+                Err(DDPError::InvalidBridge(_))
+            ));
+        }
+
+        let mut config = OutputConfig::new(Protocol::Sacn, 1);
+        config.mappings = vec![OutputMapping {
+            offset: 0,
+            length: 3,
+            universe: 0,
+            channel: 0,
+        }];
+        assert!(matches!(
+            Output::new(config, socket()),
+            Err(DDPError::InvalidBridge(_))
+        ));
+
+        // Frames running past the last universe are cut off there
+        let artnet_output = output(OutputConfig::new(Protocol::ArtNet, 0x7fff));
+        let universes = artnet_output.universes(&[1u8; 1000]);
+        assert_eq!(universes.keys().copied().collect::<Vec<_>>(), vec![0x7fff]);
+        let sacn_output = output(OutputConfig::new(Protocol::Sacn, 63999));
+        assert_eq!(sacn_output.universes(&[1u8; 1000]).len(), 1);
+    }
+
+    #[test]
+    fn test_whole_frame_split() {
+        let mut config = OutputConfig::new(Protocol::ArtNet, 3);
+        config.channels_per_universe = 4;
+        let output = output(config);
+
+        let universes = output.universes(&[1, 2, 3, 4, 5, 6]);
+        assert_eq!(universes.len(), 2);
+        assert_eq!(universes[&3], vec![1, 2, 3, 4]);
+        assert_eq!(universes[&4], vec![5, 6]);
+    }
+
+    #[test]
+    fn test_full_universes() {
+        let output = output(OutputConfig::new(Protocol::Sacn, 1));
+        let universes = output.universes(&[7u8; 1030]);
+
+        assert_eq!(universes[&1].len(), 512);
+        assert_eq!(universes[&2].len(), 512);
+        assert_eq!(universes[&3].len(), 6);
+    }
+
+    #[test]
+    fn test_channel_mapping() {
+        let mut config = OutputConfig::new(Protocol::ArtNet, 0);
+        config.channels_per_universe = 6;
+        config.mappings = vec![
+            OutputMapping {
+                offset: 3,
+                length: 3,
+                universe: 0,
+                channel: 3,
+            },
+            OutputMapping {
+                offset: 0,
+                length: 3,
+                universe: 0,
+                channel: 5,
+            },
+        ];
+        let output = output(config);
+
+        let universes = output.universes(&[1, 2, 3, 4, 5, 6]);
+        assert_eq!(universes[&0], vec![0, 0, 0, 4, 5, 1]);
+        assert_eq!(universes[&1], vec![2, 3]);
+    }
+
+    #[test]
+    fn test_send_artnet() {
+        let node = UdpSocket::bind("127.0.0.1:0").unwrap();
+        node.set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+
+        let mut config = OutputConfig::new(Protocol::ArtNet, 5);
+        config.destination = Some(node.local_addr().unwrap().to_string());
+        let mut output = output(config);
+
+        assert_eq!(output.send(&[1, 2, 3, 4]).unwrap(), 1);
+        assert_eq!(output.send(&[1, 2, 3, 4]).unwrap(), 1);
+
+        let mut buf = [0u8; 1500];
+        for sequence in 1..=2 {
+            let (size, _) = node.recv_from(&mut buf).unwrap();
+            let packet = artnet::ArtDmx::from_bytes(&buf[..size]).unwrap();
+            assert_eq!(packet.universe, 5);
+            assert_eq!(packet.sequence, sequence);
+            assert_eq!(packet.data, vec![1, 2, 3, 4]);
+        }
+    }
+
+    #[test]
+    fn test_sequence_wraps() {
+        let node = UdpSocket::bind("127.0.0.1:0").unwrap();
+        node.set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let mut buf = [0u8; 1500];
+
+        // Art-Net skips 0, which means sequencing is disabled
+        let mut config = OutputConfig::new(Protocol::ArtNet, 5);
+        config.destination = Some(node.local_addr().unwrap().to_string());
+        let mut artnet_output = output(config);
+        artnet_output.sequences.insert(5, 255);
+        artnet_output.send(&[1, 2, 3]).unwrap();
+        let (size, _) = node.recv_from(&mut buf).unwrap();
+        let packet = artnet::ArtDmx::from_bytes(&buf[..size]).unwrap();
+        assert_eq!(packet.sequence, 1);
+
+        // sACN goes through 0
+        let mut config = OutputConfig::new(Protocol::Sacn, 5);
+        config.destination = Some(node.local_addr().unwrap().to_string());
+        let mut sacn_output = output(config);
+        sacn_output.sequences.insert(5, 255);
+        sacn_output.send(&[1, 2, 3]).unwrap();
+        let (size, _) = node.recv_from(&mut buf).unwrap();
+        let packet = sacn::DataPacket::from_bytes(&buf[..size]).unwrap();
+        assert_eq!(packet.sequence, 0);
+    }
+
+    #[test]
+    fn test_receiver_to_sacn() {
+        use crate::connection::DDPConnection;
+        use crate::protocol::{PixelConfig, ID};
+
+        let node = UdpSocket::bind("127.0.0.1:0").unwrap();
+        node.set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+
+        let mut config = OutputConfig::new(Protocol::Sacn, 1);
+        config.destination = Some(node.local_addr().unwrap().to_string());
+        let mut output = output(config);
+
+        let mut receiver = DDPReceiver::bind("127.0.0.1:0").unwrap();
+        let mut conn = DDPConnection::try_new(
+            receiver.local_addr().unwrap(),
+            PixelConfig::default(),
+            ID::Default,
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+        )
+        .unwrap();
+        conn.write(&[9u8; 600]).unwrap();
+
+        let frame = receiver
+            .recv_frame_timeout(Duration::from_millis(500))
+            .unwrap();
+        assert_eq!(output.send(frame).unwrap(), 2);
+
+        let mut buf = [0u8; 1500];
+        let mut received = Vec::new();
+        for _ in 0..2 {
+            let (size, _) = node.recv_from(&mut buf).unwrap();
+            let packet = sacn::DataPacket::from_bytes(&buf[..size]).unwrap();
+            assert_eq!(packet.source_name, "ddp-rs");
+            received.push((packet.universe, packet.data.len()));
+        }
+        received.sort();
+        assert_eq!(received, vec![(1, 512), (2, 88)]);
+    }
+}
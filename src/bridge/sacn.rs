@@ -0,0 +1,218 @@
+//! sACN (ANSI E1.31) data packets.
+//!
+//! Only E1.31 data packets are handled; universe discovery and synchronization
+//! packets are ignored.
+
+use std::net::Ipv4Addr;
+
+/// UDP port sACN receivers listen on.
+pub const PORT: u16 = 5568;
+
+const ACN_ID: &[u8; 12] = b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_DATA: u32 = 0x0000_0004;
+const VECTOR_FRAMING_DATA: u32 = 0x0000_0002;
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+const HEADER_LEN: usize = 126;
+
+/// Options bit marking data meant for visualizers only.
+pub const OPTION_PREVIEW: u8 = 0x80;
+
+/// Options bit marking the last packet of a terminating stream.
+pub const OPTION_STREAM_TERMINATED: u8 = 0x40;
+
+/// Multicast group a universe is transmitted on (239.255.hi.lo).
+pub fn multicast_addr(universe: u16) -> Ipv4Addr {
+    let [hi, lo] = universe.to_be_bytes();
+    Ipv4Addr::new(239, 255, hi, lo)
+}
+
+/// An E1.31 data packet.
+///
+/// # Examples
+///
+/// ```
+/// use ddp_rs::bridge::sacn::DataPacket;
+///
+/// let packet = DataPacket::new(1, vec![255, 0, 0]);
+/// let bytes = packet.to_bytes();
+///
+/// assert_eq!(DataPacket::from_bytes(&bytes), Some(packet));
+/// ```
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct DataPacket {
+    /// Component identifier (UUID) of the sender
+    pub cid: [u8; 16],
+
+    /// Human readable name of the sender, up to 63 bytes
+    pub source_name: String,
+
+    /// Priority from 0 to 200, default 100
+    pub priority: u8,
+
+    /// Universe used for synchronization, 0 if not synchronized
+    pub sync_address: u16,
+
+    /// Sequence number
+    pub sequence: u8,
+
+    /// Option flags, see [`OPTION_PREVIEW`] and [`OPTION_STREAM_TERMINATED`]
+    pub options: u8,
+
+    /// Universe number (1-63999)
+    pub universe: u16,
+
+    /// DMX channel data, up to 512 bytes (no start code)
+    pub data: Vec<u8>,
+}
+
+impl DataPacket {
+    /// Creates a packet with default priority and an empty source.
+    pub fn new(universe: u16, data: Vec<u8>) -> Self {
+        DataPacket {
+            cid: [0u8; 16],
+            source_name: String::new(),
+            priority: 100,
+            sync_address: 0,
+            sequence: 0,
+            options: 0,
+            universe,
+            data,
+        }
+    }
+
+    /// Parses an E1.31 data packet with a null start code, returning `None` for
+    /// anything else.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_LEN || &bytes[4..16] != ACN_ID {
+            return None;
+        }
+
+        let be32 =
+            |i: usize| u32::from_be_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        let be16 = |i: usize| u16::from_be_bytes([bytes[i], bytes[i + 1]]);
+
+        if be32(18) != VECTOR_ROOT_DATA
+            || be32(40) != VECTOR_FRAMING_DATA
+            || bytes[117] != VECTOR_DMP_SET_PROPERTY
+        {
+            return None;
+        }
+
+        // Only DMX512 null start code data is pixel data
+        if bytes[125] != 0 {
+            return None;
+        }
+
+        let mut cid = [0u8; 16];
+        cid.copy_from_slice(&bytes[22..38]);
+
+        let name = &bytes[44..108];
+        let name_len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+        let source_name = String::from_utf8_lossy(&name[..name_len]).into_owned();
+
+        // Property value count includes the start code
+        let count = (be16(123) as usize).saturating_sub(1).min(512);
+        let end = (HEADER_LEN + count).min(bytes.len());
+
+        Some(DataPacket {
+            cid,
+            source_name,
+            priority: bytes[108],
+            sync_address: be16(109),
+            sequence: bytes[111],
+            options: bytes[112],
+            universe: be16(113),
+            data: bytes[HEADER_LEN..end].to_vec(),
+        })
+    }
+
+    /// Serializes the packet. Data longer than 512 bytes is truncated.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let data = &self.data[..self.data.len().min(512)];
+        let len = HEADER_LEN + data.len();
+
+        // Flags (0x7) in the top nibble, PDU length in the lower 12 bits
+        let flags_len = |from: usize| (0x7000 | (len - from) as u16).to_be_bytes();
+
+        let mut bytes = Vec::with_capacity(len);
+
+        // Root layer
+        bytes.extend_from_slice(&0x0010u16.to_be_bytes());
+        bytes.extend_from_slice(&0x0000u16.to_be_bytes());
+        bytes.extend_from_slice(ACN_ID);
+        bytes.extend_from_slice(&flags_len(16));
+        bytes.extend_from_slice(&VECTOR_ROOT_DATA.to_be_bytes());
+        bytes.extend_from_slice(&self.cid);
+
+        // Framing layer
+        bytes.extend_from_slice(&flags_len(38));
+        bytes.extend_from_slice(&VECTOR_FRAMING_DATA.to_be_bytes());
+        let mut name = [0u8; 64];
+        let name_len = self.source_name.len().min(63);
+        name[..name_len].copy_from_slice(&self.source_name.as_bytes()[..name_len]);
+        bytes.extend_from_slice(&name);
+        bytes.push(self.priority);
+        bytes.extend_from_slice(&self.sync_address.to_be_bytes());
+        bytes.push(self.sequence);
+        bytes.push(self.options);
+        bytes.extend_from_slice(&self.universe.to_be_bytes());
+
+        // DMP layer
+        bytes.extend_from_slice(&flags_len(115));
+        bytes.push(VECTOR_DMP_SET_PROPERTY);
+        bytes.push(0xa1);
+        bytes.extend_from_slice(&0x0000u16.to_be_bytes());
+        bytes.extend_from_slice(&0x0001u16.to_be_bytes());
+        bytes.extend_from_slice(&(data.len() as u16 + 1).to_be_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(data);
+
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let packet = DataPacket {
+            cid: [7u8; 16],
+            source_name: "ddp-rs".to_string(),
+            priority: 150,
+            sync_address: 9,
+            sequence: 42,
+            options: 0,
+            universe: 513,
+            data: (0..=255).collect(),
+        };
+
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes.len(), 126 + 256);
+        assert_eq!(&bytes[16..18], &[0x71, 0x6e]);
+        assert_eq!(DataPacket::from_bytes(&bytes), Some(packet));
+    }
+
+    #[test]
+    fn test_rejects_non_data() {
+        let mut bytes = DataPacket::new(1, vec![1, 2, 3]).to_bytes();
+
+        // Alternate start code
+        bytes[125] = 0xdd;
+        assert_eq!(DataPacket::from_bytes(&bytes), None);
+
+        // Sync packet vector
+        let mut bytes = DataPacket::new(1, vec![1, 2, 3]).to_bytes();
+        bytes[43] = 0x01;
+        assert_eq!(DataPacket::from_bytes(&bytes), None);
+
+        assert_eq!(DataPacket::from_bytes(&[0u8; 20]), None);
+    }
+
+    #[test]
+    fn test_multicast_addr() {
+        assert_eq!(multicast_addr(1), Ipv4Addr::new(239, 255, 0, 1));
+        assert_eq!(multicast_addr(0x1234), Ipv4Addr::new(239, 255, 0x12, 0x34));
+    }
+}
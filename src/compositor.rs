@@ -0,0 +1,488 @@
+//! Layering several frames into one.
+//!
+//! A [`Compositor`] stacks [`Layer`]s bottom to top and flattens them into the frame
+//! that is actually sent, so a notification can be drawn over an ambient effect
+//! without either knowing about the other. Each layer has an opacity, an optional
+//! per-pixel mask and a [`BlendMode`], and can crossfade from one [`Source`] to
+//! another.
+//!
+//! Like [`effects`](crate::effects), compositing is driven by the time since start
+//! so transitions and effects stay in step.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use ddp_rs::compositor::{BlendMode, Compositor, Layer, Source};
+//! use ddp_rs::connection::DDPConnection;
+//! use ddp_rs::effects::{Generator, Pattern};
+//! use ddp_rs::protocol::{PixelConfig, ID};
+//! use std::net::UdpSocket;
+//! use std::time::{Duration, Instant};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut conn = DDPConnection::try_new(
+//!     "192.168.1.40:4048",
+//!     PixelConfig::default(),
+//!     ID::Default,
+//!     UdpSocket::bind("0.0.0.0:4048")?
+//! )?;
+//!
+//! let mut compositor = Compositor::new(100, conn.pixel_config);
+//! compositor.push(Layer::effect(Generator::new(Pattern::Plasma, 100)));
+//!
+//! // A red bar over the first ten pixels, lightening what's below
+//! let alert = compositor.push(
+//!     Layer::frame([255, 0, 0].repeat(10))
+//!         .blend(BlendMode::Screen)
+//!         .opacity(0.0),
+//! );
+//!
+//! let start = Instant::now();
+//! let mut faded = false;
+//! loop {
+//!     let t = start.elapsed();
+//!     if let Some(layer) = compositor.layer_mut(alert) {
+//!         layer.set_opacity(if t.as_secs() % 2 == 0 { 1.0 } else { 0.0 });
+//!     }
+//!     if t > Duration::from_secs(30) && !faded {
+//!         let fire = Source::effect(Generator::new(Pattern::Fire, 100));
+//!         compositor.layer_mut(0).unwrap().crossfade(fire, Duration::from_secs(3));
+//!         faded = true;
+//!     }
+//!     conn.write(compositor.render(t))?;
+//!     std::thread::sleep(Duration::from_millis(16));
+//! }
+//! # }
+//! ```
+
+use crate::effects::Effect;
+use crate::protocol::PixelConfig;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+
+/// How a layer is combined with what's below it.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlendMode {
+    /// The layer replaces what's below
+    #[default]
+    Normal,
+    /// Channels are summed, clipping at full
+    Add,
+    /// Channels are multiplied, only ever darkening
+    Multiply,
+    /// Inverse of multiplying the inverses, only ever lightening
+    Screen,
+    /// The brighter of each channel
+    Max,
+}
+
+impl BlendMode {
+    /// Blends channel `top` onto `bottom`.
+    pub fn blend(&self, bottom: u8, top: u8) -> u8 {
+        let (a, b) = (bottom as u16, top as u16);
+        match self {
+            BlendMode::Normal => top,
+            BlendMode::Add => (a + b).min(255) as u8,
+            BlendMode::Multiply => ((a * b + 127) / 255) as u8,
+            BlendMode::Screen => (255 - ((255 - a) * (255 - b) + 127) / 255) as u8,
+            BlendMode::Max => bottom.max(top),
+        }
+    }
+}
+
+/// What a layer draws.
+pub enum Source {
+    /// Fixed pixel data in the compositor's pixel format
+    Frame(Vec<u8>),
+    /// An effect rendered every frame. Effects draw RGB, which fills the first
+    /// channels of other pixel formats.
+    Effect(Box<dyn Effect + Send>),
+}
+
+impl Source {
+    /// Wraps an effect.
+    pub fn effect<E: Effect + Send + 'static>(effect: E) -> Self {
+        Source::Effect(Box::new(effect))
+    }
+
+    fn render(&mut self, t: Duration, channels: usize, scratch: &mut Vec<u8>, out: &mut Vec<u8>) {
+        match self {
+            Source::Frame(data) => {
+                out.clear();
+                out.extend_from_slice(data);
+            }
+            Source::Effect(effect) => {
+                scratch.resize(effect.pixels() * 3, 0);
+                effect.render(t, scratch);
+
+                out.clear();
+                out.resize(effect.pixels() * channels, 0);
+                let n = channels.min(3);
+                for (px, rgb) in out.chunks_exact_mut(channels).zip(scratch.chunks_exact(3)) {
+                    px[..n].copy_from_slice(&rgb[..n]);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Frame(data) => f.debug_tuple("Frame").field(&data.len()).finish(),
+            Source::Effect(effect) => f.debug_tuple("Effect").field(&effect.pixels()).finish(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Transition {
+    from: Source,
+    duration: Duration,
+    // Set on the first render after the transition starts
+    start: Option<Duration>,
+}
+
+/// One layer of a [`Compositor`].
+///
+/// A frame shorter than the output only covers the pixels it has.
+#[derive(Debug)]
+pub struct Layer {
+    source: Source,
+    opacity: f32,
+    mask: Option<Vec<u8>>,
+    blend: BlendMode,
+    transition: Option<Transition>,
+    // Reused render buffers
+    pixels: Vec<u8>,
+    from: Vec<u8>,
+    scratch: Vec<u8>,
+}
+
+impl Layer {
+    /// A layer drawing `source`, fully opaque with normal blending.
+    pub fn new(source: Source) -> Self {
+        Layer {
+            source,
+            opacity: 1.0,
+            mask: None,
+            blend: BlendMode::Normal,
+            transition: None,
+            pixels: Vec::new(),
+            from: Vec::new(),
+            scratch: Vec::new(),
+        }
+    }
+
+    /// A layer drawing fixed pixel data.
+    pub fn frame(data: Vec<u8>) -> Self {
+        Self::new(Source::Frame(data))
+    }
+
+    /// A layer drawing an effect.
+    pub fn effect<E: Effect + Send + 'static>(effect: E) -> Self {
+        Self::new(Source::effect(effect))
+    }
+
+    /// Sets the opacity, from 0.0 (invisible) to 1.0.
+    pub fn opacity(mut self, opacity: f32) -> Self {
+        self.set_opacity(opacity);
+        self
+    }
+
+    /// Sets the blend mode.
+    pub fn blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    /// Sets a mask with one byte per pixel, 0 hiding the layer and 255 showing it.
+    /// Pixels past the end of the mask are hidden.
+    pub fn mask(mut self, mask: Vec<u8>) -> Self {
+        self.mask = Some(mask);
+        self
+    }
+
+    /// Changes the opacity.
+    pub fn set_opacity(&mut self, opacity: f32) {
+        self.opacity = opacity.clamp(0.0, 1.0);
+    }
+
+    /// Changes the blend mode.
+    pub fn set_blend(&mut self, blend: BlendMode) {
+        self.blend = blend;
+    }
+
+    /// Changes or removes the mask.
+    pub fn set_mask(&mut self, mask: Option<Vec<u8>>) {
+        self.mask = mask;
+    }
+
+    /// Replaces the layer's pixel data, keeping any transition going.
+    pub fn set_frame(&mut self, data: &[u8]) {
+        match &mut self.source {
+            Source::Frame(frame) => {
+                frame.clear();
+                frame.extend_from_slice(data);
+            }
+            source => *source = Source::Frame(data.to_vec()),
+        }
+    }
+
+    /// Switches to `source` immediately.
+    pub fn set_source(&mut self, source: Source) {
+        self.source = source;
+        self.transition = None;
+    }
+
+    /// Fades from the current source to `source` over `duration`, starting at the
+    /// next render.
+    pub fn crossfade(&mut self, source: Source, duration: Duration) {
+        let from = std::mem::replace(&mut self.source, source);
+        self.transition = Some(Transition {
+            from,
+            duration,
+            start: None,
+        });
+    }
+
+    /// Whether a crossfade is in progress.
+    pub fn is_transitioning(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    fn render(&mut self, t: Duration, channels: usize) {
+        self.source
+            .render(t, channels, &mut self.scratch, &mut self.pixels);
+
+        let Some(transition) = &mut self.transition else {
+            return;
+        };
+        let start = *transition.start.get_or_insert(t);
+        let progress = if transition.duration.is_zero() {
+            1.0
+        } else {
+            t.saturating_sub(start).as_secs_f32() / transition.duration.as_secs_f32()
+        };
+        if progress >= 1.0 {
+            self.transition = None;
+            return;
+        }
+
+        transition
+            .from
+            .render(t, channels, &mut self.scratch, &mut self.from);
+
+        // Mix over the longer of the two, treating missing pixels as black
+        let len = self.pixels.len().max(self.from.len());
+        self.pixels.resize(len, 0);
+        for (i, to) in self.pixels.iter_mut().enumerate() {
+            let from = self.from.get(i).copied().unwrap_or(0) as f32;
+            *to = (from + (*to as f32 - from) * progress).round() as u8;
+        }
+    }
+}
+
+/// Flattens a stack of layers into a single frame.
+#[derive(Debug)]
+pub struct Compositor {
+    channels: usize,
+    layers: Vec<Layer>,
+    output: Vec<u8>,
+}
+
+impl Compositor {
+    /// A compositor producing frames of `pixels` pixels in `pixel_config`'s format.
+    pub fn new(pixels: usize, pixel_config: PixelConfig) -> Self {
+        let channels = pixel_config.data_type.channels();
+
+        Compositor {
+            channels,
+            layers: Vec::new(),
+            output: vec![0; pixels * channels],
+        }
+    }
+
+    /// Number of pixels in the output.
+    pub fn pixels(&self) -> usize {
+        self.output.len() / self.channels
+    }
+
+    /// Bytes per pixel.
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Adds a layer on top and returns its index.
+    pub fn push(&mut self, layer: Layer) -> usize {
+        self.layers.push(layer);
+        self.layers.len() - 1
+    }
+
+    /// Inserts a layer at `index`, 0 being the bottom, shifting the ones above up.
+    pub fn insert(&mut self, index: usize, layer: Layer) {
+        self.layers.insert(index.min(self.layers.len()), layer);
+    }
+
+    /// Removes and returns the layer at `index`, shifting the ones above down.
+    pub fn remove(&mut self, index: usize) -> Option<Layer> {
+        (index < self.layers.len()).then(|| self.layers.remove(index))
+    }
+
+    /// The layer at `index`.
+    pub fn layer(&self, index: usize) -> Option<&Layer> {
+        self.layers.get(index)
+    }
+
+    /// Mutable access to the layer at `index`.
+    pub fn layer_mut(&mut self, index: usize) -> Option<&mut Layer> {
+        self.layers.get_mut(index)
+    }
+
+    /// Number of layers.
+    pub fn len(&self) -> usize {
+        self.layers.len()
+    }
+
+    /// Whether there are no layers.
+    pub fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    /// Renders every layer at `t` since start and flattens them onto black.
+    pub fn render(&mut self, t: Duration) -> &[u8] {
+        self.output.fill(0);
+
+        for layer in &mut self.layers {
+            layer.render(t, self.channels);
+            if layer.opacity <= 0.0 {
+                continue;
+            }
+
+            let opacity = (layer.opacity * 255.0).round() as u16;
+            let pixels = self
+                .output
+                .chunks_exact_mut(self.channels)
+                .zip(layer.pixels.chunks_exact(self.channels));
+
+            for (i, (out, top)) in pixels.enumerate() {
+                let coverage = match &layer.mask {
+                    Some(mask) => mask.get(i).copied().unwrap_or(0) as u16,
+                    None => 255,
+                };
+                let alpha = (opacity * coverage + 127) / 255;
+                if alpha == 0 {
+                    continue;
+                }
+
+                for (o, &b) in out.iter_mut().zip(top) {
+                    let (a, diff) = (*o as i32, layer.blend.blend(*o, b) as i32 - *o as i32);
+                    // Rounded to nearest so full opacity reaches the blended value
+                    *o = (a + (diff * alpha as i32 + 127 * diff.signum()) / 255) as u8;
+                }
+            }
+        }
+
+        &self.output
+    }
+
+    /// The last rendered frame.
+    pub fn frame(&self) -> &[u8] {
+        &self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effects::{Generator, Palette, Pattern};
+    use crate::protocol::DataType;
+
+    const T0: Duration = Duration::ZERO;
+
+    #[test]
+    fn test_blend_modes() {
+        assert_eq!(BlendMode::Normal.blend(100, 50), 50);
+        assert_eq!(BlendMode::Add.blend(200, 100), 255);
+        assert_eq!(BlendMode::Multiply.blend(255, 128), 128);
+        assert_eq!(BlendMode::Multiply.blend(128, 128), 64);
+        assert_eq!(BlendMode::Screen.blend(0, 128), 128);
+        assert_eq!(BlendMode::Screen.blend(128, 128), 192);
+        assert_eq!(BlendMode::Max.blend(10, 200), 200);
+    }
+
+    #[test]
+    fn test_normal_layers_and_opacity() {
+        let mut compositor = Compositor::new(2, PixelConfig::default());
+        compositor.push(Layer::frame(vec![200; 6]));
+        let top = compositor.push(Layer::frame(vec![0, 0, 100, 0, 0, 100]).opacity(0.5));
+
+        assert_eq!(compositor.render(T0), &[100, 100, 150, 100, 100, 150]);
+
+        compositor.layer_mut(top).unwrap().set_opacity(0.0);
+        assert_eq!(compositor.render(T0), &[200; 6]);
+    }
+
+    #[test]
+    fn test_mask_and_short_frames() {
+        let mut compositor = Compositor::new(3, PixelConfig::default());
+        compositor.push(Layer::frame(vec![10; 9]));
+        // Only two pixels long, and the second is masked out
+        compositor.push(
+            Layer::frame(vec![100; 6])
+                .blend(BlendMode::Add)
+                .mask(vec![255, 0]),
+        );
+
+        assert_eq!(
+            compositor.render(T0),
+            &[110, 110, 110, 10, 10, 10, 10, 10, 10]
+        );
+    }
+
+    #[test]
+    fn test_effect_layers_fill_rgbw() {
+        let config = PixelConfig {
+            data_type: DataType::RGBW,
+            ..PixelConfig::default()
+        };
+        let mut compositor = Compositor::new(2, config);
+        compositor.push(Layer::effect(
+            Generator::new(Pattern::Solid, 2).palette(Palette::solid([1, 2, 3])),
+        ));
+
+        assert_eq!(compositor.render(T0), &[1, 2, 3, 0, 1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn test_crossfade() {
+        let mut compositor = Compositor::new(1, PixelConfig::default());
+        compositor.push(Layer::frame(vec![0, 0, 200]));
+        compositor.render(Duration::from_secs(5));
+
+        let layer = compositor.layer_mut(0).unwrap();
+        layer.crossfade(Source::Frame(vec![200, 0, 0]), Duration::from_secs(2));
+        assert!(layer.is_transitioning());
+
+        // Timed from the first render after starting
+        assert_eq!(compositor.render(Duration::from_secs(10)), &[0, 0, 200]);
+        assert_eq!(compositor.render(Duration::from_secs(11)), &[100, 0, 100]);
+        assert_eq!(compositor.render(Duration::from_secs(12)), &[200, 0, 0]);
+        assert!(!compositor.layer(0).unwrap().is_transitioning());
+    }
+
+    #[test]
+    fn test_layer_order() {
+        let mut compositor = Compositor::new(1, PixelConfig::default());
+        compositor.push(Layer::frame(vec![1, 1, 1]));
+        compositor.insert(0, Layer::frame(vec![2, 2, 2]));
+        assert_eq!(compositor.render(T0), &[1, 1, 1]);
+
+        compositor.remove(1);
+        assert_eq!(compositor.len(), 1);
+        assert_eq!(compositor.render(T0), &[2, 2, 2]);
+        assert!(compositor.remove(5).is_none());
+    }
+}
@@ -3,21 +3,60 @@
 //! This module provides the main [`DDPConnection`] type for communicating with
 //! DDP-compatible LED displays.
 
+use crate::correction::ColorCorrection;
 use crate::error::DDPError;
 use crate::error::DDPError::CrossBeamError;
-use crate::packet::Packet;
+use crate::packet::{self, Packet};
 use crate::protocol;
+use crate::transport::{TcpTransport, Transport, UdpTransport};
 use crossbeam::channel::{unbounded, Receiver, TryRecvError};
-use std::net::{SocketAddr, UdpSocket};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// Maximum pixel data size per DDP packet (480 pixels × 3 bytes RGB = 1440 bytes)
 const MAX_DATA_LENGTH: usize = 480 * 3;
 
+/// Largest DDP header, used when the timecode field is present
+const MAX_HEADER_LENGTH: usize = 14;
+
+/// How pixel writes are stamped with a [`protocol::timecode::TimeCode`].
+///
+/// Timecodes are in units of 1/65536 of a second and tell a display when to show
+/// a frame, which keeps several displays in step. Every packet of a frame gets
+/// the same timecode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Timecode {
+    /// Packets are sent without a timecode
+    #[default]
+    Off,
+    /// Time since the connection was created
+    Elapsed,
+    /// Wall clock time since the Unix epoch, wrapping about every 18 hours. Use
+    /// this to line up displays fed by different connections or machines.
+    Clock,
+}
+
+impl Timecode {
+    pub(crate) fn stamp(&self, started: Instant) -> Option<u32> {
+        let t = match self {
+            Timecode::Off => return None,
+            Timecode::Elapsed => started.elapsed(),
+            Timecode::Clock => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+        };
+        // Wraps, which displays have to cope with anyway
+        Some((t.as_secs() << 16 | (t.subsec_nanos() as u64 * 65536 / 1_000_000_000)) as u32)
+    }
+}
+
 /// A connection to a DDP display device.
 ///
 /// This is the main type for sending pixel data to LED strips and other DDP-compatible
 /// displays. It handles packet assembly, sequencing, and automatic chunking of large
-/// data arrays.
+/// data arrays. Packets go out over UDP unless the connection was created with
+/// another [`Transport`] (see [`with_transport`](Self::with_transport)).
 ///
 /// # Examples
 ///
@@ -72,27 +111,35 @@ pub struct DDPConnection {
     /// Protocol ID for this connection
     pub id: protocol::ID,
 
+    /// Optional gamma/brightness/white point stage applied to pixel writes
+    pub correction: Option<ColorCorrection>,
+
+    /// How pixel writes are stamped with a timecode
+    pub timecode: Timecode,
+
     sequence_number: u8,
-    socket: UdpSocket,
-    addr: SocketAddr,
+    transport: Box<dyn Transport>,
+    max_data_length: usize,
+    started: Instant,
 
     /// Receiver for packets coming from the display (responses)
     pub receiver_packet: Receiver<Packet>,
 
     // Since the buffer is hot path, we can reuse it to avoid allocations per packet
-    buffer: [u8; 1500],
+    buffer: Vec<u8>,
 }
 
 impl DDPConnection {
     /// Writes pixel data to the display starting at offset 0.
     ///
     /// Large data arrays are automatically split into multiple packets. Each packet
-    /// can contain up to 1440 bytes (480 RGB pixels).
+    /// can contain up to 1440 bytes (480 RGB pixels), unless a different MTU was set
+    /// with [`ConnectionBuilder::mtu`].
     ///
     /// # Arguments
     ///
     /// * `data` - Raw pixel data bytes. For RGB, this should be groups of 3 bytes (R,G,B).
-    ///            For RGBW, groups of 4 bytes (R,G,B,W).
+    ///   For RGBW, groups of 4 bytes (R,G,B,W).
     ///
     /// # Returns
     ///
@@ -117,8 +164,9 @@ impl DDPConnection {
         h.packet_type.push(false);
         h.pixel_config = self.pixel_config;
         h.id = self.id;
+        self.stamp(&mut h);
 
-        self.slice_send(&mut h, data)
+        self.slice_send(&mut h, data, true)
     }
 
     /// Writes pixel data to the display starting at a specific byte offset.
@@ -151,8 +199,9 @@ impl DDPConnection {
         h.pixel_config = self.pixel_config;
         h.id = self.id;
         h.offset = offset;
+        self.stamp(&mut h);
 
-        self.slice_send(&mut h, data)
+        self.slice_send(&mut h, data, true)
     }
 
     /// Sends a JSON control message to the display.
@@ -185,18 +234,76 @@ impl DDPConnection {
         let msg_data: Vec<u8> = msg.try_into()?;
         h.length = msg_data.len() as u16;
 
-        self.slice_send(&mut h, &msg_data)
+        self.slice_send(&mut h, &msg_data, false)
+    }
+
+    /// Sends a packet as it is, apart from the sequence number.
+    ///
+    /// This is meant for forwarding and replaying captured traffic: the header is
+    /// kept (offset, flags, ID, timecode) and the data is sent without splitting or
+    /// [`correction`](Self::correction). The connection's sequence number is used so
+    /// the display sees a consistent sequence.
+    ///
+    /// Fails with [`DDPError::BufferTooSmall`] if the data is longer than
+    /// [`max_data_length`](Self::max_data_length), since it can't be split.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use ddp_rs::connection::DDPConnection;
+    /// # use ddp_rs::packet::Packet;
+    /// # use ddp_rs::protocol::{PixelConfig, ID};
+    /// # use std::net::UdpSocket;
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let mut conn = DDPConnection::try_new("192.168.1.40:4048", PixelConfig::default(), ID::Default, UdpSocket::bind("0.0.0.0:4048")?)?;
+    /// let packet = Packet::from_bytes(&[0x41, 0x07, 0x0D, 0x01, 0, 0, 0, 0, 0, 3, 255, 0, 0]);
+    /// conn.write_packet(&packet)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_packet(&mut self, packet: &Packet) -> Result<usize, DDPError> {
+        let mut header = packet.header;
+        header.sequence_number = self.sequence_number;
+
+        let data = packet.payload()?;
+        if data.len() > self.max_data_length {
+            return Err(DDPError::BufferTooSmall {
+                needed: data.len(),
+                available: self.max_data_length,
+            });
+        }
+        let len = packet::encode(header, &data, &mut self.buffer)?;
+        let sent = self.transport.send(&self.buffer[0..len])?;
+
+        if self.sequence_number > 15 {
+            self.sequence_number = 1;
+        } else {
+            self.sequence_number += 1;
+        }
+
+        Ok(sent)
+    }
+
+    fn stamp(&self, header: &mut protocol::Header) {
+        if let Some(t) = self.timecode.stamp(self.started) {
+            header.packet_type.timecode = true;
+            header.time_code = protocol::timecode::TimeCode(Some(t));
+        }
     }
 
     fn slice_send(
         &mut self,
         header: &mut protocol::Header,
         data: &[u8],
+        pixels: bool,
     ) -> Result<usize, DDPError> {
-        let mut offset = header.offset as usize;
+        // header.offset is where data starts in the display buffer, not in `data`
+        let base = header.offset as usize;
+        let mut offset = 0;
         let mut sent = 0;
 
-        let num_iterations = (data.len() + MAX_DATA_LENGTH - 1) / MAX_DATA_LENGTH;
+        let max_data_length = self.max_data_length;
+        let num_iterations = data.len().div_ceil(max_data_length);
         let mut iter = 0;
 
         while offset < data.len() {
@@ -207,14 +314,23 @@ impl DDPConnection {
             }
 
             header.sequence_number = self.sequence_number;
+            header.offset = (base + offset) as u32;
 
-            let chunk_end = std::cmp::min(offset + MAX_DATA_LENGTH, data.len());
+            let chunk_end = std::cmp::min(offset + max_data_length, data.len());
             let chunk = &data[offset..chunk_end];
             header.length = chunk.len() as u16;
-            let len = self.assemble_packet(*header, chunk);
+            let len = packet::encode(*header, chunk, &mut self.buffer)?;
+
+            // Correct in the send buffer so the caller's data is left untouched
+            if pixels {
+                if let Some(correction) = self.correction.as_mut() {
+                    let start = len - chunk.len();
+                    correction.apply_offset(&mut self.buffer[start..len], header.offset as usize);
+                }
+            }
 
             // Send to socket
-            sent += self.socket.send_to(&self.buffer[0..len], self.addr)?;
+            sent += self.transport.send(&self.buffer[0..len])?;
 
             // Increment sequence number
             if self.sequence_number > 15 {
@@ -222,13 +338,248 @@ impl DDPConnection {
             } else {
                 self.sequence_number += 1;
             }
-            offset += MAX_DATA_LENGTH;
-            header.offset = offset as u32;
+            offset += max_data_length;
         }
 
         Ok(sent)
     }
 
+    /// Queries the display and waits for its reply.
+    ///
+    /// Sends an empty query packet with `id` (usually [`protocol::ID::Status`] or
+    /// [`protocol::ID::Config`]) and blocks until a reply with the same ID arrives
+    /// from the display or `timeout` passes. When sending to a multicast group, the
+    /// first display to answer wins.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Packet)` - The reply, with [`Packet::parsed`] filled in if it was JSON
+    /// * `Err(DDPError::NoReply)` - Nothing was received in time
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use ddp_rs::connection::DDPConnection;
+    /// # use ddp_rs::protocol::{PixelConfig, ID};
+    /// # use std::net::UdpSocket;
+    /// # use std::time::Duration;
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let mut conn = DDPConnection::try_new("192.168.1.40:4048", PixelConfig::default(), ID::Default, UdpSocket::bind("0.0.0.0:4048")?)?;
+    /// let reply = conn.query(ID::Status, Duration::from_secs(1))?;
+    /// println!("{:?}", reply.parsed);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn query(&mut self, id: protocol::ID, timeout: Duration) -> Result<Packet, DDPError> {
+        let mut h = protocol::Header::default();
+        h.packet_type.query = true;
+        h.sequence_number = self.sequence_number;
+        h.id = id;
+
+        let header_bytes: [u8; 10] = h.into();
+        self.transport.send(&header_bytes)?;
+
+        let deadline = Instant::now() + timeout;
+        let mut buf = vec![0u8; 65536];
+
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(DDPError::NoReply);
+            }
+
+            match self.transport.recv(&mut buf, Some(remaining))? {
+                Some(size) => {
+                    let packet = Packet::from_bytes(&buf[..size]);
+                    if packet.header.packet_type.reply && packet.header.id == id {
+                        return Ok(packet);
+                    }
+                }
+                None => return Err(DDPError::NoReply),
+            }
+        }
+    }
+
+    /// Sends a CONTROL message to run an effect, switch power or set favorites.
+    ///
+    /// The message is [validated](protocol::message::Control::validate) first.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use ddp_rs::connection::DDPConnection;
+    /// # use ddp_rs::protocol::{PixelConfig, ID};
+    /// # use std::net::UdpSocket;
+    /// use ddp_rs::protocol::message::{Control, EffectName, Favorite};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let mut conn = DDPConnection::try_new("192.168.1.40:4048", PixelConfig::default(), ID::Default, UdpSocket::bind("0.0.0.0:4048")?)?;
+    /// conn.control(Control::effect(EffectName::MultiChaser).intensity(100).speed(40))?;
+    /// conn.control(Control::favorites(vec![Favorite::new(1, "Shift").time(60)]))?;
+    /// conn.control(Control::power(false))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn control(&mut self, control: protocol::message::Control) -> Result<usize, DDPError> {
+        control.validate()?;
+        self.write_message(protocol::message::Message::Control(
+            protocol::message::ControlRoot {
+                control,
+                ..Default::default()
+            },
+        ))
+    }
+
+    /// Reads the display's favorites list, waiting up to `timeout` for it.
+    ///
+    /// Returns [`DDPError::InvalidPacket`] if the reply isn't a CONTROL message.
+    pub fn favorites(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Vec<protocol::message::Favorite>, DDPError> {
+        match self.query(protocol::ID::Control, timeout)?.parsed {
+            Some(protocol::message::Message::Control(c)) => {
+                Ok(c.control.favorites.unwrap_or_default())
+            }
+            _ => Err(DDPError::InvalidPacket),
+        }
+    }
+
+    /// Changes the display's config, keeping whatever `changes` leaves out.
+    ///
+    /// The current config is queried, `changes` is applied on top of it with
+    /// [`Config::merge`](protocol::message::Config::merge), and the result is
+    /// written and queried again to check the display took it. Each query waits
+    /// up to `timeout`.
+    ///
+    /// # Returns
+    ///
+    /// What changed on the display, which is empty if the config already matched
+    /// (in which case nothing is written).
+    ///
+    /// # Errors
+    ///
+    /// * [`DDPError::NoReply`] if the display didn't answer a query
+    /// * [`DDPError::InvalidPacket`] if its config couldn't be parsed
+    /// * [`DDPError::ConfigNotApplied`] with the fields that differ if the config
+    ///   read back isn't what was written
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use ddp_rs::connection::DDPConnection;
+    /// # use ddp_rs::protocol::{PixelConfig, ID};
+    /// # use std::net::UdpSocket;
+    /// # use std::time::Duration;
+    /// use ddp_rs::protocol::message::{Config, ConfigRoot, Port};
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let mut conn = DDPConnection::try_new("192.168.1.40:4048", PixelConfig::default(), ID::Default, UdpSocket::bind("0.0.0.0:4048")?)?;
+    /// let changes = ConfigRoot {
+    ///     config: Config {
+    ///         gw: Some("192.168.1.1".to_string()),
+    ///         ports: vec![Port::new(0).start(0).length(300)],
+    ///         ..Default::default()
+    ///     },
+    ///     ..Default::default()
+    /// };
+    /// let diff = conn.set_config(changes, Duration::from_secs(1))?;
+    /// println!("changed {}", diff);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn set_config(
+        &mut self,
+        changes: protocol::message::ConfigRoot,
+        timeout: Duration,
+    ) -> Result<protocol::message::ConfigDiff, DDPError> {
+        self.edit_config(timeout, |config| config.merge(&changes.config))
+    }
+
+    /// Changes the display's config with `edit`, like [`set_config`](Self::set_config).
+    ///
+    /// `edit` gets the display's current config to change in place, using the
+    /// typed setters on [`Config`](protocol::message::Config).
+    ///
+    /// A display that moves to a new IP address can't be asked again at the old
+    /// one, so when the IP changes the write isn't verified.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// # use ddp_rs::connection::DDPConnection;
+    /// # use ddp_rs::protocol::{PixelConfig, ID};
+    /// # use std::net::UdpSocket;
+    /// # use std::time::Duration;
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let mut conn = DDPConnection::try_new("192.168.1.40:4048", PixelConfig::default(), ID::Default, UdpSocket::bind("0.0.0.0:4048")?)?;
+    /// let diff = conn.edit_config(Duration::from_secs(1), |config| {
+    ///     config.set_netmask([255, 255, 255, 0].into());
+    ///     config.port_mut(1).l = 150;
+    /// })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn edit_config<F>(
+        &mut self,
+        timeout: Duration,
+        edit: F,
+    ) -> Result<protocol::message::ConfigDiff, DDPError>
+    where
+        F: FnOnce(&mut protocol::message::Config),
+    {
+        let current = self.read_config(timeout)?;
+        let mut wanted = current.clone();
+        edit(&mut wanted.config);
+
+        let diff = current.config.diff(&wanted.config);
+        if diff.is_empty() {
+            return Ok(diff);
+        }
+
+        let moved = current.config.ip != wanted.config.ip;
+        self.write_message(protocol::message::Message::Config(wanted.clone()))?;
+        if moved {
+            return Ok(diff);
+        }
+
+        let applied = self.read_config(timeout)?;
+        let missing = applied.config.diff(&wanted.config);
+        if !missing.is_empty() {
+            return Err(DDPError::ConfigNotApplied(missing));
+        }
+
+        Ok(current.config.diff(&applied.config))
+    }
+
+    fn read_config(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<protocol::message::ConfigRoot, DDPError> {
+        match self.query(protocol::ID::Config, timeout)?.parsed {
+            Some(protocol::message::Message::Config(config)) => Ok(config),
+            _ => Err(DDPError::InvalidPacket),
+        }
+    }
+
+    /// The largest number of data bytes sent in one packet.
+    ///
+    /// Writes larger than this are split over several packets.
+    pub fn max_data_length(&self) -> usize {
+        self.max_data_length
+    }
+
+    /// The address of the display this connection sends to, if the transport has
+    /// addresses.
+    pub fn peer_addr(&self) -> Result<SocketAddr, DDPError> {
+        Ok(self.transport.peer_addr()?)
+    }
+
+    /// The local address the connection's socket is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr, DDPError> {
+        Ok(self.transport.local_addr()?)
+    }
+
     /// Attempts to retrieve a packet from the display (non-blocking).
     ///
     /// Checks if any response packets have been received from the display.
@@ -246,6 +597,45 @@ impl DDPConnection {
         }
     }
 
+    /// Starts building a connection to the display at `addr`.
+    ///
+    /// Unlike [`try_new`](Self::try_new), the builder creates the socket itself, so
+    /// socket options such as TTL or QoS marking can be set without touching the
+    /// raw socket. See [`ConnectionBuilder`] for the options and their defaults.
+    ///
+    /// # Examples
+    ///
+    /// Over TCP, which the specification allows on the same port, frames can't be
+    /// lost on the way, at the cost of latency when the link has to retransmit:
+    ///
+    /// ```no_run
+    /// # use ddp_rs::connection::DDPConnection;
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut conn = DDPConnection::builder("192.168.1.40:4048").tcp(true).build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// ```no_run
+    /// use ddp_rs::connection::{DDPConnection, Timecode};
+    /// use ddp_rs::protocol::PixelConfig;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut conn = DDPConnection::builder("192.168.1.40:4048")
+    ///     .bind("0.0.0.0:6969".parse()?)
+    ///     .dscp(46) // Expedited forwarding
+    ///     .mtu(9000)
+    ///     .timecode(Timecode::Elapsed)
+    ///     .build()?;
+    ///
+    /// conn.write(&[255, 0, 0])?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn builder<A: ToSocketAddrs>(addr: A) -> ConnectionBuilder {
+        ConnectionBuilder::new(addr)
+    }
+
     /// Creates a new DDP connection to a display.
     ///
     /// # Arguments
@@ -290,74 +680,479 @@ impl DDPConnection {
             .to_socket_addrs()?
             .next()
             .ok_or(DDPError::NoValidSocketAddr)?;
+        let transport = UdpTransport::new(socket, Some(socket_addr));
+
+        Ok(DDPConnection::with_transport(transport, pixel_config, id))
+    }
+
+    /// Creates a connection that sends over `transport`.
+    ///
+    /// Everything but the wire works as over UDP: writes are split, corrected and
+    /// sequenced the same way, and [`query`](Self::query) waits for the reply on
+    /// the transport.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ddp_rs::connection::DDPConnection;
+    /// use ddp_rs::protocol::{PixelConfig, ID};
+    /// use ddp_rs::transport::TcpTransport;
+    /// use std::net::TcpStream;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let transport = TcpTransport::new(TcpStream::connect("192.168.1.40:4048")?)?;
+    /// let mut conn = DDPConnection::with_transport(transport, PixelConfig::default(), ID::Default);
+    /// conn.write(&[255, 0, 0])?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_transport<T: Transport + 'static>(
+        transport: T,
+        pixel_config: protocol::PixelConfig,
+        id: protocol::ID,
+    ) -> DDPConnection {
+        DDPConnection::from_boxed(Box::new(transport), pixel_config, id)
+    }
+
+    fn from_boxed(
+        transport: Box<dyn Transport>,
+        pixel_config: protocol::PixelConfig,
+        id: protocol::ID,
+    ) -> DDPConnection {
         let (_s, recv) = unbounded();
 
-        Ok(DDPConnection {
-            addr: socket_addr,
+        DDPConnection {
             pixel_config,
             id,
-            socket,
+            correction: None,
+            timecode: Timecode::Off,
+            transport,
             receiver_packet: recv,
             sequence_number: 1,
-            buffer: [0u8; 1500],
-        })
-    }
-
-    // doing this to avoid allocations per frame
-    // micro optimization, but it's a hot path
-    // esp running this embedded
-    #[inline(always)]
-    fn assemble_packet(&mut self, header: protocol::Header, data: &[u8]) -> usize {
-        let header_bytes: usize = if header.packet_type.timecode {
-            let header_bytes: [u8; 14] = header.into();
-            self.buffer[0..14].copy_from_slice(&header_bytes);
-            14usize
+            max_data_length: MAX_DATA_LENGTH,
+            started: Instant::now(),
+            buffer: vec![0u8; MAX_DATA_LENGTH + MAX_HEADER_LENGTH],
+        }
+    }
+}
+
+/// The network interface multicast traffic is sent or received on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MulticastInterface {
+    /// Let the system choose, usually the interface of the default route
+    #[default]
+    Any,
+    /// The IPv4 interface with this address
+    Addr(Ipv4Addr),
+    /// The interface with this index, which is how IPv6 names interfaces
+    Index(u32),
+}
+
+impl MulticastInterface {
+    fn v4(&self) -> Result<Ipv4Addr, DDPError> {
+        match self {
+            MulticastInterface::Any => Ok(Ipv4Addr::UNSPECIFIED),
+            MulticastInterface::Addr(addr) => Ok(*addr),
+            MulticastInterface::Index(_) => Err(DDPError::InvalidOption(
+                "IPv4 multicast interfaces are chosen by address".to_string(),
+            )),
+        }
+    }
+
+    fn v6(&self) -> Result<u32, DDPError> {
+        match self {
+            MulticastInterface::Any => Ok(0),
+            MulticastInterface::Index(index) => Ok(*index),
+            MulticastInterface::Addr(_) => Err(DDPError::InvalidOption(
+                "IPv6 multicast interfaces are chosen by index".to_string(),
+            )),
+        }
+    }
+}
+
+/// Joins `socket` to the multicast `group` on `interface`.
+pub(crate) fn join_multicast(
+    socket: &UdpSocket,
+    group: IpAddr,
+    interface: MulticastInterface,
+) -> Result<(), DDPError> {
+    match group {
+        IpAddr::V4(g) if g.is_multicast() => socket.join_multicast_v4(&g, &interface.v4()?)?,
+        IpAddr::V6(g) if g.is_multicast() => socket.join_multicast_v6(&g, interface.v6()?)?,
+        _ => {
+            return Err(DDPError::InvalidOption(format!(
+                "{} is not a multicast address",
+                group
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// Removes `socket` from the multicast `group` on `interface`.
+pub(crate) fn leave_multicast(
+    socket: &UdpSocket,
+    group: IpAddr,
+    interface: MulticastInterface,
+) -> Result<(), DDPError> {
+    match group {
+        IpAddr::V4(g) if g.is_multicast() => socket.leave_multicast_v4(&g, &interface.v4()?)?,
+        IpAddr::V6(g) if g.is_multicast() => socket.leave_multicast_v6(&g, interface.v6()?)?,
+        _ => {
+            return Err(DDPError::InvalidOption(format!(
+                "{} is not a multicast address",
+                group
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// Builder for a [`DDPConnection`] that creates and configures its own socket.
+///
+/// Created with [`DDPConnection::builder`]. Everything is optional; the defaults
+/// match [`DDPConnection::try_new`] with a socket bound to an ephemeral port:
+///
+/// | Option | Default |
+/// |--------|---------|
+/// | [`bind`](Self::bind) | `0.0.0.0:0` (or `[::]:0` for an IPv6 display) |
+/// | [`broadcast`](Self::broadcast) | off |
+/// | [`ttl`](Self::ttl) | system default |
+/// | [`dscp`](Self::dscp) / [`tos`](Self::tos) | system default (unmarked) |
+/// | [`multicast_hops`](Self::multicast_hops) | 1, the local network |
+/// | [`multicast_interface`](Self::multicast_interface) | [`MulticastInterface::Any`] |
+/// | [`multicast_loop`](Self::multicast_loop) | system default (on) |
+/// | [`send_buffer_size`](Self::send_buffer_size) | system default |
+/// | [`nonblocking`](Self::nonblocking) | off |
+/// | [`tcp`](Self::tcp) | off, UDP is used |
+/// | [`pixel_config`](Self::pixel_config) | RGB, 8 bits per channel |
+/// | [`id`](Self::id) | [`protocol::ID::Default`] |
+/// | [`mtu`](Self::mtu) | 1440 data bytes per packet |
+/// | [`timecode`](Self::timecode) | [`Timecode::Off`] |
+#[derive(Debug)]
+pub struct ConnectionBuilder {
+    addr: Result<SocketAddr, DDPError>,
+    bind: Option<SocketAddr>,
+    broadcast: bool,
+    ttl: Option<u32>,
+    dscp: Option<u8>,
+    tos: Option<u8>,
+    multicast_hops: Option<u32>,
+    multicast_interface: MulticastInterface,
+    multicast_loop: Option<bool>,
+    send_buffer_size: Option<usize>,
+    nonblocking: bool,
+    tcp: bool,
+    pixel_config: protocol::PixelConfig,
+    id: protocol::ID,
+    mtu: Option<usize>,
+    timecode: Timecode,
+}
+
+impl ConnectionBuilder {
+    fn new<A: ToSocketAddrs>(addr: A) -> Self {
+        let addr = addr
+            .to_socket_addrs()
+            .map_err(DDPError::from)
+            .and_then(|mut addrs| addrs.next().ok_or(DDPError::NoValidSocketAddr));
+
+        ConnectionBuilder {
+            addr,
+            bind: None,
+            broadcast: false,
+            ttl: None,
+            dscp: None,
+            tos: None,
+            multicast_hops: None,
+            multicast_interface: MulticastInterface::Any,
+            multicast_loop: None,
+            send_buffer_size: None,
+            nonblocking: false,
+            tcp: false,
+            pixel_config: protocol::PixelConfig::default(),
+            id: protocol::ID::Default,
+            mtu: None,
+            timecode: Timecode::Off,
+        }
+    }
+
+    /// Local address to send from.
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.bind = Some(addr);
+        self
+    }
+
+    /// Allows sending to a broadcast address, such as `192.168.1.255:4048`.
+    pub fn broadcast(mut self, broadcast: bool) -> Self {
+        self.broadcast = broadcast;
+        self
+    }
+
+    /// Time to live (hop limit on IPv6) of outgoing packets.
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Marks outgoing packets with a DSCP class (0-63) for QoS, e.g. 46 for
+    /// expedited forwarding or 34 for AF41. Overrides [`tos`](Self::tos).
+    pub fn dscp(mut self, dscp: u8) -> Self {
+        self.dscp = Some(dscp);
+        self
+    }
+
+    /// Sets the raw type of service byte (traffic class on IPv6), DSCP and ECN
+    /// together.
+    pub fn tos(mut self, tos: u8) -> Self {
+        self.tos = Some(tos);
+        self
+    }
+
+    /// How many routers multicast packets may cross. Displays further away than
+    /// the local network need more than the default of 1.
+    pub fn multicast_hops(mut self, hops: u32) -> Self {
+        self.multicast_hops = Some(hops);
+        self
+    }
+
+    /// Interface multicast packets are sent from. IPv4 interfaces are picked by
+    /// address, IPv6 interfaces by index.
+    pub fn multicast_interface(mut self, interface: MulticastInterface) -> Self {
+        self.multicast_interface = interface;
+        self
+    }
+
+    /// Whether multicast packets are also delivered to group members on this
+    /// host, such as a [`DDPReceiver`](crate::receiver::DDPReceiver) for preview.
+    pub fn multicast_loop(mut self, multicast_loop: bool) -> Self {
+        self.multicast_loop = Some(multicast_loop);
+        self
+    }
+
+    /// Size of the socket's send buffer in bytes. The system may round it.
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Makes writes fail with [`DDPError::Disconnect`] (`WouldBlock`) instead of
+    /// waiting when the send buffer is full, so a slow network can't stall a
+    /// render loop. [`DDPConnection::query`] still waits for its reply.
+    pub fn nonblocking(mut self, nonblocking: bool) -> Self {
+        self.nonblocking = nonblocking;
+        self
+    }
+
+    /// Connects over TCP instead of sending UDP datagrams.
+    ///
+    /// Nothing is lost on a lossy link such as Wi-Fi and config writes are
+    /// reliable, but a retransmission holds up every frame behind it. The display
+    /// has to accept TCP connections, which not all do. Broadcast, multicast and
+    /// nonblocking mode only apply to UDP.
+    pub fn tcp(mut self, tcp: bool) -> Self {
+        self.tcp = tcp;
+        self
+    }
+
+    /// Pixel format used for pixel writes.
+    pub fn pixel_config(mut self, pixel_config: protocol::PixelConfig) -> Self {
+        self.pixel_config = pixel_config;
+        self
+    }
+
+    /// Protocol ID used for pixel writes.
+    pub fn id(mut self, id: protocol::ID) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Largest IP packet the network carries, e.g. 9000 with jumbo frames.
+    ///
+    /// Writes are split so each packet, with its IP, UDP and DDP headers, fits in
+    /// `mtu` bytes. Packets only split on whole pixels.
+    pub fn mtu(mut self, mtu: usize) -> Self {
+        self.mtu = Some(mtu);
+        self
+    }
+
+    /// How pixel writes are stamped with a timecode.
+    pub fn timecode(mut self, timecode: Timecode) -> Self {
+        self.timecode = timecode;
+        self
+    }
+
+    /// Creates the socket, applies the options and returns the connection.
+    ///
+    /// # Errors
+    ///
+    /// * [`DDPError::NoValidSocketAddr`] if the address didn't resolve
+    /// * [`DDPError::InvalidOption`] for a DSCP above 63, an MTU too small for a
+    ///   single pixel, a multicast interface of the wrong address family or UDP
+    ///   only options over TCP
+    /// * [`DDPError::Disconnect`] if the socket couldn't be created, configured,
+    ///   bound or connected
+    pub fn build(self) -> Result<DDPConnection, DDPError> {
+        let addr = self.addr?;
+        let ipv4 = addr.is_ipv4();
+
+        let multicast = self.multicast_hops.is_some()
+            || self.multicast_interface != MulticastInterface::Any
+            || self.multicast_loop.is_some()
+            || addr.ip().is_multicast();
+        if self.tcp && (self.broadcast || multicast || self.nonblocking) {
+            return Err(DDPError::InvalidOption(
+                "broadcast, multicast and nonblocking mode need UDP".to_string(),
+            ));
+        }
+
+        let tos = match self.dscp {
+            Some(dscp) if dscp > 63 => {
+                return Err(DDPError::InvalidOption(format!(
+                    "DSCP {} is above 63",
+                    dscp
+                )))
+            }
+            Some(dscp) => Some(dscp << 2),
+            None => self.tos,
+        };
+
+        let max_data_length = match self.mtu {
+            Some(mtu) => {
+                // IP and UDP or TCP headers
+                let ip = if ipv4 { 20 } else { 40 };
+                let overhead = if self.tcp { ip + 20 } else { ip + 8 };
+                let header = if self.timecode == Timecode::Off {
+                    10
+                } else {
+                    MAX_HEADER_LENGTH
+                };
+                let channels = self.pixel_config.data_type.channels();
+                let length = mtu.saturating_sub(overhead + header).min(u16::MAX as usize);
+                let length = length - length % channels;
+                if length == 0 {
+                    return Err(DDPError::InvalidOption(format!(
+                        "MTU {} is too small for a pixel",
+                        mtu
+                    )));
+                }
+                length
+            }
+            None => MAX_DATA_LENGTH,
+        };
+
+        let bind = self.bind.unwrap_or(if ipv4 {
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+        } else {
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))
+        });
+
+        let socket = if self.tcp {
+            Socket::new(Domain::for_address(bind), Type::STREAM, Some(Protocol::TCP))?
+        } else {
+            let socket = Socket::new(Domain::for_address(bind), Type::DGRAM, Some(Protocol::UDP))?;
+            socket.set_broadcast(self.broadcast)?;
+            socket
+        };
+        if let Some(ttl) = self.ttl {
+            if bind.is_ipv4() {
+                socket.set_ttl_v4(ttl)?;
+            } else {
+                socket.set_unicast_hops_v6(ttl)?;
+            }
+        }
+        if let Some(tos) = tos {
+            if bind.is_ipv4() {
+                socket.set_tos_v4(tos as u32)?;
+            } else {
+                set_tclass_v6(&socket, tos)?;
+            }
+        }
+        if bind.is_ipv4() {
+            if let Some(hops) = self.multicast_hops {
+                socket.set_multicast_ttl_v4(hops)?;
+            }
+            if self.multicast_interface != MulticastInterface::Any {
+                socket.set_multicast_if_v4(&self.multicast_interface.v4()?)?;
+            }
+            if let Some(multicast_loop) = self.multicast_loop {
+                socket.set_multicast_loop_v4(multicast_loop)?;
+            }
+        } else {
+            if let Some(hops) = self.multicast_hops {
+                socket.set_multicast_hops_v6(hops)?;
+            }
+            if self.multicast_interface != MulticastInterface::Any {
+                socket.set_multicast_if_v6(self.multicast_interface.v6()?)?;
+            }
+            if let Some(multicast_loop) = self.multicast_loop {
+                socket.set_multicast_loop_v6(multicast_loop)?;
+            }
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        socket.set_nonblocking(self.nonblocking)?;
+        socket.bind(&SockAddr::from(bind))?;
+
+        let transport: Box<dyn Transport> = if self.tcp {
+            socket.connect(&SockAddr::from(addr))?;
+            Box::new(TcpTransport::new(TcpStream::from(socket))?)
         } else {
-            let header_bytes: [u8; 10] = header.into();
-            self.buffer[0..10].copy_from_slice(&header_bytes);
-            10usize
+            let socket = UdpSocket::from(socket);
+            Box::new(UdpTransport::new(socket, Some(addr)).nonblocking(self.nonblocking))
         };
-        self.buffer[header_bytes..(header_bytes + data.len())].copy_from_slice(data);
 
-        header_bytes + data.len()
+        let mut conn = DDPConnection::from_boxed(transport, self.pixel_config, self.id);
+        conn.timecode = self.timecode;
+        conn.max_data_length = max_data_length;
+        conn.buffer = vec![0u8; max_data_length + MAX_HEADER_LENGTH];
+        Ok(conn)
     }
 }
 
+#[cfg(any(
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "macos",
+))]
+fn set_tclass_v6(socket: &Socket, tclass: u8) -> Result<(), DDPError> {
+    Ok(socket.set_tclass_v6(tclass as u32)?)
+}
+
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "macos",
+)))]
+fn set_tclass_v6(_socket: &Socket, _tclass: u8) -> Result<(), DDPError> {
+    Err(DDPError::InvalidOption(
+        "QoS marking of IPv6 traffic isn't supported on this platform".to_string(),
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::protocol::{PixelConfig, ID};
-    use crossbeam::channel::unbounded;
+    use crate::testing::VirtualDisplay;
     use std::thread;
 
     #[test]
     // Test sending to a loopback device
     fn test_conn() {
-        let data_to_send = &vec![255, 0, 0, 255, 0, 0, 255, 0, 0];
-        let (s, r) = unbounded();
-
-        thread::spawn(move || {
-            let socket = UdpSocket::bind("127.0.0.1:4048").unwrap();
-
-            let mut buf = [0; 1500];
-            let (amt, _) = socket.recv_from(&mut buf).unwrap();
-            let buf = &mut buf[..amt];
-
-            s.send(buf.to_vec()).unwrap();
-        });
-
-        let mut conn = DDPConnection::try_new(
-            "127.0.0.1:4048",
-            PixelConfig::default(),
-            ID::Default,
-            UdpSocket::bind("0.0.0.0:4049").unwrap(),
-        )
-        .unwrap();
+        let data_to_send = &[255, 0, 0, 255, 0, 0, 255, 0, 0];
+        let display = VirtualDisplay::new().unwrap();
+        let mut conn = display.connect(PixelConfig::default()).unwrap();
 
         // Test simple send
         conn.write(data_to_send).unwrap();
-        std::thread::sleep(std::time::Duration::from_millis(10));
-        let recv_data = r.recv().unwrap();
+        let recv_data = display
+            .next_packet(Duration::from_secs(1))
+            .unwrap()
+            .to_bytes()
+            .unwrap();
         assert_eq!(
             &vec![
                 0x41, 0x01, 0x0D, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x09, 0xFF, 0x00, 0x00, 0xFF,
@@ -439,6 +1234,129 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_connection_write_offset_chunked() {
+        use std::time::Duration;
+
+        let (mut conn, display_socket) = create_test_connection();
+        display_socket
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+
+        let data: Vec<u8> = (0..2000).map(|i| i as u8).collect();
+        conn.write_offset(&data, 3000).unwrap();
+
+        let mut buf = [0u8; 1500];
+        let (size, _) = display_socket.recv_from(&mut buf).unwrap();
+        assert_eq!(u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]), 3000);
+        assert_eq!(&buf[10..size], &data[..1440]);
+
+        let (size, _) = display_socket.recv_from(&mut buf).unwrap();
+        assert_eq!(u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]), 4440);
+        assert_eq!(buf[0] & 0x01, 0x01);
+        assert_eq!(&buf[10..size], &data[1440..]);
+    }
+
+    #[test]
+    fn test_connection_write_packet() {
+        use std::time::Duration;
+
+        let (mut conn, display_socket) = create_test_connection();
+        display_socket
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+
+        // Sequence 9 from some other sender, offset 6
+        let packet = Packet::from_bytes(&[0x41, 0x09, 0x0D, 0x01, 0, 0, 0, 6, 0, 3, 1, 2, 3]);
+        conn.write_packet(&packet).unwrap();
+
+        let mut buf = [0u8; 1500];
+        let (size, _) = display_socket.recv_from(&mut buf).unwrap();
+        let sent = Packet::from_bytes(&buf[..size]);
+        assert_eq!(sent.header.sequence_number, 1);
+        assert_eq!(sent.header.offset, 6);
+        assert!(sent.header.packet_type.push);
+        assert_eq!(sent.data, vec![1, 2, 3]);
+
+        // Too big for one datagram is an error rather than cut short
+        let mut packet = Packet::from_bytes(&[0x41, 0x09, 0x0D, 0x01, 0, 0, 0, 0, 0, 0]);
+        packet.data = vec![1; conn.max_data_length() + 3];
+        packet.header.length = packet.data.len() as u16;
+        assert!(matches!(
+            conn.write_packet(&packet),
+            Err(DDPError::BufferTooSmall { .. })
+        ));
+        assert!(display_socket.recv_from(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_query() {
+        let (mut conn, display_socket) = create_test_connection();
+
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 1500];
+            let (size, from) = display_socket.recv_from(&mut buf).unwrap();
+            let query = Packet::from_bytes(&buf[..size]);
+            assert!(query.header.packet_type.query);
+            assert_eq!(query.header.id, ID::Status);
+
+            // Unrelated reply first, which should be skipped
+            let mut reply = vec![0x44, 0x00, 0x0D, 0xFA, 0, 0, 0, 0, 0, 2];
+            reply.extend_from_slice(b"{}");
+            display_socket.send_to(&reply, from).unwrap();
+
+            let body = br#"{"status": {"man": "ddp-rs"}}"#;
+            let mut reply = vec![0x44, 0x00, 0x0D, 0xFB, 0, 0, 0, 0, 0, body.len() as u8];
+            reply.extend_from_slice(body);
+            display_socket.send_to(&reply, from).unwrap();
+        });
+
+        let reply = conn.query(ID::Status, Duration::from_millis(500)).unwrap();
+        handle.join().unwrap();
+
+        match reply.parsed {
+            Some(protocol::message::Message::Status(s)) => {
+                assert_eq!(s.status.man.as_deref(), Some("ddp-rs"))
+            }
+            p => panic!("unexpected reply {:?}", p),
+        }
+    }
+
+    #[test]
+    fn test_query_broadcast() {
+        let display_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut conn = DDPConnection::builder(display_socket.local_addr().unwrap())
+            .broadcast(true)
+            .build()
+            .unwrap();
+
+        // Displays answer a broadcast from their own address
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 1500];
+            let (_, from) = display_socket.recv_from(&mut buf).unwrap();
+            let body = br#"{"status": {"man": "ddp-rs"}}"#;
+            let mut reply = vec![0x44, 0x00, 0x0D, 0xFB, 0, 0, 0, 0, 0, body.len() as u8];
+            reply.extend_from_slice(body);
+            UdpSocket::bind("127.0.0.1:0")
+                .unwrap()
+                .send_to(&reply, from)
+                .unwrap();
+        });
+
+        let reply = conn.query(ID::Status, Duration::from_millis(500)).unwrap();
+        handle.join().unwrap();
+        assert!(reply.header.packet_type.reply);
+    }
+
+    #[test]
+    fn test_query_no_reply() {
+        let (mut conn, _display_socket) = create_test_connection();
+        assert!(matches!(
+            conn.query(ID::Config, Duration::from_millis(20)),
+            Err(DDPError::NoReply)
+        ));
+    }
+
     #[test]
     fn test_connection_sequence_numbers() {
         use std::time::Duration;
@@ -480,11 +1398,8 @@ mod tests {
         let mut received_packets = 0;
         let mut buf = [0u8; 1500];
 
-        loop {
-            match display_socket.recv_from(&mut buf) {
-                Ok(_) => received_packets += 1,
-                Err(_) => break,
-            }
+        while display_socket.recv_from(&mut buf).is_ok() {
+            received_packets += 1;
 
             if received_packets >= 2 {
                 break;
@@ -546,4 +1461,450 @@ mod tests {
 
         assert_eq!(conn.id, custom_id);
     }
+
+    #[test]
+    fn test_builder_defaults() {
+        let display = VirtualDisplay::new().unwrap();
+        let mut conn = DDPConnection::builder(display.addr()).build().unwrap();
+        assert_eq!(conn.pixel_config, PixelConfig::default());
+        assert_eq!(conn.id, ID::Default);
+        assert_eq!(conn.max_data_length(), MAX_DATA_LENGTH);
+        assert_eq!(conn.peer_addr().unwrap(), display.addr());
+
+        conn.write(&[1, 2, 3]).unwrap();
+        let packet = display.next_packet(Duration::from_secs(1)).unwrap();
+        assert!(!packet.header.packet_type.timecode);
+        assert_eq!(packet.data, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_builder_socket_options() {
+        let display = VirtualDisplay::new().unwrap();
+        let conn = DDPConnection::builder(display.addr())
+            .bind("127.0.0.1:0".parse().unwrap())
+            .broadcast(true)
+            .ttl(7)
+            .dscp(46)
+            .send_buffer_size(64 * 1024)
+            .build()
+            .unwrap();
+
+        assert!(conn.local_addr().unwrap().ip().is_loopback());
+        let socket = socket2::SockRef::from(conn.transport.udp_socket().unwrap());
+        assert!(socket.broadcast().unwrap());
+        assert_eq!(socket.ttl_v4().unwrap(), 7);
+        assert_eq!(socket.tos_v4().unwrap(), 46 << 2);
+        assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+    }
+
+    #[test]
+    fn test_builder_invalid_options() {
+        assert!(matches!(
+            DDPConnection::builder("127.0.0.1:4048").dscp(64).build(),
+            Err(DDPError::InvalidOption(_))
+        ));
+        assert!(matches!(
+            DDPConnection::builder("127.0.0.1:4048").mtu(40).build(),
+            Err(DDPError::InvalidOption(_))
+        ));
+        assert!(matches!(
+            DDPConnection::builder("").build(),
+            Err(DDPError::Disconnect(_)) | Err(DDPError::NoValidSocketAddr)
+        ));
+    }
+
+    #[test]
+    fn test_builder_mtu() {
+        use crate::protocol::{DataType, PixelFormat};
+
+        let rgbw = PixelConfig {
+            data_type: DataType::RGBW,
+            data_size: PixelFormat::Pixel32Bits,
+            customer_defined: false,
+        };
+        let display = VirtualDisplay::new().unwrap();
+        let mut conn = DDPConnection::builder(display.addr())
+            .pixel_config(rgbw)
+            .mtu(576)
+            .build()
+            .unwrap();
+
+        // 576 - 28 - 10 = 538, down to whole RGBW pixels
+        assert_eq!(conn.max_data_length(), 536);
+
+        let data = vec![7u8; 1000];
+        conn.write(&data).unwrap();
+        let first = display.next_packet(Duration::from_secs(1)).unwrap();
+        let second = display.next_packet(Duration::from_secs(1)).unwrap();
+        assert_eq!(first.data.len(), 536);
+        assert_eq!(second.header.offset, 536);
+        assert_eq!(second.data.len(), 464);
+        assert!(second.header.packet_type.push);
+
+        // Jumbo frames fit more than the default buffer
+        let mut conn = DDPConnection::builder(display.addr())
+            .mtu(9000)
+            .build()
+            .unwrap();
+        assert_eq!(conn.max_data_length(), 8961);
+        conn.write(&vec![1u8; 3000]).unwrap();
+        assert_eq!(
+            display
+                .next_packet(Duration::from_secs(1))
+                .unwrap()
+                .data
+                .len(),
+            3000
+        );
+    }
+
+    #[test]
+    fn test_builder_timecode() {
+        let display = VirtualDisplay::new().unwrap();
+        let mut conn = DDPConnection::builder(display.addr())
+            .timecode(Timecode::Elapsed)
+            .build()
+            .unwrap();
+
+        conn.write(&vec![0u8; 2000]).unwrap();
+        let first = display.next_packet(Duration::from_secs(1)).unwrap();
+        let second = display.next_packet(Duration::from_secs(1)).unwrap();
+        assert!(first.header.packet_type.timecode);
+        assert_eq!(first.header.time_code, second.header.time_code);
+        // Well under a second since the connection was built
+        assert!(first.header.time_code.0.unwrap() < 65536);
+
+        assert_eq!(Timecode::Off.stamp(Instant::now()), None);
+        assert!(Timecode::Clock.stamp(Instant::now()).is_some());
+    }
+
+    #[test]
+    fn test_builder_nonblocking_query() {
+        let display = VirtualDisplay::new().unwrap();
+        display.set_response(ID::Status, serde_json::json!({"status": {"man": "ddp-rs"}}));
+        let mut conn = DDPConnection::builder(display.addr())
+            .bind("127.0.0.1:0".parse().unwrap())
+            .nonblocking(true)
+            .build()
+            .unwrap();
+
+        let reply = conn.query(ID::Status, Duration::from_secs(1)).unwrap();
+        assert!(reply.header.packet_type.reply);
+        conn.write(&[1, 2, 3]).unwrap();
+    }
+
+    #[test]
+    fn test_builder_ipv6() {
+        let display = match VirtualDisplay::bind("[::1]:0") {
+            Ok(display) => display,
+            Err(e) => {
+                eprintln!("skipping, no IPv6 loopback: {}", e);
+                return;
+            }
+        };
+        let mut conn = DDPConnection::builder(display.addr())
+            .ttl(5)
+            .mtu(1280)
+            .build()
+            .unwrap();
+
+        assert!(conn.local_addr().unwrap().is_ipv6());
+        // 1280 - 48 - 10, down to whole RGB pixels
+        assert_eq!(conn.max_data_length(), 1221);
+
+        conn.write(&[1, 2, 3]).unwrap();
+        let frame = display.next_frame(Duration::from_secs(1)).unwrap();
+        assert_eq!(frame.data, vec![1, 2, 3]);
+
+        let reply = conn.query(ID::Status, Duration::from_secs(1)).unwrap();
+        assert!(reply.header.packet_type.reply);
+    }
+
+    #[test]
+    fn test_builder_multicast() {
+        let group: IpAddr = "239.255.40.48".parse().unwrap();
+        let loopback = MulticastInterface::Addr(Ipv4Addr::LOCALHOST);
+
+        let display = VirtualDisplay::bind("0.0.0.0:0").unwrap();
+        if let Err(e) = display.join_multicast(group, loopback) {
+            eprintln!("skipping, no multicast on loopback: {}", e);
+            return;
+        }
+
+        let mut conn = DDPConnection::builder((group, display.addr().port()))
+            .multicast_interface(loopback)
+            .multicast_hops(1)
+            .multicast_loop(true)
+            .build()
+            .unwrap();
+        let socket = socket2::SockRef::from(conn.transport.udp_socket().unwrap());
+        assert_eq!(socket.multicast_ttl_v4().unwrap(), 1);
+
+        conn.write(&[4, 5, 6]).unwrap();
+        let frame = display.next_frame(Duration::from_secs(1)).unwrap();
+        assert_eq!(frame.data, vec![4, 5, 6]);
+
+        // Replies come from the display's own address
+        let reply = conn.query(ID::Status, Duration::from_secs(1)).unwrap();
+        assert!(reply.header.packet_type.reply);
+    }
+
+    #[test]
+    fn test_builder_tcp() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut query = [0u8; 10];
+            stream.read_exact(&mut query).unwrap();
+            assert!(Packet::from_bytes(&query).header.packet_type.query);
+
+            // Reply split over two writes
+            let body = br#"{"status": {"man": "ddp-rs"}}"#;
+            let mut reply = vec![0x44, 0x00, 0x0D, 0xFB, 0, 0, 0, 0, 0, body.len() as u8];
+            reply.extend_from_slice(body);
+            stream.write_all(&reply[..12]).unwrap();
+            stream.flush().unwrap();
+            thread::sleep(Duration::from_millis(10));
+            stream.write_all(&reply[12..]).unwrap();
+
+            let mut data = [0u8; 13];
+            stream.read_exact(&mut data).unwrap();
+            data
+        });
+
+        let mut conn = DDPConnection::builder(addr).tcp(true).build().unwrap();
+        assert!(conn.transport.udp_socket().is_none());
+        let reply = conn.query(ID::Status, Duration::from_secs(1)).unwrap();
+        assert!(reply.header.packet_type.reply);
+
+        conn.write(&[1, 2, 3]).unwrap();
+        let data = handle.join().unwrap();
+        assert_eq!(&data[10..], &[1, 2, 3]);
+
+        assert!(matches!(
+            DDPConnection::builder(addr)
+                .tcp(true)
+                .broadcast(true)
+                .build(),
+            Err(DDPError::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn test_multicast_invalid() {
+        let socket = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let any = MulticastInterface::Any;
+        assert!(matches!(
+            join_multicast(&socket, "10.0.0.1".parse().unwrap(), any),
+            Err(DDPError::InvalidOption(_))
+        ));
+        assert!(matches!(
+            join_multicast(
+                &socket,
+                "239.1.2.3".parse().unwrap(),
+                MulticastInterface::Index(1)
+            ),
+            Err(DDPError::InvalidOption(_))
+        ));
+        assert!(matches!(
+            DDPConnection::builder("[ff02::1]:4048")
+                .multicast_interface(MulticastInterface::Addr(Ipv4Addr::LOCALHOST))
+                .build(),
+            Err(DDPError::InvalidOption(_))
+        ));
+
+        join_multicast(&socket, "239.1.2.3".parse().unwrap(), any).unwrap();
+        leave_multicast(&socket, "239.1.2.3".parse().unwrap(), any).unwrap();
+    }
+
+    #[test]
+    fn test_with_transport() {
+        use crate::protocol::message::Message;
+        use crate::transport::{MemoryTransport, Transport};
+
+        let (sender, mut display) = MemoryTransport::pair();
+        let mut conn = DDPConnection::with_transport(sender, PixelConfig::default(), ID::Default);
+        assert!(conn.peer_addr().is_err());
+
+        let data: Vec<u8> = (0..2000).map(|i| i as u8).collect();
+        conn.write(&data).unwrap();
+
+        let mut buf = [0u8; 1500];
+        let size = display.recv(&mut buf, None).unwrap().unwrap();
+        let first = Packet::from_bytes(&buf[..size]);
+        assert_eq!(first.data, &data[..1440]);
+        let size = display.recv(&mut buf, None).unwrap().unwrap();
+        let second = Packet::from_bytes(&buf[..size]);
+        assert_eq!(second.header.offset, 1440);
+        assert!(second.header.packet_type.push);
+
+        // Queries are answered over the same transport
+        let body = br#"{"status": {"man": "ddp-rs"}}"#;
+        let mut reply = vec![0x44, 0x00, 0x0D, 0xFB, 0, 0, 0, 0, 0, body.len() as u8];
+        reply.extend_from_slice(body);
+        display.send(&reply).unwrap();
+        let reply = conn.query(ID::Status, Duration::from_millis(100)).unwrap();
+        assert!(matches!(reply.parsed, Some(Message::Status(_))));
+
+        // The query itself arrived at the display
+        let size = display.recv(&mut buf, None).unwrap().unwrap();
+        assert!(Packet::from_bytes(&buf[..size]).header.packet_type.query);
+    }
+
+    #[test]
+    fn test_control() {
+        use crate::protocol::message::{Control, EffectName, Favorite};
+
+        let display = VirtualDisplay::new().unwrap();
+        let mut conn = display.connect(PixelConfig::default()).unwrap();
+
+        conn.control(Control::effect(EffectName::Shift).intensity(50))
+            .unwrap();
+        let packet = display.next_packet(Duration::from_secs(1)).unwrap();
+        assert_eq!(packet.header.id, ID::Control);
+        assert_eq!(packet.data, br#"{"control":{"fx":"Shift","int":50}}"#);
+
+        assert!(matches!(
+            conn.control(Control::effect("Shift").speed(0)),
+            Err(DDPError::InvalidControl(_))
+        ));
+
+        display.set_response(
+            ID::Control,
+            serde_json::json!({"control": {"favorites": [{"i": 1, "fx": "Multi Chaser", "t": 60}]}}),
+        );
+        let favorites = conn.favorites(Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            favorites,
+            vec![Favorite::new(1, EffectName::MultiChaser).time(60)]
+        );
+    }
+
+    #[test]
+    fn test_set_config() {
+        use crate::protocol::message::{Config, ConfigRoot, Port};
+
+        let display = VirtualDisplay::new().unwrap();
+        let mut conn = display.connect(PixelConfig::default()).unwrap();
+        let wait = Duration::from_secs(1);
+
+        let changes = ConfigRoot {
+            config: Config {
+                nm: Some("255.255.0.0".to_string()),
+                ports: vec![Port::new(0).length(300)],
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let diff = conn.set_config(changes.clone(), wait).unwrap();
+        assert_eq!(diff.changes.len(), 2);
+        assert_eq!(diff.get("nm").unwrap().new.as_deref(), Some("255.255.0.0"));
+        assert!(diff.get("ports[0]").is_some());
+
+        // The rest of the config was kept
+        let config = display.response(ID::Config).unwrap();
+        assert_eq!(config["config"]["ip"], "127.0.0.1");
+        assert_eq!(config["config"]["ports"][0]["l"], 300);
+
+        // Writing the same again changes nothing
+        assert!(conn.set_config(changes, wait).unwrap().is_empty());
+
+        let diff = conn
+            .edit_config(wait, |config| {
+                config.port_mut(0).ss = 10;
+                config.port_mut(1).l = 20;
+            })
+            .unwrap();
+        assert_eq!(
+            diff.changes
+                .iter()
+                .map(|c| c.field.as_str())
+                .collect::<Vec<_>>(),
+            vec!["ports[0].ss", "ports[1]"]
+        );
+    }
+
+    #[test]
+    fn test_edit_config_keeps_vendor_fields() {
+        let display = VirtualDisplay::new().unwrap();
+        let mut conn = display.connect(PixelConfig::default()).unwrap();
+        display.set_response(
+            ID::Config,
+            serde_json::json!({"config": {
+                "ip": "127.0.0.1",
+                "ports": [{"port": 0, "ts": 2, "l": 100, "ss": 0, "order": "GRB"}],
+                "name": "porch"
+            }}),
+        );
+
+        conn.edit_config(Duration::from_secs(1), |config| config.port_mut(0).l = 150)
+            .unwrap();
+
+        let config = display.response(ID::Config).unwrap();
+        assert_eq!(config["config"]["name"], "porch");
+        assert_eq!(config["config"]["ports"][0]["order"], "GRB");
+        assert_eq!(config["config"]["ports"][0]["l"], 150);
+    }
+
+    #[test]
+    fn test_set_config_not_applied() {
+        use crate::transport::{MemoryTransport, Transport};
+
+        // A display that reports its config but ignores writes to it
+        let (sender, mut display) = MemoryTransport::pair();
+        let handle = thread::spawn(move || {
+            let body = br#"{"config": {"ip": "10.0.0.2", "ports": []}}"#;
+            let mut reply = vec![0x44, 0x00, 0x0D, 0xFA, 0, 0, 0, 0, 0, body.len() as u8];
+            reply.extend_from_slice(body);
+
+            let mut buf = [0u8; 1500];
+            while let Ok(Some(size)) = display.recv(&mut buf, None) {
+                if Packet::from_bytes(&buf[..size]).header.packet_type.query {
+                    display.send(&reply).unwrap();
+                }
+            }
+        });
+
+        let mut conn = DDPConnection::with_transport(sender, PixelConfig::default(), ID::Default);
+        let result = conn.edit_config(Duration::from_secs(1), |c| {
+            c.set_gateway([10, 0, 0, 1].into())
+        });
+        match result {
+            Err(DDPError::ConfigNotApplied(diff)) => {
+                assert_eq!(diff.to_string(), "gw: - -> 10.0.0.1")
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        drop(conn);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_connection_applies_correction() {
+        use crate::correction::ColorCorrection;
+        use std::time::Duration;
+
+        let (mut conn, display_socket) = create_test_connection();
+        display_socket
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+
+        conn.correction = Some(ColorCorrection::new(conn.pixel_config).brightness(0.5));
+
+        let pixel_data = vec![255, 100, 0];
+        conn.write(&pixel_data).unwrap();
+
+        let mut buf = [0u8; 1500];
+        let (size, _) = display_socket.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[10..size], &[128, 50, 0]);
+
+        // The caller's buffer is not modified
+        assert_eq!(pixel_data, vec![255, 100, 0]);
+    }
 }
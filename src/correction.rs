@@ -0,0 +1,369 @@
+//! Gamma correction, brightness and color calibration.
+//!
+//! LEDs respond linearly to their PWM duty cycle while our eyes do not, and every
+//! batch of pixels has a slightly different white. [`ColorCorrection`] is the output
+//! stage that fixes this up right before pixel data goes on the wire: a per-channel
+//! gamma curve, a white point (or color temperature) correction, global brightness
+//! and optional temporal dithering.
+//!
+//! All stages are folded into one 16-bit lookup table per channel, so applying the
+//! correction costs a table lookup per byte.
+//!
+//! # Examples
+//!
+//! ```
+//! use ddp_rs::correction::ColorCorrection;
+//! use ddp_rs::protocol::PixelConfig;
+//!
+//! let mut correction = ColorCorrection::new(PixelConfig::default())
+//!     .gamma(2.2)
+//!     .brightness(0.5);
+//!
+//! let mut frame = vec![255, 128, 0];
+//! correction.apply(&mut frame);
+//! assert_eq!(frame, vec![128, 28, 0]);
+//! ```
+
+use crate::protocol::PixelConfig;
+use std::collections::HashMap;
+
+/// Output stage applying gamma, white point, brightness and dithering to pixel data.
+///
+/// Attach it to a connection through
+/// [`DDPConnection::correction`](crate::connection::DDPConnection::correction) to have
+/// every pixel write corrected, or call [`apply`](Self::apply) yourself.
+#[derive(Debug, Clone)]
+pub struct ColorCorrection {
+    gamma: Vec<f32>,
+    white_point: Vec<f32>,
+    brightness: f32,
+    dithering: bool,
+
+    // Combined curve for each channel, 8-bit in, 16-bit out
+    luts: Vec<[u16; 256]>,
+
+    // Fractional part left over from the previous frame, per byte offset in the
+    // display buffer. Only offsets that have been written are kept.
+    residual: HashMap<usize, u16>,
+}
+
+impl ColorCorrection {
+    /// Creates a pass-through correction for the channel layout of `pixel_config`.
+    pub fn new(pixel_config: PixelConfig) -> Self {
+        let channels = pixel_config.data_type.channels();
+
+        let mut correction = ColorCorrection {
+            gamma: vec![1.0; channels],
+            white_point: vec![1.0; channels],
+            brightness: 1.0,
+            dithering: false,
+            luts: vec![[0u16; 256]; channels],
+            residual: HashMap::new(),
+        };
+        correction.rebuild();
+        correction
+    }
+
+    /// Sets the same gamma exponent on every channel. 2.2 to 2.8 suits most LEDs.
+    pub fn gamma(mut self, gamma: f32) -> Self {
+        self.gamma.fill(gamma);
+        self.rebuild();
+        self
+    }
+
+    /// Sets the gamma exponent of a single channel. Out of range channels are ignored.
+    pub fn channel_gamma(mut self, channel: usize, gamma: f32) -> Self {
+        if let Some(g) = self.gamma.get_mut(channel) {
+            *g = gamma;
+        }
+        self.rebuild();
+        self
+    }
+
+    /// Sets the global brightness, from 0.0 (off) to 1.0 (full).
+    pub fn brightness(mut self, brightness: f32) -> Self {
+        self.brightness = brightness.clamp(0.0, 1.0);
+        self.rebuild();
+        self
+    }
+
+    /// Sets per-channel scale factors (0.0 to 1.0) that make full white look white.
+    ///
+    /// Channels not covered by `scale` keep a factor of 1.0.
+    pub fn white_point(mut self, scale: &[f32]) -> Self {
+        for (w, s) in self.white_point.iter_mut().zip(scale) {
+            *w = s.clamp(0.0, 1.0);
+        }
+        self.rebuild();
+        self
+    }
+
+    /// Sets the white point to the color of a black body at `kelvin`.
+    ///
+    /// 6500K is neutral; lower values warm the output up, higher values cool it down.
+    /// Only the first three (RGB) channels are affected.
+    pub fn color_temperature(self, kelvin: f32) -> Self {
+        let (r, g, b) = kelvin_to_rgb(kelvin);
+        let (r0, g0, b0) = kelvin_to_rgb(6500.0);
+
+        let scale = [r / r0, g / g0, b / b0];
+        let max = scale.iter().cloned().fold(f32::MIN, f32::max);
+
+        self.white_point(&scale.map(|s| s / max))
+    }
+
+    /// Enables temporal dithering.
+    ///
+    /// The precision lost when rounding to 8 bits is carried over into the next
+    /// frame, so low brightness levels and smooth fades don't band.
+    pub fn dithering(mut self, dithering: bool) -> Self {
+        self.dithering = dithering;
+        self.residual.clear();
+        self
+    }
+
+    /// Number of channels per pixel this correction expects.
+    pub fn channels(&self) -> usize {
+        self.luts.len()
+    }
+
+    /// Corrects a frame of 8-bit pixel data in place.
+    pub fn apply(&mut self, data: &mut [u8]) {
+        self.apply_offset(data, 0);
+    }
+
+    /// Corrects 8-bit pixel data that starts `offset` bytes into the display buffer.
+    ///
+    /// The offset keeps channels and dithering state aligned when a frame is
+    /// corrected in several pieces.
+    pub fn apply_offset(&mut self, data: &mut [u8], offset: usize) {
+        let channels = self.channels();
+
+        for (i, byte) in data.iter_mut().enumerate() {
+            let pos = offset + i;
+            let value = self.luts[pos % channels][*byte as usize];
+            *byte = self.quantize(pos, value);
+        }
+    }
+
+    /// Converts a frame of 16-bit pixel data down to 8 bits, applying the correction.
+    ///
+    /// Only `min(src.len(), dst.len())` values are converted. Enable
+    /// [`dithering`](Self::dithering) to keep the extra precision visible.
+    pub fn apply_u16(&mut self, src: &[u16], dst: &mut [u8]) {
+        let channels = self.channels();
+
+        for (pos, (value, out)) in src.iter().zip(dst.iter_mut()).enumerate() {
+            let lut = &self.luts[pos % channels];
+
+            // Interpolate between the two nearest table entries
+            let scaled = *value as u32 * 255;
+            let i = (scaled / 65535) as usize;
+            let frac = scaled % 65535;
+            let a = lut[i] as i64;
+            let b = lut[(i + 1).min(255)] as i64;
+            let corrected = (a + (b - a) * frac as i64 / 65535) as u16;
+
+            *out = self.quantize(pos, corrected);
+        }
+    }
+
+    fn quantize(&mut self, pos: usize, value: u16) -> u8 {
+        // One 8-bit step is 257 16-bit steps (65535 / 255)
+        if !self.dithering {
+            return ((value as u32 + 128) / 257) as u8;
+        }
+
+        let residual = self.residual.entry(pos).or_insert(0);
+        let sum = value as u32 + *residual as u32;
+        *residual = (sum % 257) as u16;
+        (sum / 257).min(255) as u8
+    }
+
+    fn rebuild(&mut self) {
+        for (ch, lut) in self.luts.iter_mut().enumerate() {
+            let scale = self.white_point[ch] * self.brightness * 65535.0;
+            let gamma = self.gamma[ch];
+
+            for (i, entry) in lut.iter_mut().enumerate() {
+                let v = (i as f32 / 255.0).powf(gamma);
+                *entry = (v * scale).round() as u16;
+            }
+        }
+    }
+}
+
+/// Approximate RGB color of a black body, each component from 0.0 to 1.0.
+fn kelvin_to_rgb(kelvin: f32) -> (f32, f32, f32) {
+    let t = kelvin.clamp(1000.0, 40000.0) / 100.0;
+
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.698_73 * (t - 60.0).powf(-0.133_204_76)
+    };
+
+    let g = if t <= 66.0 {
+        99.470_8 * t.ln() - 161.119_57
+    } else {
+        288.122_16 * (t - 60.0).powf(-0.075_514_85)
+    };
+
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.517_73 * (t - 10.0).ln() - 305.044_8
+    };
+
+    (
+        r.clamp(0.0, 255.0) / 255.0,
+        g.clamp(0.0, 255.0) / 255.0,
+        b.clamp(0.0, 255.0) / 255.0,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{DataType, PixelFormat};
+
+    #[test]
+    fn test_passthrough() {
+        let mut correction = ColorCorrection::new(PixelConfig::default());
+        let mut data: Vec<u8> = (0..=255).collect();
+        let expected = data.clone();
+
+        correction.apply(&mut data);
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn test_gamma() {
+        let mut correction = ColorCorrection::new(PixelConfig::default()).gamma(2.0);
+        let mut data = vec![0, 128, 255];
+
+        correction.apply(&mut data);
+        assert_eq!(data, vec![0, 64, 255]);
+    }
+
+    #[test]
+    fn test_channel_gamma() {
+        let mut correction = ColorCorrection::new(PixelConfig::default()).channel_gamma(1, 2.0);
+        let mut data = vec![128, 128, 128, 128, 128, 128];
+
+        correction.apply(&mut data);
+        assert_eq!(data, vec![128, 64, 128, 128, 64, 128]);
+    }
+
+    #[test]
+    fn test_brightness() {
+        let mut correction = ColorCorrection::new(PixelConfig::default()).brightness(0.5);
+        let mut data = vec![255, 100, 0];
+
+        correction.apply(&mut data);
+        assert_eq!(data, vec![128, 50, 0]);
+    }
+
+    #[test]
+    fn test_white_point_rgbw() {
+        let config = PixelConfig {
+            data_type: DataType::RGBW,
+            data_size: PixelFormat::Pixel32Bits,
+            customer_defined: false,
+        };
+        let mut correction = ColorCorrection::new(config).white_point(&[1.0, 0.5, 0.25]);
+        let mut data = vec![255; 8];
+
+        correction.apply(&mut data);
+        assert_eq!(data, vec![255, 128, 64, 255, 255, 128, 64, 255]);
+    }
+
+    #[test]
+    fn test_color_temperature() {
+        let mut neutral = ColorCorrection::new(PixelConfig::default()).color_temperature(6500.0);
+        let mut data = vec![255, 255, 255];
+        neutral.apply(&mut data);
+        assert_eq!(data, vec![255, 255, 255]);
+
+        let mut warm = ColorCorrection::new(PixelConfig::default()).color_temperature(3000.0);
+        let mut data = vec![255, 255, 255];
+        warm.apply(&mut data);
+        assert_eq!(data[0], 255);
+        assert!(data[1] < 255);
+        assert!(data[2] < data[1]);
+    }
+
+    #[test]
+    fn test_apply_offset_keeps_channels_aligned() {
+        let mut correction =
+            ColorCorrection::new(PixelConfig::default()).white_point(&[1.0, 0.0, 0.0]);
+        let mut data = vec![255, 255, 255];
+
+        // Starts on the green channel
+        correction.apply_offset(&mut data, 1);
+        assert_eq!(data, vec![0, 0, 255]);
+    }
+
+    #[test]
+    fn test_dithering_averages_out() {
+        // Half brightness of 1 is half an 8-bit step
+        let mut correction = ColorCorrection::new(PixelConfig::default())
+            .brightness(0.5)
+            .dithering(true);
+
+        let mut total = 0u32;
+        for _ in 0..8 {
+            let mut data = vec![1, 0, 0];
+            correction.apply(&mut data);
+            total += data[0] as u32;
+        }
+
+        assert_eq!(total, 4);
+    }
+
+    #[test]
+    fn test_dithering_far_offset() {
+        let mut correction = ColorCorrection::new(PixelConfig::default())
+            .brightness(0.5)
+            .dithering(true);
+
+        let mut total = 0u32;
+        for _ in 0..8 {
+            let mut data = vec![1, 0, 0];
+            correction.apply_offset(&mut data, 3_000_000_000);
+            total += data[0] as u32;
+        }
+
+        assert_eq!(total, 4);
+        // State is kept for the bytes written, not everything before them
+        assert_eq!(correction.residual.len(), 3);
+    }
+
+    #[test]
+    fn test_apply_u16() {
+        let mut correction = ColorCorrection::new(PixelConfig::default());
+        let src = [0u16, 0x8000, 0xffff];
+        let mut dst = [0u8; 3];
+
+        correction.apply_u16(&src, &mut dst);
+        assert_eq!(dst, [0, 128, 255]);
+    }
+
+    #[test]
+    fn test_apply_u16_dithered() {
+        let mut correction = ColorCorrection::new(PixelConfig::default()).dithering(true);
+
+        // Maps to 99.6 in 8-bit terms, which rounds up to 100 without dithering
+        let src = [100 << 8; 3];
+        let mut total = 0u32;
+        for _ in 0..4 {
+            let mut dst = [0u8; 3];
+            correction.apply_u16(&src, &mut dst);
+            total += dst[0] as u32;
+        }
+
+        assert_eq!(total, 398);
+    }
+}
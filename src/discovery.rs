@@ -0,0 +1,232 @@
+//! Finding DDP displays on the local network.
+//!
+//! Displays answer a STATUS query sent to the broadcast address, so discovery is a
+//! matter of sending one and collecting the replies for a while. IPv6 has no
+//! broadcast, so there the query goes to the [`ALL_NODES`] multicast group instead.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use ddp_rs::discovery;
+//! use std::net::UdpSocket;
+//! use std::time::Duration;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let socket = UdpSocket::bind("0.0.0.0:0")?;
+//! for device in discovery::discover(&socket, discovery::BROADCAST, Duration::from_secs(1))? {
+//!     println!("{} {:?}", device.addr, device.status().and_then(|s| s.model.clone()));
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::error::DDPError;
+use crate::packet::Packet;
+use crate::protocol::message::{Message, Status};
+use crate::protocol::profile::{Extension, Profile, Registry};
+use crate::protocol::{Header, ID};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Standard DDP port.
+pub const PORT: u16 = 4048;
+
+/// Limited broadcast address on the standard DDP port.
+pub const BROADCAST: &str = "255.255.255.255:4048";
+
+/// Link-local all nodes multicast group on the standard DDP port, the IPv6
+/// counterpart of [`BROADCAST`]. Use an IPv6 socket; the query goes out on the
+/// socket's multicast interface.
+pub const ALL_NODES: &str = "[ff02::1]:4048";
+
+/// A display that answered a discovery query.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Device {
+    /// Address the reply came from
+    pub addr: SocketAddr,
+
+    /// The STATUS reply, if it could be parsed
+    pub message: Option<Message>,
+}
+
+impl Device {
+    /// The typed status of the display, if its reply matched the specification.
+    pub fn status(&self) -> Option<&Status> {
+        match &self.message {
+            Some(Message::Status(s)) => Some(&s.status),
+            _ => None,
+        }
+    }
+
+    /// The profile in `registry` that recognizes the display, if any.
+    pub fn profile<'a>(&self, registry: &'a Registry) -> Option<&'a dyn Profile> {
+        registry.identify(self.status()?)
+    }
+
+    /// The vendor fields of the STATUS reply, parsed with the display's profile.
+    pub fn extension(&self, registry: &Registry) -> Result<Option<Extension>, DDPError> {
+        match (self.status(), &self.message) {
+            (Some(status), Some(message)) => registry.parse(status, message),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Sends a STATUS query to `addr` and collects replies for `timeout`.
+///
+/// `addr` is usually [`BROADCAST`], a subnet broadcast address or [`ALL_NODES`],
+/// but unicast and other multicast addresses work too. Broadcast is enabled on
+/// IPv4 sockets. Every device is listed once, in the order it replied.
+pub fn discover<A: ToSocketAddrs>(
+    socket: &UdpSocket,
+    addr: A,
+    timeout: Duration,
+) -> Result<Vec<Device>, DDPError> {
+    let addr = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or(DDPError::NoValidSocketAddr)?;
+
+    let mut h = Header::default();
+    h.packet_type.query = true;
+    h.id = ID::Status;
+    let header_bytes: [u8; 10] = h.into();
+
+    if socket.local_addr()?.is_ipv4() {
+        socket.set_broadcast(true)?;
+    }
+    socket.send_to(&header_bytes, addr)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut devices: Vec<Device> = Vec::new();
+    let mut buf = [0u8; 1500];
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        socket.set_read_timeout(Some(remaining))?;
+
+        let (size, from) = match socket.recv_from(&mut buf) {
+            Ok(r) => r,
+            Err(e)
+                if e.kind() == std::io::ErrorKind::WouldBlock
+                    || e.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                break
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let packet = Packet::from_bytes(&buf[..size]);
+        if !packet.header.packet_type.reply || packet.header.id != ID::Status {
+            continue;
+        }
+        if devices.iter().any(|d| d.addr == from) {
+            continue;
+        }
+
+        devices.push(Device {
+            addr: from,
+            message: packet.parsed,
+        });
+    }
+
+    socket.set_read_timeout(None)?;
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discover() {
+        let display = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let display_addr = display.local_addr().unwrap();
+
+        let handle = std::thread::spawn(move || {
+            let mut buf = [0u8; 1500];
+            let (size, from) = display.recv_from(&mut buf).unwrap();
+            let query = Packet::from_bytes(&buf[..size]);
+            assert!(query.header.packet_type.query);
+
+            let body = br#"{"status": {"mod": "test", "ver": "1.0"}}"#;
+            let mut reply = vec![0x44, 0x00, 0x0D, 0xFB, 0, 0, 0, 0, 0, body.len() as u8];
+            reply.extend_from_slice(body);
+
+            // Answering twice still lists the display once
+            display.send_to(&reply, from).unwrap();
+            display.send_to(&reply, from).unwrap();
+        });
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let devices = discover(&socket, display_addr, Duration::from_millis(200)).unwrap();
+        handle.join().unwrap();
+
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].addr, display_addr);
+        assert_eq!(devices[0].status().unwrap().model.as_deref(), Some("test"));
+    }
+
+    #[test]
+    fn test_discover_ipv6() {
+        use crate::testing::VirtualDisplay;
+
+        let display = match VirtualDisplay::bind("[::1]:0") {
+            Ok(display) => display,
+            Err(e) => {
+                eprintln!("skipping, no IPv6 loopback: {}", e);
+                return;
+            }
+        };
+        let socket = UdpSocket::bind("[::1]:0").unwrap();
+
+        let devices = discover(&socket, display.addr(), Duration::from_millis(200)).unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].addr, display.addr());
+        assert_eq!(
+            devices[0].status().unwrap().model.as_deref(),
+            Some("virtual display")
+        );
+    }
+
+    #[test]
+    fn test_discover_nothing() {
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let devices = discover(
+            &socket,
+            silent.local_addr().unwrap(),
+            Duration::from_millis(20),
+        )
+        .unwrap();
+        assert!(devices.is_empty());
+    }
+
+    #[test]
+    fn test_device_profile() {
+        let registry = Registry::default();
+        let device = Device {
+            addr: "127.0.0.1:4048".parse().unwrap(),
+            message: Some(Message::Status(
+                serde_json::from_str(r#"{"status":{"man":"WLED","leds":{"count":60}}}"#).unwrap(),
+            )),
+        };
+
+        assert_eq!(device.profile(&registry).unwrap().name(), "wled");
+        match device.extension(&registry).unwrap() {
+            Some(Extension::Wled(wled)) => assert_eq!(wled.leds.unwrap().count, Some(60)),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let unknown = Device {
+            message: None,
+            ..device
+        };
+        assert!(unknown.profile(&registry).is_none());
+        assert!(unknown.extension(&registry).unwrap().is_none());
+    }
+}
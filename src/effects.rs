@@ -0,0 +1,516 @@
+//! Standard pattern generators for demos, tests and ambient output.
+//!
+//! A [`Generator`] renders one of the built-in [`Pattern`]s for a strip of a given
+//! length, at a given speed and through a [`Palette`], into RGB frames that can be
+//! passed straight to [`DDPConnection::write`](crate::connection::DDPConnection::write).
+//! Custom effects implement the [`Effect`] trait.
+//!
+//! Effects are driven by the time since they started rather than a frame counter,
+//! so they run at the same speed whatever the frame rate.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use ddp_rs::connection::DDPConnection;
+//! use ddp_rs::effects::{Effect, Generator, Palette, Pattern};
+//! use ddp_rs::protocol::{PixelConfig, ID};
+//! use std::net::UdpSocket;
+//! use std::time::{Duration, Instant};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut conn = DDPConnection::try_new(
+//!     "192.168.1.40:4048",
+//!     PixelConfig::default(),
+//!     ID::Default,
+//!     UdpSocket::bind("0.0.0.0:4048")?
+//! )?;
+//!
+//! let mut fire = Generator::new(Pattern::Fire, 300).speed(1.5);
+//! let mut ocean = Generator::new(Pattern::Plasma, 300).palette(Palette::ocean());
+//!
+//! let start = Instant::now();
+//! loop {
+//!     let effect = if start.elapsed().as_secs() % 20 < 10 { &mut fire } else { &mut ocean };
+//!     conn.write(&effect.frame(start.elapsed()))?;
+//!     std::thread::sleep(Duration::from_millis(16));
+//! }
+//! # }
+//! ```
+
+use serde::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+/// Something that draws RGB frames over time.
+pub trait Effect {
+    /// Number of pixels rendered.
+    fn pixels(&self) -> usize;
+
+    /// Renders the effect at `t` since it started into `frame`, 3 bytes per pixel.
+    ///
+    /// Pixels that don't fit in `frame` are skipped. Stateful effects expect `t` to
+    /// increase between calls.
+    fn render(&mut self, t: Duration, frame: &mut [u8]);
+
+    /// Renders into a newly allocated frame.
+    fn frame(&mut self, t: Duration) -> Vec<u8> {
+        let mut frame = vec![0u8; self.pixels() * 3];
+        self.render(t, &mut frame);
+        frame
+    }
+}
+
+/// A gradient of colors effects pick from, with positions between 0.0 and 1.0.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Palette {
+    /// Colors spread evenly from 0.0 to 1.0
+    pub colors: Vec<[u8; 3]>,
+}
+
+impl Palette {
+    /// A gradient through `colors`, evenly spaced.
+    pub fn new(colors: &[[u8; 3]]) -> Self {
+        Palette {
+            colors: colors.to_vec(),
+        }
+    }
+
+    /// A single color.
+    pub fn solid(color: [u8; 3]) -> Self {
+        Self::new(&[color])
+    }
+
+    /// Every hue, wrapping back to red.
+    pub fn rainbow() -> Self {
+        Self::new(&[
+            [255, 0, 0],
+            [255, 255, 0],
+            [0, 255, 0],
+            [0, 255, 255],
+            [0, 0, 255],
+            [255, 0, 255],
+            [255, 0, 0],
+        ])
+    }
+
+    /// Black through red and yellow to white, for heat.
+    pub fn fire() -> Self {
+        Self::new(&[
+            [0, 0, 0],
+            [128, 0, 0],
+            [255, 32, 0],
+            [255, 160, 0],
+            [255, 255, 96],
+            [255, 255, 255],
+        ])
+    }
+
+    /// Deep blues and teals.
+    pub fn ocean() -> Self {
+        Self::new(&[
+            [0, 0, 32],
+            [0, 32, 128],
+            [0, 128, 160],
+            [32, 192, 192],
+            [0, 32, 128],
+            [0, 0, 32],
+        ])
+    }
+
+    /// Greens and yellows.
+    pub fn forest() -> Self {
+        Self::new(&[
+            [0, 48, 0],
+            [16, 128, 16],
+            [96, 160, 0],
+            [192, 192, 32],
+            [16, 128, 16],
+            [0, 48, 0],
+        ])
+    }
+
+    /// Saturated pinks, purples and oranges.
+    pub fn party() -> Self {
+        Self::new(&[
+            [255, 0, 128],
+            [128, 0, 255],
+            [0, 64, 255],
+            [255, 0, 64],
+            [255, 128, 0],
+            [255, 0, 128],
+        ])
+    }
+
+    /// The color at `position`, clamped to 0.0 to 1.0.
+    ///
+    /// An empty palette is black.
+    pub fn at(&self, position: f32) -> [u8; 3] {
+        match self.colors.len() {
+            0 => [0; 3],
+            1 => self.colors[0],
+            n => {
+                let x = position.clamp(0.0, 1.0) * (n - 1) as f32;
+                let i = (x as usize).min(n - 2);
+                let f = x - i as f32;
+                let (a, b) = (self.colors[i], self.colors[i + 1]);
+                std::array::from_fn(|c| {
+                    (a[c] as f32 + (b[c] as f32 - a[c] as f32) * f).round() as u8
+                })
+            }
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::rainbow()
+    }
+}
+
+/// The built-in patterns.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pattern {
+    /// The first palette color on every pixel
+    Solid,
+    /// The palette stretched along the strip, scrolling
+    Rainbow,
+    /// Every third pixel lit, marching along the strip
+    Chase,
+    /// Random pixels flashing and fading out
+    Twinkle,
+    /// Flames rising from the start of the strip, defaulting to the fire palette
+    Fire,
+    /// Overlapping sine waves
+    Plasma,
+    /// Smooth random drifting color
+    Noise,
+    /// The whole strip fading in and out
+    Breathing,
+}
+
+/// Steps per second of the simulated patterns at speed 1.0.
+const STEP_RATE: f32 = 60.0;
+
+/// Renders a [`Pattern`].
+#[derive(Debug, Clone)]
+pub struct Generator {
+    pattern: Pattern,
+    pixels: usize,
+    speed: f32,
+    palette: Palette,
+    seed: u32,
+    rng: u32,
+    // Heat for fire, brightness for twinkle
+    levels: Vec<f32>,
+    // Palette position for twinkle
+    hues: Vec<f32>,
+    // Simulation time already stepped, in steps
+    stepped: f32,
+}
+
+impl Generator {
+    /// A generator for `pixels` pixels at normal speed with the default palette.
+    pub fn new(pattern: Pattern, pixels: usize) -> Self {
+        let palette = match pattern {
+            Pattern::Fire => Palette::fire(),
+            _ => Palette::default(),
+        };
+
+        Generator {
+            pattern,
+            pixels,
+            speed: 1.0,
+            palette,
+            seed: 0x2545_f491,
+            rng: 0x2545_f491,
+            levels: vec![0.0; pixels],
+            hues: vec![0.0; pixels],
+            stepped: 0.0,
+        }
+    }
+
+    /// Multiplies how fast the pattern moves, 1.0 being normal.
+    pub fn speed(mut self, speed: f32) -> Self {
+        self.speed = speed.max(0.0);
+        self
+    }
+
+    /// Sets the colors the pattern uses.
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    /// Seeds the random patterns (twinkle, fire, noise) for repeatable output.
+    pub fn seed(mut self, seed: u32) -> Self {
+        // xorshift gets stuck at zero
+        self.seed = seed.max(1);
+        self.rng = self.seed;
+        self
+    }
+
+    /// The pattern being rendered.
+    pub fn pattern(&self) -> Pattern {
+        self.pattern
+    }
+
+    fn random(&mut self) -> f32 {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng >> 8) as f32 / (1 << 24) as f32
+    }
+
+    /// Advances fire and twinkle to `t`, at most a second of steps at a time.
+    fn step_to(&mut self, t: f32) {
+        let target = t * STEP_RATE;
+        if target < self.stepped {
+            // Time went backwards, start over
+            self.levels.fill(0.0);
+            self.stepped = 0.0;
+            self.rng = self.seed;
+        }
+        self.stepped = self.stepped.max(target - STEP_RATE);
+
+        while self.stepped + 1.0 <= target {
+            self.stepped += 1.0;
+            match self.pattern {
+                Pattern::Fire => self.step_fire(),
+                Pattern::Twinkle => self.step_twinkle(),
+                _ => {}
+            }
+        }
+    }
+
+    // Fire2012 by Mark Kriegsman
+    fn step_fire(&mut self) {
+        let n = self.pixels;
+        let cooling = (0.55 * 10.0 / n.max(1) as f32 + 2.0 / 255.0).min(1.0);
+
+        for i in 0..n {
+            let cool = self.random() * cooling;
+            self.levels[i] = (self.levels[i] - cool).max(0.0);
+        }
+        for i in (2..n).rev() {
+            self.levels[i] = (self.levels[i - 1] + 2.0 * self.levels[i - 2]) / 3.0;
+        }
+        if n > 0 && self.random() < 0.47 {
+            let i = ((self.random() * 7.0) as usize).min(n - 1);
+            let spark = 0.63 + self.random() * 0.37;
+            self.levels[i] = (self.levels[i] + spark).min(1.0);
+        }
+    }
+
+    fn step_twinkle(&mut self) {
+        for i in 0..self.pixels {
+            self.levels[i] *= 0.93;
+            if self.random() < 0.005 {
+                self.levels[i] = 1.0;
+                self.hues[i] = self.random();
+            }
+        }
+    }
+
+    fn color(&self, i: usize, t: f32) -> [u8; 3] {
+        let x = i as f32 / self.pixels.max(1) as f32;
+
+        match self.pattern {
+            Pattern::Solid => self.palette.at(0.0),
+            Pattern::Rainbow => self.palette.at((x + t * 0.2).fract()),
+            Pattern::Chase => {
+                if (i + (t * 10.0) as usize).is_multiple_of(3) {
+                    self.palette.at(x)
+                } else {
+                    [0; 3]
+                }
+            }
+            Pattern::Twinkle => scale(self.palette.at(self.hues[i]), self.levels[i]),
+            Pattern::Fire => self.palette.at(self.levels[i]),
+            Pattern::Plasma => {
+                let v = (x * 9.0 + t).sin()
+                    + (x * 15.0 - t * 1.3).sin()
+                    + ((x * 4.0 + t * 0.4).sin() * 3.0 + t * 0.7).sin();
+                self.palette.at((v + 3.0) / 6.0)
+            }
+            Pattern::Noise => self.palette.at(noise(self.seed, i as f32 * 0.08, t * 0.5)),
+            Pattern::Breathing => {
+                let level = (1.0 - (t * TAU / 4.0).cos()) / 2.0;
+                scale(self.palette.at((t * 0.02).fract()), level * level)
+            }
+        }
+    }
+}
+
+impl Effect for Generator {
+    fn pixels(&self) -> usize {
+        self.pixels
+    }
+
+    fn render(&mut self, t: Duration, frame: &mut [u8]) {
+        let t = t.as_secs_f32() * self.speed;
+        if matches!(self.pattern, Pattern::Fire | Pattern::Twinkle) {
+            self.step_to(t);
+        }
+
+        for (i, px) in frame.chunks_exact_mut(3).take(self.pixels).enumerate() {
+            px.copy_from_slice(&self.color(i, t));
+        }
+    }
+}
+
+fn scale(color: [u8; 3], level: f32) -> [u8; 3] {
+    color.map(|c| (c as f32 * level.clamp(0.0, 1.0)).round() as u8)
+}
+
+/// Smoothly interpolated value noise between 0.0 and 1.0.
+fn noise(seed: u32, x: f32, y: f32) -> f32 {
+    let lattice = |x: i32, y: i32| {
+        let mut h =
+            seed ^ (x as u32).wrapping_mul(0x27d4_eb2d) ^ (y as u32).wrapping_mul(0x1656_67b1);
+        h = (h ^ (h >> 15)).wrapping_mul(0x85eb_ca6b);
+        h = (h ^ (h >> 13)).wrapping_mul(0xc2b2_ae35);
+        (h ^ (h >> 16)) as f32 / u32::MAX as f32
+    };
+    let smooth = |f: f32| f * f * (3.0 - 2.0 * f);
+
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (smooth(x - x0), smooth(y - y0));
+    let (x0, y0) = (x0 as i32, y0 as i32);
+
+    let top = lattice(x0, y0) + (lattice(x0 + 1, y0) - lattice(x0, y0)) * fx;
+    let bottom = lattice(x0, y0 + 1) + (lattice(x0 + 1, y0 + 1) - lattice(x0, y0 + 1)) * fx;
+    top + (bottom - top) * fy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Pattern; 8] = [
+        Pattern::Solid,
+        Pattern::Rainbow,
+        Pattern::Chase,
+        Pattern::Twinkle,
+        Pattern::Fire,
+        Pattern::Plasma,
+        Pattern::Noise,
+        Pattern::Breathing,
+    ];
+
+    fn pixel(frame: &[u8], i: usize) -> [u8; 3] {
+        [frame[i * 3], frame[i * 3 + 1], frame[i * 3 + 2]]
+    }
+
+    #[test]
+    fn test_palette() {
+        let palette = Palette::new(&[[0, 0, 0], [255, 100, 0]]);
+        assert_eq!(palette.at(0.0), [0, 0, 0]);
+        assert_eq!(palette.at(0.5), [128, 50, 0]);
+        assert_eq!(palette.at(1.0), [255, 100, 0]);
+        assert_eq!(palette.at(7.0), [255, 100, 0]);
+
+        assert_eq!(Palette::rainbow().at(0.0), Palette::rainbow().at(1.0));
+        assert_eq!(Palette::solid([1, 2, 3]).at(0.7), [1, 2, 3]);
+        assert_eq!(Palette::new(&[]).at(0.5), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_frame_sizes() {
+        for pattern in ALL {
+            let mut generator = Generator::new(pattern, 50);
+            assert_eq!(generator.frame(Duration::from_millis(500)).len(), 150);
+
+            // Short buffers are filled as far as they go
+            let mut short = [0u8; 10];
+            generator.render(Duration::from_secs(1), &mut short);
+
+            let mut empty = Generator::new(pattern, 0);
+            assert!(empty.frame(Duration::from_secs(1)).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_solid() {
+        let mut solid = Generator::new(Pattern::Solid, 4).palette(Palette::solid([9, 8, 7]));
+        assert_eq!(solid.frame(Duration::ZERO), [9, 8, 7].repeat(4));
+    }
+
+    #[test]
+    fn test_rainbow_scrolls() {
+        let mut rainbow = Generator::new(Pattern::Rainbow, 6);
+        let start = rainbow.frame(Duration::ZERO);
+        assert_eq!(pixel(&start, 0), [255, 0, 0]);
+        assert_eq!(pixel(&start, 2), [0, 255, 0]);
+
+        // One full cycle every 5 seconds, faster with speed
+        assert_ne!(rainbow.frame(Duration::from_secs(1)), start);
+        assert_eq!(rainbow.frame(Duration::from_secs(5)), start);
+        let mut fast = Generator::new(Pattern::Rainbow, 6).speed(5.0);
+        assert_eq!(fast.frame(Duration::from_secs(1)), start);
+    }
+
+    #[test]
+    fn test_chase() {
+        let mut chase = Generator::new(Pattern::Chase, 6).palette(Palette::solid([255; 3]));
+        let lit = |frame: &[u8]| (0..6).filter(|&i| frame[i * 3] > 0).collect::<Vec<_>>();
+
+        assert_eq!(lit(&chase.frame(Duration::ZERO)), vec![0, 3]);
+        assert_eq!(lit(&chase.frame(Duration::from_millis(100))), vec![2, 5]);
+    }
+
+    #[test]
+    fn test_fire_is_hot_at_the_start() {
+        let mut fire = Generator::new(Pattern::Fire, 60).seed(7);
+        let frame = fire.frame(Duration::from_secs(2));
+
+        let brightness =
+            |range: std::ops::Range<usize>| -> u32 { range.map(|i| frame[i * 3] as u32).sum() };
+        assert!(brightness(0..10) > brightness(50..60));
+    }
+
+    #[test]
+    fn test_seeded_patterns_repeat() {
+        for pattern in [Pattern::Twinkle, Pattern::Fire, Pattern::Noise] {
+            let t = Duration::from_millis(1500);
+            let a = Generator::new(pattern, 30).seed(42).frame(t);
+            let b = Generator::new(pattern, 30).seed(42).frame(t);
+            let c = Generator::new(pattern, 30).seed(43).frame(t);
+            assert_eq!(a, b);
+            assert_ne!(a, c, "{:?}", pattern);
+        }
+    }
+
+    #[test]
+    fn test_twinkle_fades() {
+        let mut twinkle = Generator::new(Pattern::Twinkle, 200).seed(3);
+        let lit = twinkle.frame(Duration::from_secs(1));
+        let lit_pixels = (0..200).filter(|&i| pixel(&lit, i) != [0; 3]).count();
+        assert!(lit_pixels > 0);
+        assert!(lit_pixels < 200);
+    }
+
+    #[test]
+    fn test_breathing() {
+        let mut breathing = Generator::new(Pattern::Breathing, 3).palette(Palette::solid([200; 3]));
+        assert_eq!(breathing.frame(Duration::ZERO), [0; 9]);
+        assert_eq!(breathing.frame(Duration::from_secs(2)), [200; 9]);
+    }
+
+    #[test]
+    fn test_noise_range_and_continuity() {
+        for i in 0..100 {
+            let v = noise(1, i as f32 * 0.37, i as f32 * 0.11);
+            assert!((0.0..=1.0).contains(&v));
+        }
+        assert!((noise(1, 2.0, 3.0) - noise(1, 2.001, 3.0)).abs() < 0.01);
+    }
+
+    #[test]
+    fn test_pattern_serde() {
+        assert_eq!(serde_json::to_string(&Pattern::Fire).unwrap(), r#""fire""#);
+        let pattern: Pattern = serde_json::from_str(r#""breathing""#).unwrap();
+        assert_eq!(pattern, Pattern::Breathing);
+    }
+}
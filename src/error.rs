@@ -34,10 +34,56 @@ pub enum DDPError {
     #[error("Invalid packet")]
     InvalidPacket,
 
+    /// A header built with [`HeaderBuilder`](crate::protocol::HeaderBuilder) is invalid
+    #[error("invalid header: {0}")]
+    InvalidHeader(String),
+
+    /// A packet doesn't fit in the buffer it is being encoded into
+    #[error("buffer too small, {needed} bytes needed but {available} available")]
+    BufferTooSmall {
+        /// Size of the encoded packet
+        needed: usize,
+        /// Size of the buffer
+        available: usize,
+    },
+
+    /// A [`ConnectionBuilder`](crate::connection::ConnectionBuilder) option is out of range
+    #[error("invalid connection option: {0}")]
+    InvalidOption(String),
+
     /// No packets are currently available to receive (non-blocking operation)
     #[error("There are no packets waiting to be read. This error should be handled explicitly")]
     NothingToReceive,
 
+    /// A query was not answered in time
+    #[error("no reply received")]
+    NoReply,
+
+    /// A CONTROL message has a value out of range
+    #[error("invalid control message: {0}")]
+    InvalidControl(String),
+
+    /// A display's config didn't read back as written
+    #[error("config was not applied: {0}")]
+    ConfigNotApplied(crate::protocol::message::ConfigDiff),
+
+    /// A capture or recording file could not be read or written
+    #[error("invalid capture file: {0}")]
+    InvalidCapture(String),
+
+    /// A show refers to something it doesn't define
+    #[error("invalid show: {0}")]
+    InvalidShow(String),
+
+    /// A universe of a bridge or output config is out of range for its protocol
+    #[error("invalid bridge config: {0}")]
+    InvalidBridge(String),
+
+    /// An image could not be opened or decoded
+    #[cfg(feature = "image")]
+    #[error("image error: {0}")]
+    Image(#[from] image::ImageError),
+
     /// Error from the internal packet receiver channel
     #[error("Error receiving packet: {0}")]
     CrossBeamError(#[from] crossbeam::channel::TryRecvError),
@@ -90,6 +136,36 @@ mod tests {
         assert_eq!(error.to_string(), "Invalid packet");
     }
 
+    #[test]
+    fn test_error_display_invalid_header() {
+        let error = DDPError::InvalidHeader("sequence number 16 is above 15".to_string());
+        assert_eq!(
+            error.to_string(),
+            "invalid header: sequence number 16 is above 15"
+        );
+    }
+
+    #[test]
+    fn test_error_display_buffer_too_small() {
+        let error = DDPError::BufferTooSmall {
+            needed: 20,
+            available: 10,
+        };
+        assert_eq!(
+            error.to_string(),
+            "buffer too small, 20 bytes needed but 10 available"
+        );
+    }
+
+    #[test]
+    fn test_error_display_invalid_option() {
+        let error = DDPError::InvalidOption("DSCP 64 is above 63".to_string());
+        assert_eq!(
+            error.to_string(),
+            "invalid connection option: DSCP 64 is above 63"
+        );
+    }
+
     #[test]
     fn test_error_display_nothing_to_receive() {
         let error = DDPError::NothingToReceive;
@@ -99,6 +175,69 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_error_display_no_reply() {
+        let error = DDPError::NoReply;
+        assert_eq!(error.to_string(), "no reply received");
+    }
+
+    #[test]
+    fn test_error_display_invalid_control() {
+        let error = DDPError::InvalidControl("speed 0 is outside 1-100".to_string());
+        assert_eq!(
+            error.to_string(),
+            "invalid control message: speed 0 is outside 1-100"
+        );
+    }
+
+    #[test]
+    fn test_error_display_config_not_applied() {
+        use crate::protocol::message::{ConfigChange, ConfigDiff};
+
+        let error = DDPError::ConfigNotApplied(ConfigDiff {
+            changes: vec![ConfigChange {
+                field: "gw".to_string(),
+                old: Some("10.0.0.1".to_string()),
+                new: None,
+            }],
+        });
+        assert_eq!(
+            error.to_string(),
+            "config was not applied: gw: 10.0.0.1 -> -"
+        );
+    }
+
+    #[test]
+    fn test_error_display_invalid_capture() {
+        let error = DDPError::InvalidCapture("bad magic".to_string());
+        assert_eq!(error.to_string(), "invalid capture file: bad magic");
+    }
+
+    #[test]
+    fn test_error_display_invalid_bridge() {
+        let error = DDPError::InvalidBridge("sACN universe 0 is outside 1-63999".to_string());
+        assert_eq!(
+            error.to_string(),
+            "invalid bridge config: sACN universe 0 is outside 1-63999"
+        );
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_error_display_image() {
+        let error = DDPError::Image(image::ImageError::IoError(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "nyan.gif",
+        )));
+        assert_eq!(error.to_string(), "image error: nyan.gif");
+    }
+
+    #[test]
+    fn test_error_display_invalid_show() {
+        let error = DDPError::InvalidShow("no scene".to_string());
+        assert_eq!(error.to_string(), "invalid show: no scene");
+    }
+
     #[test]
     fn test_error_display_crossbeam_error() {
         use crossbeam::channel::TryRecvError;
@@ -156,7 +295,7 @@ mod tests {
         let data = vec![0xDE, 0xAD, 0xBE, 0xEF];
 
         let error = DDPError::UnknownClient {
-            from: addr.clone(),
+            from: addr,
             data: data.clone(),
         };
 
@@ -0,0 +1,540 @@
+//! 2D matrix layouts and pixel mapping.
+//!
+//! LED matrices are almost never wired in plain reading order. This module maps
+//! logical `(x, y)` coordinates onto the strip index the pixel is actually wired to,
+//! and provides [`MatrixFrame`] for drawing into the linear byte buffer that
+//! [`DDPConnection::write`](crate::connection::DDPConnection::write) sends.
+//!
+//! # Examples
+//!
+//! ```
+//! use ddp_rs::layout::{Layout, MatrixFrame, MatrixLayout, Order};
+//! use ddp_rs::protocol::PixelConfig;
+//!
+//! // 8x8 panel wired in rows, every other row running backwards
+//! let layout = MatrixLayout::new(8, 8).order(Order::RowMajor).serpentine(true);
+//! assert_eq!(layout.index(0, 1), Some(15));
+//!
+//! let mut frame = MatrixFrame::new(layout, PixelConfig::default());
+//! frame.set(0, 1, &[255, 0, 0]);
+//! assert_eq!(&frame.as_bytes()[45..48], &[255, 0, 0]);
+//! ```
+
+use crate::protocol::PixelConfig;
+
+/// Maps 2D coordinates onto indices in a linear pixel strip.
+pub trait Layout {
+    /// Logical width in pixels.
+    fn width(&self) -> usize;
+
+    /// Logical height in pixels.
+    fn height(&self) -> usize;
+
+    /// Returns the strip index for the pixel at `(x, y)`, or `None` if out of bounds.
+    fn index(&self, x: usize, y: usize) -> Option<usize>;
+
+    /// Total number of pixels covered by the layout.
+    fn len(&self) -> usize {
+        self.width() * self.height()
+    }
+
+    /// Returns `true` if the layout has no pixels.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Direction in which consecutive pixels are wired.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum Order {
+    /// Pixels run along rows, rows stacked top to bottom
+    #[default]
+    RowMajor,
+    /// Pixels run along columns, columns stacked left to right
+    ColumnMajor,
+}
+
+/// Clockwise rotation of a panel as it is mounted.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub enum Rotation {
+    /// Mounted as wired
+    #[default]
+    None,
+    /// Rotated 90 degrees clockwise
+    Cw90,
+    /// Rotated 180 degrees
+    Cw180,
+    /// Rotated 270 degrees clockwise
+    Cw270,
+}
+
+/// Index of `(x, y)` within a `width` x `height` grid wired in the given order.
+fn wire(order: Order, serpentine: bool, width: usize, height: usize, x: usize, y: usize) -> usize {
+    match order {
+        Order::RowMajor => {
+            let x = if serpentine && y % 2 == 1 {
+                width - 1 - x
+            } else {
+                x
+            };
+            y * width + x
+        }
+        Order::ColumnMajor => {
+            let y = if serpentine && x % 2 == 1 {
+                height - 1 - y
+            } else {
+                y
+            };
+            x * height + y
+        }
+    }
+}
+
+/// A single rectangular panel.
+///
+/// `width` and `height` describe the panel as it is wired, starting at the first
+/// pixel in the top left corner. [`Rotation`] and the flip options describe how the
+/// panel is mounted; [`Layout::width`] and [`Layout::height`] report the mounted
+/// dimensions, so a 90 degree rotation swaps them.
+///
+/// # Examples
+///
+/// ```
+/// use ddp_rs::layout::{Layout, MatrixLayout, Order, Rotation};
+///
+/// let layout = MatrixLayout::new(16, 8)
+///     .order(Order::ColumnMajor)
+///     .serpentine(true)
+///     .rotation(Rotation::Cw90);
+///
+/// assert_eq!(layout.width(), 8);
+/// assert_eq!(layout.height(), 16);
+/// ```
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct MatrixLayout {
+    width: usize,
+    height: usize,
+    order: Order,
+    serpentine: bool,
+    rotation: Rotation,
+    flip_x: bool,
+    flip_y: bool,
+}
+
+impl MatrixLayout {
+    /// Creates a row-major, non-serpentine layout of `width` x `height` pixels.
+    pub fn new(width: usize, height: usize) -> Self {
+        MatrixLayout {
+            width,
+            height,
+            order: Order::RowMajor,
+            serpentine: false,
+            rotation: Rotation::None,
+            flip_x: false,
+            flip_y: false,
+        }
+    }
+
+    /// Sets the wiring direction.
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Sets whether every other row (or column) runs in the opposite direction.
+    pub fn serpentine(mut self, serpentine: bool) -> Self {
+        self.serpentine = serpentine;
+        self
+    }
+
+    /// Sets the mounting rotation.
+    pub fn rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Mirrors the panel horizontally.
+    pub fn flip_x(mut self, flip: bool) -> Self {
+        self.flip_x = flip;
+        self
+    }
+
+    /// Mirrors the panel vertically.
+    pub fn flip_y(mut self, flip: bool) -> Self {
+        self.flip_y = flip;
+        self
+    }
+}
+
+impl Layout for MatrixLayout {
+    fn width(&self) -> usize {
+        match self.rotation {
+            Rotation::None | Rotation::Cw180 => self.width,
+            Rotation::Cw90 | Rotation::Cw270 => self.height,
+        }
+    }
+
+    fn height(&self) -> usize {
+        match self.rotation {
+            Rotation::None | Rotation::Cw180 => self.height,
+            Rotation::Cw90 | Rotation::Cw270 => self.width,
+        }
+    }
+
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        let (w, h) = (self.width(), self.height());
+        if x >= w || y >= h {
+            return None;
+        }
+
+        let x = if self.flip_x { w - 1 - x } else { x };
+        let y = if self.flip_y { h - 1 - y } else { y };
+
+        // Undo the mounting rotation to get back to wired coordinates
+        let (px, py) = match self.rotation {
+            Rotation::None => (x, y),
+            Rotation::Cw90 => (y, self.height - 1 - x),
+            Rotation::Cw180 => (self.width - 1 - x, self.height - 1 - y),
+            Rotation::Cw270 => (self.width - 1 - y, x),
+        };
+
+        Some(wire(
+            self.order,
+            self.serpentine,
+            self.width,
+            self.height,
+            px,
+            py,
+        ))
+    }
+}
+
+/// Identical panels tiled into a larger wall.
+///
+/// Panels are chained one after another on the same strip; `order` and
+/// `serpentine` describe how the chain runs across the wall, just like pixels
+/// within a [`MatrixLayout`].
+///
+/// # Examples
+///
+/// ```
+/// use ddp_rs::layout::{Layout, MatrixLayout, Order, TiledLayout};
+///
+/// // Two rows of three 8x8 panels
+/// let wall = TiledLayout::new(MatrixLayout::new(8, 8), 3, 2).serpentine(true);
+///
+/// assert_eq!(wall.width(), 24);
+/// assert_eq!(wall.height(), 16);
+/// // First pixel of the second panel
+/// assert_eq!(wall.index(8, 0), Some(64));
+/// ```
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct TiledLayout {
+    panel: MatrixLayout,
+    tiles_x: usize,
+    tiles_y: usize,
+    order: Order,
+    serpentine: bool,
+}
+
+impl TiledLayout {
+    /// Creates a wall of `tiles_x` x `tiles_y` panels chained in row-major order.
+    pub fn new(panel: MatrixLayout, tiles_x: usize, tiles_y: usize) -> Self {
+        TiledLayout {
+            panel,
+            tiles_x,
+            tiles_y,
+            order: Order::RowMajor,
+            serpentine: false,
+        }
+    }
+
+    /// Sets the direction in which panels are chained.
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Sets whether every other row (or column) of panels is chained in reverse.
+    pub fn serpentine(mut self, serpentine: bool) -> Self {
+        self.serpentine = serpentine;
+        self
+    }
+}
+
+impl Layout for TiledLayout {
+    fn width(&self) -> usize {
+        self.panel.width() * self.tiles_x
+    }
+
+    fn height(&self) -> usize {
+        self.panel.height() * self.tiles_y
+    }
+
+    fn index(&self, x: usize, y: usize) -> Option<usize> {
+        if x >= self.width() || y >= self.height() {
+            return None;
+        }
+
+        let (pw, ph) = (self.panel.width(), self.panel.height());
+        let tile = wire(
+            self.order,
+            self.serpentine,
+            self.tiles_x,
+            self.tiles_y,
+            x / pw,
+            y / ph,
+        );
+
+        self.panel
+            .index(x % pw, y % ph)
+            .map(|i| tile * self.panel.len() + i)
+    }
+}
+
+/// A frame buffer addressed in 2D and stored in strip order.
+///
+/// The buffer holds one byte per channel for every pixel of the layout, with the
+/// channel count taken from the [`PixelConfig`] data type (3 for RGB, 4 for RGBW...).
+///
+/// # Examples
+///
+/// ```no_run
+/// use ddp_rs::connection::DDPConnection;
+/// use ddp_rs::layout::{MatrixFrame, MatrixLayout};
+/// use ddp_rs::protocol::{PixelConfig, ID};
+/// use std::net::UdpSocket;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut conn = DDPConnection::try_new(
+///     "192.168.1.40:4048",
+///     PixelConfig::default(),
+///     ID::Default,
+///     UdpSocket::bind("0.0.0.0:4048")?
+/// )?;
+///
+/// let mut frame = MatrixFrame::new(MatrixLayout::new(16, 16).serpentine(true), conn.pixel_config);
+/// frame.fill(&[0, 0, 32]);
+/// frame.set(3, 4, &[255, 255, 255]);
+///
+/// conn.write(frame.as_bytes())?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct MatrixFrame<L: Layout> {
+    layout: L,
+    channels: usize,
+    data: Vec<u8>,
+}
+
+impl<L: Layout> MatrixFrame<L> {
+    /// Creates a black frame sized for `layout` in the given pixel format.
+    pub fn new(layout: L, pixel_config: PixelConfig) -> Self {
+        let channels = pixel_config.data_type.channels();
+        let data = vec![0u8; layout.len() * channels];
+
+        MatrixFrame {
+            layout,
+            channels,
+            data,
+        }
+    }
+
+    /// The layout this frame is mapped through.
+    pub fn layout(&self) -> &L {
+        &self.layout
+    }
+
+    /// Number of bytes per pixel.
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Sets the pixel at `(x, y)`.
+    ///
+    /// Coordinates outside the layout are ignored. Only the first
+    /// [`channels`](Self::channels) bytes of `color` are used.
+    pub fn set(&mut self, x: usize, y: usize, color: &[u8]) {
+        if let Some(i) = self.layout.index(x, y) {
+            let start = i * self.channels;
+            let n = color.len().min(self.channels);
+            self.data[start..start + n].copy_from_slice(&color[..n]);
+        }
+    }
+
+    /// Returns the pixel at `(x, y)`, or `None` if it is outside the layout.
+    pub fn get(&self, x: usize, y: usize) -> Option<&[u8]> {
+        self.layout.index(x, y).map(|i| {
+            let start = i * self.channels;
+            &self.data[start..start + self.channels]
+        })
+    }
+
+    /// Sets every pixel to `color`.
+    pub fn fill(&mut self, color: &[u8]) {
+        let n = color.len().min(self.channels);
+        for pixel in self.data.chunks_exact_mut(self.channels) {
+            pixel[..n].copy_from_slice(&color[..n]);
+        }
+    }
+
+    /// Sets every pixel to zero.
+    pub fn clear(&mut self) {
+        self.data.fill(0);
+    }
+
+    /// The frame in strip order, ready to pass to
+    /// [`DDPConnection::write`](crate::connection::DDPConnection::write).
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Mutable access to the raw strip-ordered buffer.
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{DataType, PixelFormat};
+
+    fn indices<L: Layout>(layout: &L) -> Vec<Vec<usize>> {
+        (0..layout.height())
+            .map(|y| {
+                (0..layout.width())
+                    .map(|x| layout.index(x, y).unwrap())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_row_major() {
+        let layout = MatrixLayout::new(3, 2);
+        assert_eq!(indices(&layout), vec![vec![0, 1, 2], vec![3, 4, 5]]);
+    }
+
+    #[test]
+    fn test_row_major_serpentine() {
+        let layout = MatrixLayout::new(3, 3).serpentine(true);
+        assert_eq!(
+            indices(&layout),
+            vec![vec![0, 1, 2], vec![5, 4, 3], vec![6, 7, 8]]
+        );
+    }
+
+    #[test]
+    fn test_column_major_serpentine() {
+        let layout = MatrixLayout::new(3, 2)
+            .order(Order::ColumnMajor)
+            .serpentine(true);
+        assert_eq!(indices(&layout), vec![vec![0, 3, 4], vec![1, 2, 5]]);
+    }
+
+    #[test]
+    fn test_flips() {
+        let layout = MatrixLayout::new(3, 2).flip_x(true);
+        assert_eq!(indices(&layout), vec![vec![2, 1, 0], vec![5, 4, 3]]);
+
+        let layout = MatrixLayout::new(3, 2).flip_y(true);
+        assert_eq!(indices(&layout), vec![vec![3, 4, 5], vec![0, 1, 2]]);
+    }
+
+    #[test]
+    fn test_rotations() {
+        // Wired as:
+        // 0 1 2
+        // 3 4 5
+        let layout = MatrixLayout::new(3, 2).rotation(Rotation::Cw90);
+        assert_eq!((layout.width(), layout.height()), (2, 3));
+        assert_eq!(indices(&layout), vec![vec![3, 0], vec![4, 1], vec![5, 2]]);
+
+        let layout = MatrixLayout::new(3, 2).rotation(Rotation::Cw180);
+        assert_eq!(indices(&layout), vec![vec![5, 4, 3], vec![2, 1, 0]]);
+
+        let layout = MatrixLayout::new(3, 2).rotation(Rotation::Cw270);
+        assert_eq!(indices(&layout), vec![vec![2, 5], vec![1, 4], vec![0, 3]]);
+    }
+
+    #[test]
+    fn test_out_of_bounds() {
+        let layout = MatrixLayout::new(4, 4);
+        assert_eq!(layout.index(4, 0), None);
+        assert_eq!(layout.index(0, 4), None);
+
+        let wall = TiledLayout::new(layout, 2, 2);
+        assert_eq!(wall.index(8, 0), None);
+    }
+
+    #[test]
+    fn test_tiled() {
+        let panel = MatrixLayout::new(2, 2);
+        let wall = TiledLayout::new(panel, 2, 2).serpentine(true);
+
+        assert_eq!(wall.len(), 16);
+        assert_eq!(
+            indices(&wall),
+            vec![
+                vec![0, 1, 4, 5],
+                vec![2, 3, 6, 7],
+                vec![12, 13, 8, 9],
+                vec![14, 15, 10, 11],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_layout_is_bijective() {
+        let layout = TiledLayout::new(
+            MatrixLayout::new(5, 3)
+                .serpentine(true)
+                .rotation(Rotation::Cw90)
+                .flip_x(true),
+            3,
+            2,
+        )
+        .order(Order::ColumnMajor)
+        .serpentine(true);
+
+        let mut seen: Vec<usize> = indices(&layout).into_iter().flatten().collect();
+        seen.sort_unstable();
+        assert_eq!(seen, (0..layout.len()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_frame_set_get() {
+        let mut frame = MatrixFrame::new(
+            MatrixLayout::new(2, 2).serpentine(true),
+            PixelConfig::default(),
+        );
+        assert_eq!(frame.as_bytes().len(), 12);
+
+        frame.set(0, 1, &[1, 2, 3]);
+        assert_eq!(frame.get(0, 1), Some(&[1, 2, 3][..]));
+        assert_eq!(&frame.as_bytes()[9..12], &[1, 2, 3]);
+
+        // Out of bounds writes are ignored
+        frame.set(5, 5, &[9, 9, 9]);
+        assert_eq!(frame.get(5, 5), None);
+    }
+
+    #[test]
+    fn test_frame_rgbw_fill() {
+        let config = PixelConfig {
+            data_type: DataType::RGBW,
+            data_size: PixelFormat::Pixel32Bits,
+            customer_defined: false,
+        };
+        let mut frame = MatrixFrame::new(MatrixLayout::new(3, 1), config);
+        assert_eq!(frame.channels(), 4);
+
+        frame.fill(&[1, 2, 3, 4]);
+        assert_eq!(frame.as_bytes(), &[1, 2, 3, 4, 1, 2, 3, 4, 1, 2, 3, 4]);
+
+        frame.clear();
+        assert!(frame.as_bytes().iter().all(|&b| b == 0));
+    }
+}
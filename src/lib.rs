@@ -35,15 +35,43 @@
 //! ## Modules
 //!
 //! - [`connection`] - Main connection type for sending pixel data
+//! - [`bridge`] - Bridging between DDP and Art-Net, sACN (E1.31) and OPC
 //! - [`protocol`] - DDP protocol types and structures
 //! - [`packet`] - Packet parsing for receiving data from displays
+//! - [`pcap`] - Reading and writing DDP traffic in pcap/pcapng captures
+//! - [`power`] - Current estimation and limiting of pixel frames
 //! - [`error`] - Error types used throughout the crate
+//! - [`discovery`] - Finding displays on the local network
+//! - [`correction`] - Gamma, brightness and white point correction of outgoing pixels
+//! - [`layout`] - 2D matrix layouts and frames for serpentine and tiled panels
+//! - [`effects`] - Built-in pattern generators and palettes
+//! - [`compositor`] - Layering and blending frames, with crossfades
+//! - [`show`] - Scenes, cue lists and a scheduler for running shows
+//! - `testing` - A virtual display for integration tests (requires the `testing` feature)
+//! - [`receiver`] - Frame assembly for receiving pixel data over DDP
+//! - [`record`] - Recording DDP streams and replaying them with original timing
+//! - [`transport`] - UDP, TCP and in-memory transports for connections and receivers
+//! - `animation` - GIF and image sequence playback (requires the `image` feature)
 //!
 //! 
+#[cfg(feature = "image")]
+pub mod animation;
+pub mod bridge;
+pub mod compositor;
 pub mod connection;
+pub mod correction;
+pub mod discovery;
+pub mod effects;
 pub mod error;
+pub mod layout;
 pub mod packet;
+pub mod pcap;
+pub mod power;
 pub mod protocol;
+pub mod receiver;
+pub mod record;
+pub mod show;
+pub mod transport;
 
-#[cfg(test)]
-mod testing;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
@@ -1,9 +1,12 @@
 //! Packet parsing for receiving data from DDP displays.
 //!
 //! This module provides the [`Packet`] type for parsing incoming DDP packets,
-//! typically used when receiving responses from displays.
+//! typically used when receiving responses from displays, and [`PacketBuilder`]
+//! for crafting arbitrary packets.
 
-use crate::protocol::{message::Message, Header};
+use crate::error::DDPError;
+use crate::protocol::{message::Message, Header, HeaderBuilder};
+use std::borrow::Cow;
 
 /// A parsed DDP packet received from a display.
 ///
@@ -47,6 +50,71 @@ impl Packet {
         }
     }
 
+    /// The payload that goes on the wire: the serialized [`parsed`](Self::parsed)
+    /// message if there is one, otherwise the data.
+    ///
+    /// A received packet whose message hasn't been changed keeps the data exactly
+    /// as it arrived, so re-encoding it doesn't reformat its JSON. Once the message
+    /// is edited, the edit is what gets sent.
+    pub fn payload(&self) -> Result<Cow<'_, [u8]>, DDPError> {
+        match &self.parsed {
+            Some(message) if parse_message(&self.header, &self.data).as_ref() != Some(message) => {
+                let bytes: Vec<u8> = message.clone().try_into()?;
+                Ok(Cow::Owned(bytes))
+            }
+            _ => Ok(Cow::Borrowed(&self.data)),
+        }
+    }
+
+    /// Serializes the packet into `buf` and returns how many bytes were written.
+    ///
+    /// The header is 14 bytes when the timecode flag is set and 10 otherwise, and
+    /// its length field is filled in from the [`payload`](Self::payload).
+    ///
+    /// Fails with [`DDPError::BufferTooSmall`] if `buf` can't hold the packet and
+    /// [`DDPError::InvalidHeader`] if the payload is longer than the length field
+    /// allows.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ddp_rs::packet::Packet;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let bytes = [0x41, 0x01, 0x0D, 0x01, 0, 0, 0, 0, 0, 0x03, 0xFF, 0x00, 0x00];
+    /// let packet = Packet::from_bytes(&bytes);
+    ///
+    /// let mut buf = [0u8; 1500];
+    /// let len = packet.encode_into(&mut buf)?;
+    /// assert_eq!(&buf[..len], &bytes);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn encode_into(&self, buf: &mut [u8]) -> Result<usize, DDPError> {
+        encode(self.header, &self.payload()?, buf)
+    }
+
+    /// Serializes the packet as it goes over the wire, like
+    /// [`encode_into`](Self::encode_into).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ddp_rs::packet::Packet;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let bytes = [0x41, 0x01, 0x0D, 0x01, 0, 0, 0, 0, 0, 0x03, 0xFF, 0x00, 0x00];
+    /// assert_eq!(Packet::from_bytes(&bytes).to_bytes()?, bytes);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn to_bytes(&self) -> Result<Vec<u8>, DDPError> {
+        let payload = self.payload()?;
+        let mut buf = vec![0u8; header_len(&self.header) + payload.len()];
+        encode(self.header, &payload, &mut buf)?;
+        Ok(buf)
+    }
+
     /// Parses a DDP packet from raw bytes.
     ///
     /// This method handles both 10-byte and 14-byte headers (with timecode),
@@ -102,50 +170,225 @@ impl Packet {
         let header = Header::from(header_bytes);
         let data = &bytes[header_size..];
 
-        let mut parsed: Option<Message> = None;
+        Packet {
+            header,
+            data: data.to_vec(),
+            parsed: parse_message(&header, data),
+        }
+    }
+}
 
-        if header.packet_type.reply {
-            // Try to parse the data into typed structs in the spec
-            parsed = match match header.id {
-                crate::protocol::ID::Control => match serde_json::from_slice(data) {
-                    Ok(v) => Some(Message::Control(v)),
-                    Err(_) => None,
-                },
-                crate::protocol::ID::Config => match serde_json::from_slice(data) {
-                    Ok(v) => Some(Message::Config(v)),
-                    Err(_) => None,
-                },
-                crate::protocol::ID::Status => match serde_json::from_slice(data) {
-                    Ok(v) => Some(Message::Status(v)),
-                    Err(_) => None,
-                },
-                _ => None,
-            } {
-                // Worked, return the typed struct
-                Some(v) => Some(v),
-
-                // OK, no bueno, lets try just untyped JSON
-                None => match header.id {
-                    crate::protocol::ID::Control
-                    | crate::protocol::ID::Config
-                    | crate::protocol::ID::Status => match serde_json::from_slice(data) {
-                        // JSON Value it is
-                        Ok(v) => Some(Message::Parsed((header.id, v))),
-                        // Ok we're really screwed, lets just return the raw data as a string
-                        Err(_) => match std::str::from_utf8(data) {
-                            Ok(v) => Some(Message::Unparsed((header.id, v.to_string()))),
-                            // I guess it's... just bytes?
-                            Err(_) => None,
-                        },
+/// Parses the JSON message in the data of a reply, as typed as it allows.
+fn parse_message(header: &Header, data: &[u8]) -> Option<Message> {
+    let mut parsed: Option<Message> = None;
+
+    if header.packet_type.reply {
+        // Try to parse the data into typed structs in the spec
+        parsed = match match header.id {
+            crate::protocol::ID::Control => match serde_json::from_slice(data) {
+                Ok(v) => Some(Message::Control(v)),
+                Err(_) => None,
+            },
+            crate::protocol::ID::Config => match serde_json::from_slice(data) {
+                Ok(v) => Some(Message::Config(v)),
+                Err(_) => None,
+            },
+            crate::protocol::ID::Status => match serde_json::from_slice(data) {
+                Ok(v) => Some(Message::Status(v)),
+                Err(_) => None,
+            },
+            _ => None,
+        } {
+            // Worked, return the typed struct
+            Some(v) => Some(v),
+
+            // OK, no bueno, lets try just untyped JSON
+            None => match header.id {
+                crate::protocol::ID::Control
+                | crate::protocol::ID::Config
+                | crate::protocol::ID::Status => match serde_json::from_slice(data) {
+                    // JSON Value it is
+                    Ok(v) => Some(Message::Parsed((header.id, v))),
+                    // Ok we're really screwed, lets just return the raw data as a string
+                    Err(_) => match std::str::from_utf8(data) {
+                        Ok(v) => Some(Message::Unparsed((header.id, v.to_string()))),
+                        // I guess it's... just bytes?
+                        Err(_) => None,
                     },
-                    _ => None,
                 },
-            }
+                _ => None,
+            },
         }
-        Packet {
-            header,
-            data: data.to_vec(),
-            parsed,
+    }
+    parsed
+}
+
+fn header_len(header: &Header) -> usize {
+    if header.packet_type.timecode {
+        14
+    } else {
+        10
+    }
+}
+
+/// Writes `header` and `payload` into `buf`, setting the header's length from the
+/// payload. Shared with the connection's send path, so kept allocation free.
+#[inline]
+pub(crate) fn encode(
+    mut header: Header,
+    payload: &[u8],
+    buf: &mut [u8],
+) -> Result<usize, DDPError> {
+    header.length = u16::try_from(payload.len()).map_err(|_| {
+        DDPError::InvalidHeader(format!("{} bytes of payload is too long", payload.len()))
+    })?;
+
+    let header_len = header_len(&header);
+    let needed = header_len + payload.len();
+    if buf.len() < needed {
+        return Err(DDPError::BufferTooSmall {
+            needed,
+            available: buf.len(),
+        });
+    }
+
+    if header.packet_type.timecode {
+        let bytes: [u8; 14] = header.into();
+        buf[..14].copy_from_slice(&bytes);
+    } else {
+        let bytes: [u8; 10] = header.into();
+        buf[..10].copy_from_slice(&bytes);
+    }
+    buf[header_len..needed].copy_from_slice(payload);
+
+    Ok(needed)
+}
+
+/// Builds a [`Packet`] with a validated header.
+///
+/// The header's length field is filled in from the data.
+///
+/// # Examples
+///
+/// ```
+/// use ddp_rs::packet::PacketBuilder;
+/// use ddp_rs::protocol::{HeaderBuilder, ID};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let packet = PacketBuilder::new()
+///     .header(HeaderBuilder::new().sequence_number(1).push(true))
+///     .data(&[255, 0, 0])
+///     .build()?;
+/// assert_eq!(
+///     packet.to_bytes()?,
+///     [0x41, 0x01, 0x0D, 0x01, 0, 0, 0, 0, 0, 0x03, 0xFF, 0x00, 0x00]
+/// );
+///
+/// let query = PacketBuilder::new()
+///     .header(HeaderBuilder::new().id(ID::Status).query(true))
+///     .build()?;
+/// assert_eq!(query.to_bytes()?.len(), 10);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct PacketBuilder {
+    header: HeaderBuilder,
+    data: Vec<u8>,
+}
+
+impl PacketBuilder {
+    /// Starts a packet with a default header and no data.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the header. Its length is replaced by the length of the data.
+    pub fn header(mut self, header: HeaderBuilder) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Sets the payload, pixel data or a JSON message.
+    pub fn data(mut self, data: &[u8]) -> Self {
+        self.data = data.to_vec();
+        self
+    }
+
+    /// Validates the header and returns the packet.
+    ///
+    /// Fails with [`DDPError::InvalidHeader`] if the header is invalid or the data
+    /// is longer than the 16 bit length field allows.
+    pub fn build(self) -> Result<Packet, DDPError> {
+        let length = u16::try_from(self.data.len()).map_err(|_| {
+            DDPError::InvalidHeader(format!("{} bytes of data is too long", self.data.len()))
+        })?;
+        let header = self.header.length(length).build()?;
+
+        Ok(Packet::from_data(header, &self.data))
+    }
+}
+
+/// Bytes shown by the one line form of [`Packet`]'s `Display`.
+const SUMMARY_BYTES: usize = 16;
+
+/// Bytes shown by the hex dump in the alternate form of [`Packet`]'s `Display`.
+const DUMP_BYTES: usize = 256;
+
+impl std::fmt::Display for Packet {
+    /// Renders the header and a summary of the payload.
+    ///
+    /// The one line form shows the first few payload bytes in hex, or the message as
+    /// compact JSON. The alternate form (`{:#}`) shows one header field per line
+    /// followed by pretty printed JSON or a hex dump.
+    ///
+    /// ```
+    /// use ddp_rs::packet::Packet;
+    ///
+    /// let packet = Packet::from_bytes(&[0x41, 0x01, 0x0D, 0x01, 0, 0, 0, 0, 0, 0x03, 0xFF, 0, 0]);
+    /// assert_eq!(
+    ///     packet.to_string(),
+    ///     "v1 ----P seq=1 RGB 24 bits id=default (1) offset=0 len=3 | ff 00 00"
+    /// );
+    /// ```
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            writeln!(f, "{:#}", self.header)?;
+            return match &self.parsed {
+                Some(message) => write!(f, "payload:\n{:#}", message),
+                None if self.data.is_empty() => write!(f, "payload:   none"),
+                None => {
+                    write!(f, "payload:   {} bytes", self.data.len())?;
+                    for (i, line) in self.data[..self.data.len().min(DUMP_BYTES)]
+                        .chunks(16)
+                        .enumerate()
+                    {
+                        write!(f, "\n  {:04x} ", i * 16)?;
+                        for b in line {
+                            write!(f, " {:02x}", b)?;
+                        }
+                    }
+                    if self.data.len() > DUMP_BYTES {
+                        write!(f, "\n  ... {} more bytes", self.data.len() - DUMP_BYTES)?;
+                    }
+                    Ok(())
+                }
+            };
+        }
+
+        write!(f, "{} |", self.header)?;
+        match &self.parsed {
+            Some(message) => write!(f, " {}", message),
+            None if self.data.is_empty() => write!(f, " no data"),
+            None => {
+                for b in &self.data[..self.data.len().min(SUMMARY_BYTES)] {
+                    write!(f, " {:02x}", b)?;
+                }
+                if self.data.len() > SUMMARY_BYTES {
+                    write!(f, " ... ({} bytes)", self.data.len())?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -153,6 +396,8 @@ impl Packet {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::protocol::{PixelConfig, ID};
+    use serde_json::json;
 
     #[test]
     fn test_json() {
@@ -562,4 +807,186 @@ mod tests {
         assert_eq!(parsed.data.len(), data.len());
         assert_eq!(parsed.data, data);
     }
+
+    #[test]
+    fn test_display_summary() {
+        let mut bytes = vec![0x41, 0x02, 0x0D, 0x01, 0, 0, 0, 0, 0, 20];
+        bytes.extend(0..20u8);
+        let packet = Packet::from_bytes(&bytes);
+
+        assert_eq!(
+            packet.to_string(),
+            "v1 ----P seq=2 RGB 24 bits id=default (1) offset=0 len=20 | \
+             00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f ... (20 bytes)"
+        );
+
+        let dump = format!("{:#}", packet);
+        assert!(dump.ends_with(
+            "payload:   20 bytes\n  \
+             0000  00 01 02 03 04 05 06 07 08 09 0a 0b 0c 0d 0e 0f\n  \
+             0010  10 11 12 13"
+        ));
+    }
+
+    #[test]
+    fn test_display_message() {
+        let body = br#"{"status":{"man":"x"}}"#;
+        let mut bytes = vec![0x44, 0x00, 0x0D, 0xFB, 0, 0, 0, 0, 0, body.len() as u8];
+        bytes.extend_from_slice(body);
+        let packet = Packet::from_bytes(&bytes);
+
+        assert!(packet.to_string().ends_with(r#"| {"status":{"man":"x"}}"#));
+        assert!(format!("{:#}", packet).contains("payload:\n{\n  \"status\": {"));
+
+        // Query for status, no payload
+        let query = Packet::from_bytes(&[0x42, 0x00, 0x0D, 0xFB, 0, 0, 0, 0, 0, 0]);
+        assert!(query.to_string().ends_with("id=status (251) offset=0 len=0 | no data"));
+    }
+
+    #[test]
+    fn test_packet_builder() {
+        let packet = PacketBuilder::new()
+            .header(HeaderBuilder::new().offset(6).length(1000).timecode(7))
+            .data(&[1, 2, 3])
+            .build()
+            .unwrap();
+
+        assert_eq!(packet.header.length, 3);
+        let bytes = packet.to_bytes().unwrap();
+        assert_eq!(bytes.len(), 17);
+        assert_eq!(Packet::from_bytes(&bytes), packet);
+    }
+
+    #[test]
+    fn test_packet_builder_validation() {
+        let too_long = PacketBuilder::new().data(&vec![0; 70_000]).build();
+        assert!(matches!(too_long, Err(DDPError::InvalidHeader(_))));
+
+        let bad_header = PacketBuilder::new()
+            .header(HeaderBuilder::new().sequence_number(20))
+            .build();
+        assert!(matches!(bad_header, Err(DDPError::InvalidHeader(_))));
+    }
+
+    #[test]
+    fn test_encode_into() {
+        let packet = Packet::from_bytes(&[0x41, 0x01, 0x0D, 0x01, 0, 0, 0, 0, 0, 3, 1, 2, 3]);
+
+        let mut small = [0u8; 12];
+        assert!(matches!(
+            packet.encode_into(&mut small),
+            Err(DDPError::BufferTooSmall {
+                needed: 13,
+                available: 12
+            })
+        ));
+
+        // The length field follows the data, not the header
+        let mut packet = packet;
+        packet.data.push(4);
+        let bytes = packet.to_bytes().unwrap();
+        assert_eq!(&bytes[8..10], &[0, 4]);
+    }
+
+    #[test]
+    fn test_encode_parsed_message() {
+        let header = HeaderBuilder::new()
+            .id(ID::Control)
+            .reply(true)
+            .build()
+            .unwrap();
+        let packet = Packet {
+            header,
+            data: Vec::new(),
+            parsed: Some(Message::Parsed((ID::Control, json!({"control": {"fx": "fire"}})))),
+        };
+
+        let bytes = packet.to_bytes().unwrap();
+        assert_eq!(&bytes[10..], br#"{"control":{"fx":"fire"}}"#);
+
+        let decoded = Packet::from_bytes(&bytes);
+        assert_eq!(decoded.header.length as usize, bytes.len() - 10);
+        assert!(matches!(decoded.parsed, Some(Message::Control(_))));
+    }
+
+    #[test]
+    fn test_encode_edited_message() {
+        // Spaces the serializer wouldn't write, to tell the original bytes apart
+        let body = br#"{"status": {"man": "x"}}"#;
+        let mut bytes = vec![0x44, 0x00, 0x0D, 0xFB, 0, 0, 0, 0, 0, body.len() as u8];
+        bytes.extend_from_slice(body);
+
+        // Unedited, the data goes back out exactly as it came in
+        let mut packet = Packet::from_bytes(&bytes);
+        assert_eq!(packet.to_bytes().unwrap(), bytes);
+
+        match &mut packet.parsed {
+            Some(Message::Status(s)) => s.status.man = Some("y".to_string()),
+            other => panic!("unexpected {:?}", other),
+        }
+        let edited = packet.to_bytes().unwrap();
+        assert_eq!(&edited[10..], br#"{"status":{"man":"y"}}"#);
+        assert_eq!(Packet::from_bytes(&edited).parsed, packet.parsed);
+    }
+
+    proptest! {
+        #[test]
+        fn test_encode_decode_roundtrip(
+            version in 1u8..=3,
+            flags in prop::array::uniform4(any::<bool>()),
+            sequence_number in 0u8..=15,
+            pixel_config in any::<u8>(),
+            id in any::<u8>(),
+            offset in 0u32..u32::MAX / 2,
+            timecode in prop::option::of(any::<u32>()),
+            data in prop::collection::vec(any::<u8>(), 0..1500),
+        ) {
+            let [storage, reply, query, push] = flags;
+            let mut header = HeaderBuilder::new()
+                .version(version)
+                .storage(storage)
+                .reply(reply && !query)
+                .query(query)
+                .push(push)
+                .sequence_number(sequence_number)
+                .pixel_config(PixelConfig::from(pixel_config))
+                .id(ID::from(id))
+                .offset(offset);
+            if let Some(timecode) = timecode {
+                header = header.timecode(timecode);
+            }
+            let packet = PacketBuilder::new().header(header).data(&data).build().unwrap();
+
+            let mut buf = [0u8; 1514];
+            let len = packet.encode_into(&mut buf).unwrap();
+            prop_assert_eq!(len, if timecode.is_some() { 14 } else { 10 } + data.len());
+            prop_assert_eq!(&buf[..len], &packet.to_bytes().unwrap()[..]);
+
+            let decoded = Packet::from_bytes(&buf[..len]);
+            prop_assert_eq!(decoded.header, packet.header);
+            prop_assert_eq!(decoded.data, packet.data);
+        }
+
+        #[test]
+        fn test_decode_encode_roundtrip(
+            first in any::<u8>(),
+            data_type in 0u8..=4,
+            data_size in 0u8..=6,
+            customer_defined in any::<bool>(),
+            rest in prop::array::uniform12(any::<u8>()),
+            data in prop::collection::vec(any::<u8>(), 0..1500),
+        ) {
+            // Any version and flags, leaving out the unassigned bit
+            let first = first & !0x20;
+            let pixel_config = (customer_defined as u8) << 7 | data_type << 3 | data_size;
+
+            let mut bytes = vec![first, rest[0], pixel_config];
+            bytes.extend_from_slice(&rest[1..]);
+            bytes.truncate(if first & 0x10 != 0 { 14 } else { 10 });
+            bytes[8..10].copy_from_slice(&(data.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(&data);
+
+            prop_assert_eq!(Packet::from_bytes(&bytes).to_bytes().unwrap(), bytes);
+        }
+    }
 }
@@ -0,0 +1,1016 @@
+//! Reading and writing DDP traffic in pcap and pcapng capture files.
+//!
+//! [`PcapReader`] pulls DDP packets out of captures made with tcpdump or
+//! Wireshark: frames are decapsulated (Ethernet with VLAN tags, Linux cooked
+//! captures, BSD loopback or raw IP, then IPv4 or IPv6 and UDP) and every datagram
+//! to or from the DDP port is parsed into a timestamped [`Packet`].
+//! [`PcapWriter`] goes the other way and writes packets out as a classic pcap file
+//! with synthesized Ethernet, IP and UDP headers, ready to open in Wireshark.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use ddp_rs::pcap::PcapReader;
+//! use std::fs::File;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let reader = PcapReader::new(File::open("show.pcapng")?)?;
+//!
+//! for captured in reader {
+//!     let captured = captured?;
+//!     println!("{:?} {} -> {} {}", captured.timestamp, captured.src, captured.dst, captured.packet);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::error::DDPError;
+use crate::packet::Packet;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+/// Standard DDP port, used as the default filter.
+pub const DDP_PORT: u16 = 4048;
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 1;
+const BLOCK_PACKET: u32 = 2;
+const BLOCK_SIMPLE_PACKET: u32 = 3;
+const BLOCK_ENHANCED_PACKET: u32 = 6;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LOOP: u32 = 108;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+
+const IP_PROTO_UDP: u8 = 17;
+
+/// Guards against corrupt length fields making us allocate gigabytes.
+const MAX_RECORD_LEN: usize = 16 * 1024 * 1024;
+
+/// A DDP packet taken from a capture.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CapturedPacket {
+    /// Capture time, since the Unix epoch
+    pub timestamp: Duration,
+
+    /// Sender of the datagram
+    pub src: SocketAddr,
+
+    /// Receiver of the datagram
+    pub dst: SocketAddr,
+
+    /// The parsed DDP packet
+    pub packet: Packet,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Interface {
+    linktype: u32,
+    // Timestamp units per second
+    resolution: u64,
+}
+
+#[derive(Debug)]
+enum Format {
+    Pcap {
+        big_endian: bool,
+        nanos: bool,
+        linktype: u32,
+    },
+    PcapNg {
+        big_endian: bool,
+        interfaces: Vec<Interface>,
+    },
+}
+
+/// A raw frame read from the capture.
+struct Frame {
+    timestamp: Duration,
+    linktype: u32,
+    data: Vec<u8>,
+}
+
+/// Reads DDP packets from a pcap or pcapng capture.
+///
+/// The format is detected from the file header. Iterating yields every UDP
+/// datagram to or from the DDP port (4048 unless changed with
+/// [`port`](Self::port)) that holds at least a DDP header; all other traffic is
+/// skipped.
+#[derive(Debug)]
+pub struct PcapReader<R> {
+    reader: R,
+    format: Format,
+    port: Option<u16>,
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Reads the file header and prepares to read packets.
+    pub fn new(mut reader: R) -> Result<Self, DDPError> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+
+        let format = if u32::from_be_bytes(magic) == PCAPNG_SECTION_HEADER {
+            let big_endian = read_section_header(&mut reader)?;
+            Format::PcapNg {
+                big_endian,
+                interfaces: Vec::new(),
+            }
+        } else {
+            let (big_endian, nanos) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+                (PCAP_MAGIC_MICROS, _) => (false, false),
+                (PCAP_MAGIC_NANOS, _) => (false, true),
+                (_, PCAP_MAGIC_MICROS) => (true, false),
+                (_, PCAP_MAGIC_NANOS) => (true, true),
+                _ => return Err(invalid("not a pcap or pcapng file")),
+            };
+
+            let mut header = [0u8; 20];
+            reader.read_exact(&mut header)?;
+            let linktype = u32_at(&header, 16, big_endian) & 0x0fff_ffff;
+
+            Format::Pcap {
+                big_endian,
+                nanos,
+                linktype,
+            }
+        };
+
+        Ok(PcapReader {
+            reader,
+            format,
+            port: Some(DDP_PORT),
+        })
+    }
+
+    /// Only yields datagrams to or from `port`.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Yields every UDP datagram that parses as DDP, whatever its port.
+    pub fn any_port(mut self) -> Self {
+        self.port = None;
+        self
+    }
+
+    /// Reads the next DDP packet, or `None` at the end of the capture.
+    pub fn read_packet(&mut self) -> Result<Option<CapturedPacket>, DDPError> {
+        while let Some(frame) = self.read_frame()? {
+            let Some((src, dst, payload)) = decapsulate(frame.linktype, &frame.data) else {
+                continue;
+            };
+
+            if let Some(port) = self.port {
+                if src.port() != port && dst.port() != port {
+                    continue;
+                }
+            }
+            if payload.len() < 10 {
+                continue;
+            }
+
+            return Ok(Some(CapturedPacket {
+                timestamp: frame.timestamp,
+                src,
+                dst,
+                packet: Packet::from_bytes(payload),
+            }));
+        }
+
+        Ok(None)
+    }
+
+    fn read_frame(&mut self) -> Result<Option<Frame>, DDPError> {
+        match &mut self.format {
+            Format::Pcap {
+                big_endian,
+                nanos,
+                linktype,
+            } => {
+                let mut header = [0u8; 16];
+                if !read_or_eof(&mut self.reader, &mut header)? {
+                    return Ok(None);
+                }
+
+                let secs = u32_at(&header, 0, *big_endian) as u64;
+                let frac = u32_at(&header, 4, *big_endian);
+                let caplen = u32_at(&header, 8, *big_endian) as usize;
+
+                let timestamp = if *nanos {
+                    Duration::new(secs, frac)
+                } else {
+                    Duration::new(secs, 0) + Duration::from_micros(frac as u64)
+                };
+                let data = read_vec(&mut self.reader, caplen)?;
+
+                Ok(Some(Frame {
+                    timestamp,
+                    linktype: *linktype,
+                    data,
+                }))
+            }
+            Format::PcapNg {
+                big_endian,
+                interfaces,
+            } => loop {
+                let mut head = [0u8; 8];
+                if !read_or_eof(&mut self.reader, &mut head)? {
+                    return Ok(None);
+                }
+
+                let block_type = u32_at(&head, 0, *big_endian);
+                if block_type == PCAPNG_SECTION_HEADER {
+                    // A new section can switch byte order and resets the interfaces
+                    *big_endian = read_section_header_after_type(&mut self.reader, &head[4..8])?;
+                    interfaces.clear();
+                    continue;
+                }
+
+                let total_len = u32_at(&head, 4, *big_endian) as usize;
+                if total_len < 12 || !total_len.is_multiple_of(4) {
+                    return Err(invalid("bad pcapng block length"));
+                }
+                let body = read_vec(&mut self.reader, total_len - 8)?;
+                // Trailing copy of the block length
+                let body = &body[..body.len() - 4];
+
+                match block_type {
+                    BLOCK_INTERFACE_DESCRIPTION => {
+                        interfaces.push(read_interface(body, *big_endian)?);
+                    }
+                    BLOCK_ENHANCED_PACKET | BLOCK_PACKET => {
+                        if body.len() < 20 {
+                            return Err(invalid("truncated pcapng packet block"));
+                        }
+                        let interface = if block_type == BLOCK_PACKET {
+                            u16_at(body, 0, *big_endian) as usize
+                        } else {
+                            u32_at(body, 0, *big_endian) as usize
+                        };
+                        let interface = *interfaces
+                            .get(interface)
+                            .ok_or_else(|| invalid("packet for unknown interface"))?;
+
+                        let ts = (u32_at(body, 4, *big_endian) as u64) << 32
+                            | u32_at(body, 8, *big_endian) as u64;
+                        let caplen = u32_at(body, 12, *big_endian) as usize;
+                        let data = body
+                            .get(20..20 + caplen)
+                            .ok_or_else(|| invalid("truncated pcapng packet block"))?;
+
+                        return Ok(Some(Frame {
+                            timestamp: ticks_to_duration(ts, interface.resolution),
+                            linktype: interface.linktype,
+                            data: data.to_vec(),
+                        }));
+                    }
+                    BLOCK_SIMPLE_PACKET => {
+                        let interface = *interfaces
+                            .first()
+                            .ok_or_else(|| invalid("packet for unknown interface"))?;
+                        if body.len() < 4 {
+                            return Err(invalid("truncated pcapng simple packet block"));
+                        }
+                        let len = (u32_at(body, 0, *big_endian) as usize).min(body.len() - 4);
+
+                        // Simple packets carry no timestamp
+                        return Ok(Some(Frame {
+                            timestamp: Duration::ZERO,
+                            linktype: interface.linktype,
+                            data: body[4..4 + len].to_vec(),
+                        }));
+                    }
+                    // Statistics, name resolution, custom blocks...
+                    _ => {}
+                }
+            },
+        }
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = Result<CapturedPacket, DDPError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_packet().transpose()
+    }
+}
+
+/// Writes DDP packets to a classic pcap file.
+///
+/// Each packet is wrapped in Ethernet, IPv4 or IPv6 (following the addresses) and
+/// UDP headers so tools like Wireshark can dissect it.
+///
+/// # Examples
+///
+/// ```
+/// use ddp_rs::packet::Packet;
+/// use ddp_rs::pcap::{CapturedPacket, PcapReader, PcapWriter};
+/// use std::time::Duration;
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let captured = CapturedPacket {
+///     timestamp: Duration::from_secs(1),
+///     src: "10.0.0.1:50000".parse()?,
+///     dst: "10.0.0.2:4048".parse()?,
+///     packet: Packet::from_bytes(&[0x41, 0x01, 0x0D, 0x01, 0, 0, 0, 0, 0, 3, 255, 0, 0]),
+/// };
+///
+/// let mut writer = PcapWriter::new(Vec::new())?;
+/// writer.write(&captured)?;
+/// let file = writer.into_inner();
+///
+/// let read: Vec<_> = PcapReader::new(&file[..])?.collect::<Result<_, _>>()?;
+/// assert_eq!(read, vec![captured]);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct PcapWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Writes the file header.
+    pub fn new(mut writer: W) -> Result<Self, DDPError> {
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&PCAP_MAGIC_NANOS.to_le_bytes());
+        header.extend_from_slice(&2u16.to_le_bytes());
+        header.extend_from_slice(&4u16.to_le_bytes());
+        header.extend_from_slice(&0i32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&65535u32.to_le_bytes());
+        header.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        writer.write_all(&header)?;
+
+        Ok(PcapWriter { writer })
+    }
+
+    /// Writes a captured packet.
+    pub fn write(&mut self, captured: &CapturedPacket) -> Result<(), DDPError> {
+        self.write_datagram(
+            captured.timestamp,
+            captured.src,
+            captured.dst,
+            &captured.packet.to_bytes()?,
+        )
+    }
+
+    /// Writes a raw UDP payload, which doesn't have to be a well formed DDP packet.
+    ///
+    /// IPv4 and IPv6 addresses can't be mixed within one datagram; a mismatched
+    /// pair is rejected with [`DDPError::InvalidCapture`].
+    pub fn write_datagram(
+        &mut self,
+        timestamp: Duration,
+        src: SocketAddr,
+        dst: SocketAddr,
+        payload: &[u8],
+    ) -> Result<(), DDPError> {
+        let frame = encapsulate(src, dst, payload)?;
+
+        let mut record = Vec::with_capacity(16 + frame.len());
+        record.extend_from_slice(&(timestamp.as_secs() as u32).to_le_bytes());
+        record.extend_from_slice(&timestamp.subsec_nanos().to_le_bytes());
+        record.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        record.extend_from_slice(&frame);
+        self.writer.write_all(&record)?;
+
+        Ok(())
+    }
+
+    /// Flushes the underlying writer.
+    pub fn flush(&mut self) -> Result<(), DDPError> {
+        Ok(self.writer.flush()?)
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+fn invalid(reason: &str) -> DDPError {
+    DDPError::InvalidCapture(reason.to_string())
+}
+
+fn u16_at(bytes: &[u8], at: usize, big_endian: bool) -> u16 {
+    let b = [bytes[at], bytes[at + 1]];
+    if big_endian {
+        u16::from_be_bytes(b)
+    } else {
+        u16::from_le_bytes(b)
+    }
+}
+
+fn u32_at(bytes: &[u8], at: usize, big_endian: bool) -> u32 {
+    let b = [bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]];
+    if big_endian {
+        u32::from_be_bytes(b)
+    } else {
+        u32::from_le_bytes(b)
+    }
+}
+
+/// Fills `buf`, returning `false` if the stream ended before the first byte.
+fn read_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<bool, DDPError> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) if filled == 0 => return Ok(false),
+            Ok(0) => return Err(invalid("capture ends mid record")),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
+
+fn read_vec<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>, DDPError> {
+    if len > MAX_RECORD_LEN {
+        return Err(invalid("record too large"));
+    }
+    let mut data = vec![0u8; len];
+    reader.read_exact(&mut data).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => invalid("capture ends mid record"),
+        _ => e.into(),
+    })?;
+    Ok(data)
+}
+
+/// Reads the rest of a section header block after its type, returning whether the
+/// section is big-endian.
+fn read_section_header<R: Read>(reader: &mut R) -> Result<bool, DDPError> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    read_section_header_after_type(reader, &len)
+}
+
+fn read_section_header_after_type<R: Read>(reader: &mut R, len: &[u8]) -> Result<bool, DDPError> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+
+    let big_endian = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+        (PCAPNG_BYTE_ORDER_MAGIC, _) => false,
+        (_, PCAPNG_BYTE_ORDER_MAGIC) => true,
+        _ => return Err(invalid("bad pcapng byte order magic")),
+    };
+
+    let total_len = u32_at(len, 0, big_endian) as usize;
+    if total_len < 28 || !total_len.is_multiple_of(4) {
+        return Err(invalid("bad pcapng section header length"));
+    }
+    read_vec(reader, total_len - 12)?;
+
+    Ok(big_endian)
+}
+
+fn read_interface(body: &[u8], big_endian: bool) -> Result<Interface, DDPError> {
+    if body.len() < 8 {
+        return Err(invalid("truncated pcapng interface block"));
+    }
+
+    let mut interface = Interface {
+        linktype: u16_at(body, 0, big_endian) as u32,
+        resolution: 1_000_000,
+    };
+
+    // Options: code, length, value padded to 32 bits
+    let mut options = &body[8..];
+    while options.len() >= 4 {
+        let code = u16_at(options, 0, big_endian);
+        let len = u16_at(options, 2, big_endian) as usize;
+        let value = options
+            .get(4..4 + len)
+            .ok_or_else(|| invalid("truncated pcapng interface option"))?;
+
+        match code {
+            0 => break,
+            // if_tsresol: power of 10, or of 2 if the top bit is set
+            9 if len == 1 => {
+                let exp = (value[0] & 0x7f) as u32;
+                interface.resolution = if value[0] & 0x80 == 0 {
+                    10u64.checked_pow(exp).unwrap_or(u64::MAX)
+                } else {
+                    1u64.checked_shl(exp).unwrap_or(u64::MAX)
+                };
+            }
+            _ => {}
+        }
+
+        let padded = 4 + len.div_ceil(4) * 4;
+        options = options.get(padded..).unwrap_or(&[]);
+    }
+
+    Ok(interface)
+}
+
+fn ticks_to_duration(ticks: u64, resolution: u64) -> Duration {
+    let resolution = resolution.max(1);
+    let secs = ticks / resolution;
+    let nanos = (ticks % resolution) as u128 * 1_000_000_000 / resolution as u128;
+    Duration::new(secs, nanos as u32)
+}
+
+/// Finds the UDP payload in a link layer frame.
+fn decapsulate(linktype: u32, frame: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = u16::from_be_bytes([*frame.get(12)?, *frame.get(13)?]);
+            let mut rest = &frame[14..];
+
+            // Skip any number of 802.1Q / 802.1ad tags
+            while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+                ethertype = u16::from_be_bytes([*rest.get(2)?, *rest.get(3)?]);
+                rest = &rest[4..];
+            }
+            ip_by_ethertype(ethertype, rest)
+        }
+        LINKTYPE_LINUX_SLL => {
+            let ethertype = u16::from_be_bytes([*frame.get(14)?, *frame.get(15)?]);
+            ip_by_ethertype(ethertype, &frame[16..])
+        }
+        LINKTYPE_LINUX_SLL2 => {
+            let ethertype = u16::from_be_bytes([*frame.first()?, *frame.get(1)?]);
+            ip_by_ethertype(ethertype, frame.get(20..)?)
+        }
+        LINKTYPE_NULL | LINKTYPE_LOOP => {
+            // Address family, in the byte order of the capturing host for NULL
+            let family = frame.get(..4)?;
+            let family = match u32::from_le_bytes([family[0], family[1], family[2], family[3]]) {
+                f if f < 0x100 => f,
+                _ => u32::from_be_bytes([family[0], family[1], family[2], family[3]]),
+            };
+            match family {
+                2 => ipv4(&frame[4..]),
+                24 | 28 | 30 => ipv6(&frame[4..]),
+                _ => None,
+            }
+        }
+        LINKTYPE_RAW => match frame.first()? >> 4 {
+            4 => ipv4(frame),
+            6 => ipv6(frame),
+            _ => None,
+        },
+        LINKTYPE_IPV4 => ipv4(frame),
+        LINKTYPE_IPV6 => ipv6(frame),
+        _ => None,
+    }
+}
+
+fn ip_by_ethertype(ethertype: u16, packet: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    match ethertype {
+        ETHERTYPE_IPV4 => ipv4(packet),
+        ETHERTYPE_IPV6 => ipv6(packet),
+        _ => None,
+    }
+}
+
+fn ipv4(packet: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    if packet.len() < 20 || packet[0] >> 4 != 4 {
+        return None;
+    }
+
+    let header_len = (packet[0] & 0x0f) as usize * 4;
+    if header_len < 20 || header_len > packet.len() {
+        return None;
+    }
+    let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    let fragment = u16::from_be_bytes([packet[6], packet[7]]);
+
+    // Reassembly is out of scope; DDP packets fit in one datagram anyway
+    if fragment & 0x3fff != 0 || packet[9] != IP_PROTO_UDP {
+        return None;
+    }
+
+    let src = IpAddr::V4(Ipv4Addr::new(
+        packet[12], packet[13], packet[14], packet[15],
+    ));
+    let dst = IpAddr::V4(Ipv4Addr::new(
+        packet[16], packet[17], packet[18], packet[19],
+    ));
+    let end = total_len.min(packet.len()).max(header_len);
+
+    udp(src, dst, packet.get(header_len..end)?)
+}
+
+fn ipv6(packet: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    if packet.len() < 40 || packet[0] >> 4 != 6 {
+        return None;
+    }
+
+    let payload_len = u16::from_be_bytes([packet[4], packet[5]]) as usize;
+    let mut next = packet[6];
+    let src: [u8; 16] = packet[8..24].try_into().ok()?;
+    let dst: [u8; 16] = packet[24..40].try_into().ok()?;
+    let mut rest = &packet[40..(40 + payload_len).min(packet.len())];
+
+    // Walk extension headers: hop-by-hop, routing, destination options
+    while matches!(next, 0 | 43 | 60) {
+        next = *rest.first()?;
+        let len = (*rest.get(1)? as usize + 1) * 8;
+        rest = rest.get(len..)?;
+    }
+    if next != IP_PROTO_UDP {
+        return None;
+    }
+
+    udp(
+        IpAddr::V6(Ipv6Addr::from(src)),
+        IpAddr::V6(Ipv6Addr::from(dst)),
+        rest,
+    )
+}
+
+fn udp(src: IpAddr, dst: IpAddr, datagram: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    if datagram.len() < 8 {
+        return None;
+    }
+
+    let src_port = u16::from_be_bytes([datagram[0], datagram[1]]);
+    let dst_port = u16::from_be_bytes([datagram[2], datagram[3]]);
+    let len = (u16::from_be_bytes([datagram[4], datagram[5]]) as usize).clamp(8, datagram.len());
+
+    Some((
+        SocketAddr::new(src, src_port),
+        SocketAddr::new(dst, dst_port),
+        &datagram[8..len],
+    ))
+}
+
+/// Wraps a UDP payload in Ethernet, IP and UDP headers.
+fn encapsulate(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Result<Vec<u8>, DDPError> {
+    let udp_len = 8 + payload.len();
+    if udp_len > u16::MAX as usize {
+        return Err(invalid("payload too large for a UDP datagram"));
+    }
+
+    let mut udp = Vec::with_capacity(udp_len);
+    udp.extend_from_slice(&src.port().to_be_bytes());
+    udp.extend_from_slice(&dst.port().to_be_bytes());
+    udp.extend_from_slice(&(udp_len as u16).to_be_bytes());
+    udp.extend_from_slice(&[0, 0]);
+    udp.extend_from_slice(payload);
+
+    // Locally administered MACs, the low bytes hinting at the IP address
+    let mac = |ip: IpAddr| -> [u8; 6] {
+        let tail = match ip {
+            IpAddr::V4(ip) => ip.octets()[2..].to_vec(),
+            IpAddr::V6(ip) => ip.octets()[14..].to_vec(),
+        };
+        [0x02, 0x00, 0x00, 0x00, tail[0], tail[1]]
+    };
+
+    let mut frame = Vec::with_capacity(14 + 40 + udp_len);
+    frame.extend_from_slice(&mac(dst.ip()));
+    frame.extend_from_slice(&mac(src.ip()));
+
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(s), IpAddr::V4(d)) => {
+            frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+
+            let mut ip = [0u8; 20];
+            ip[0] = 0x45;
+            ip[2..4].copy_from_slice(&((20 + udp_len) as u16).to_be_bytes());
+            ip[6] = 0x40; // Don't fragment
+            ip[8] = 64;
+            ip[9] = IP_PROTO_UDP;
+            ip[12..16].copy_from_slice(&s.octets());
+            ip[16..20].copy_from_slice(&d.octets());
+            let checksum = !ones_complement_sum(&ip, 0);
+            ip[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+            // The UDP checksum is optional over IPv4
+            frame.extend_from_slice(&ip);
+        }
+        (IpAddr::V6(s), IpAddr::V6(d)) => {
+            frame.extend_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+
+            let mut ip = [0u8; 40];
+            ip[0] = 0x60;
+            ip[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+            ip[6] = IP_PROTO_UDP;
+            ip[7] = 64;
+            ip[8..24].copy_from_slice(&s.octets());
+            ip[24..40].copy_from_slice(&d.octets());
+
+            // Mandatory over IPv6, computed over a pseudo header
+            let mut pseudo = Vec::with_capacity(40);
+            pseudo.extend_from_slice(&s.octets());
+            pseudo.extend_from_slice(&d.octets());
+            pseudo.extend_from_slice(&(udp_len as u32).to_be_bytes());
+            pseudo.extend_from_slice(&[0, 0, 0, IP_PROTO_UDP]);
+            let checksum = match !ones_complement_sum(&udp, ones_complement_sum(&pseudo, 0)) {
+                0 => 0xffff,
+                c => c,
+            };
+            udp[6..8].copy_from_slice(&checksum.to_be_bytes());
+
+            frame.extend_from_slice(&ip);
+        }
+        _ => return Err(invalid("mixed IPv4 and IPv6 addresses")),
+    }
+
+    frame.extend_from_slice(&udp);
+    Ok(frame)
+}
+
+fn ones_complement_sum(bytes: &[u8], initial: u16) -> u16 {
+    let mut sum = initial as u32;
+    for chunk in bytes.chunks(2) {
+        let word = match chunk {
+            [a, b] => u16::from_be_bytes([*a, *b]),
+            [a] => u16::from_be_bytes([*a, 0]),
+            _ => 0,
+        };
+        sum += word as u32;
+    }
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DDP: [u8; 13] = [0x41, 0x01, 0x0D, 0x01, 0, 0, 0, 0, 0, 3, 255, 0, 0];
+
+    fn captured(src: &str, dst: &str) -> CapturedPacket {
+        CapturedPacket {
+            timestamp: Duration::new(1_700_000_000, 123_456_789),
+            src: src.parse().unwrap(),
+            dst: dst.parse().unwrap(),
+            packet: Packet::from_bytes(&DDP),
+        }
+    }
+
+    fn ipv4_udp(src_port: u16, dst_port: u16, payload: &[u8]) -> Vec<u8> {
+        let frame = encapsulate(
+            SocketAddr::new([10, 0, 0, 1].into(), src_port),
+            SocketAddr::new([10, 0, 0, 2].into(), dst_port),
+            payload,
+        )
+        .unwrap();
+        // Strip Ethernet
+        frame[14..].to_vec()
+    }
+
+    #[test]
+    fn test_roundtrip_ipv4_and_ipv6() {
+        let packets = vec![
+            captured("192.168.1.10:50000", "192.168.1.40:4048"),
+            captured("[fe80::1]:50000", "[fe80::2]:4048"),
+        ];
+
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        for p in &packets {
+            writer.write(p).unwrap();
+        }
+        let file = writer.into_inner();
+
+        let read: Vec<_> = PcapReader::new(&file[..])
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, packets);
+    }
+
+    #[test]
+    fn test_checksums() {
+        let frame = encapsulate(
+            "192.168.1.10:50000".parse().unwrap(),
+            "192.168.1.40:4048".parse().unwrap(),
+            &DDP,
+        )
+        .unwrap();
+        assert_eq!(ones_complement_sum(&frame[14..34], 0), 0xffff);
+
+        let frame = encapsulate(
+            "[fe80::1]:50000".parse().unwrap(),
+            "[fe80::2]:4048".parse().unwrap(),
+            &DDP,
+        )
+        .unwrap();
+        let udp = &frame[54..];
+        let mut pseudo = frame[22..54].to_vec();
+        pseudo.extend_from_slice(&(udp.len() as u32).to_be_bytes());
+        pseudo.extend_from_slice(&[0, 0, 0, 17]);
+        assert_eq!(
+            ones_complement_sum(udp, ones_complement_sum(&pseudo, 0)),
+            0xffff
+        );
+    }
+
+    #[test]
+    fn test_port_filter() {
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        writer
+            .write_datagram(
+                Duration::ZERO,
+                "10.0.0.1:5000".parse().unwrap(),
+                "10.0.0.2:53".parse().unwrap(),
+                &DDP,
+            )
+            .unwrap();
+        writer
+            .write(&captured("10.0.0.2:4048", "10.0.0.1:5000"))
+            .unwrap();
+        let file = writer.into_inner();
+
+        assert_eq!(PcapReader::new(&file[..]).unwrap().count(), 1);
+        assert_eq!(PcapReader::new(&file[..]).unwrap().port(53).count(), 1);
+        assert_eq!(PcapReader::new(&file[..]).unwrap().any_port().count(), 2);
+    }
+
+    #[test]
+    fn test_big_endian_pcap_with_vlan() {
+        let mut frame = vec![0u8; 12];
+        frame.extend_from_slice(&[0x81, 0x00, 0x00, 0x05, 0x08, 0x00]);
+        frame.extend(ipv4_udp(5000, 4048, &DDP));
+
+        let mut file = Vec::new();
+        file.extend_from_slice(&PCAP_MAGIC_MICROS.to_be_bytes());
+        file.extend_from_slice(&[0, 2, 0, 4]);
+        file.extend_from_slice(&[0; 8]);
+        file.extend_from_slice(&65535u32.to_be_bytes());
+        file.extend_from_slice(&LINKTYPE_ETHERNET.to_be_bytes());
+        file.extend_from_slice(&10u32.to_be_bytes());
+        file.extend_from_slice(&500_000u32.to_be_bytes());
+        file.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        file.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        file.extend_from_slice(&frame);
+
+        let read = PcapReader::new(&file[..])
+            .unwrap()
+            .read_packet()
+            .unwrap()
+            .unwrap();
+        assert_eq!(read.timestamp, Duration::from_millis(10_500));
+        assert_eq!(read.packet.data, vec![255, 0, 0]);
+    }
+
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let mut body = body.to_vec();
+        body.resize(body.len().div_ceil(4) * 4, 0);
+        let len = (body.len() + 12) as u32;
+
+        let mut b = Vec::new();
+        b.extend_from_slice(&block_type.to_le_bytes());
+        b.extend_from_slice(&len.to_le_bytes());
+        b.extend_from_slice(&body);
+        b.extend_from_slice(&len.to_le_bytes());
+        b
+    }
+
+    #[test]
+    fn test_pcapng_sll_and_tsresol() {
+        let mut file = Vec::new();
+
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend_from_slice(&[1, 0, 0, 0]);
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        file.extend(block(PCAPNG_SECTION_HEADER, &shb));
+
+        // Linux cooked capture, nanosecond timestamps
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&(LINKTYPE_LINUX_SLL as u16).to_le_bytes());
+        idb.extend_from_slice(&[0, 0]);
+        idb.extend_from_slice(&0u32.to_le_bytes());
+        idb.extend_from_slice(&[9, 0, 1, 0, 9, 0, 0, 0]);
+        idb.extend_from_slice(&[0, 0, 0, 0]);
+        file.extend(block(BLOCK_INTERFACE_DESCRIPTION, &idb));
+
+        // Something to skip
+        file.extend(block(5, &[0; 8]));
+
+        let mut frame = vec![0u8; 14];
+        frame.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        frame.extend(ipv4_udp(4048, 6000, &DDP));
+
+        let ts: u64 = 2_000_000_123;
+        let mut epb = Vec::new();
+        epb.extend_from_slice(&0u32.to_le_bytes());
+        epb.extend_from_slice(&((ts >> 32) as u32).to_le_bytes());
+        epb.extend_from_slice(&(ts as u32).to_le_bytes());
+        epb.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        epb.extend_from_slice(&frame);
+        file.extend(block(BLOCK_ENHANCED_PACKET, &epb));
+
+        let read: Vec<_> = PcapReader::new(&file[..])
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].timestamp, Duration::new(2, 123));
+        assert_eq!(read[0].src, "10.0.0.1:4048".parse().unwrap());
+        assert_eq!(read[0].packet.header.length, 3);
+    }
+
+    #[test]
+    fn test_pcapng_truncated_option() {
+        let mut file = Vec::new();
+
+        let mut shb = Vec::new();
+        shb.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        shb.extend_from_slice(&[1, 0, 0, 0]);
+        shb.extend_from_slice(&(-1i64).to_le_bytes());
+        file.extend(block(PCAPNG_SECTION_HEADER, &shb));
+
+        // if_tsresol with its value cut off
+        let mut idb = Vec::new();
+        idb.extend_from_slice(&(LINKTYPE_ETHERNET as u16).to_le_bytes());
+        idb.extend_from_slice(&[0, 0]);
+        idb.extend_from_slice(&0u32.to_le_bytes());
+        idb.extend_from_slice(&[9, 0, 1, 0]);
+        file.extend(block(BLOCK_INTERFACE_DESCRIPTION, &idb));
+
+        let read: Result<Vec<_>, _> = PcapReader::new(&file[..]).unwrap().collect();
+        assert!(matches!(read, Err(DDPError::InvalidCapture(_))));
+    }
+
+    #[test]
+    fn test_truncated_ip_header() {
+        // IHL claims a 60 byte header in a 24 byte packet
+        let mut ip = ipv4_udp(5000, 4048, &DDP)[..24].to_vec();
+        ip[0] = 0x4f;
+        assert!(decapsulate(LINKTYPE_RAW, &ip).is_none());
+
+        // And one shorter than the minimum
+        let mut ip = ipv4_udp(5000, 4048, &DDP);
+        ip[0] = 0x44;
+        assert!(decapsulate(LINKTYPE_RAW, &ip).is_none());
+    }
+
+    #[test]
+    fn test_raw_ip_and_loopback() {
+        let ip = ipv4_udp(5000, 4048, &DDP);
+        assert!(decapsulate(LINKTYPE_RAW, &ip).is_some());
+
+        let mut null = 2u32.to_le_bytes().to_vec();
+        null.extend_from_slice(&ip);
+        assert!(decapsulate(LINKTYPE_NULL, &null).is_some());
+
+        let mut looped = 2u32.to_be_bytes().to_vec();
+        looped.extend_from_slice(&ip);
+        assert!(decapsulate(LINKTYPE_LOOP, &looped).is_some());
+    }
+
+    #[test]
+    fn test_ignores_fragments_and_tcp() {
+        let mut ip = ipv4_udp(5000, 4048, &DDP);
+        ip[6] = 0x20; // More fragments
+        assert!(decapsulate(LINKTYPE_RAW, &ip).is_none());
+
+        let mut ip = ipv4_udp(5000, 4048, &DDP);
+        ip[9] = 6;
+        assert!(decapsulate(LINKTYPE_RAW, &ip).is_none());
+    }
+
+    #[test]
+    fn test_rejects_garbage() {
+        assert!(matches!(
+            PcapReader::new(&b"definitely not pcap"[..]),
+            Err(DDPError::InvalidCapture(_))
+        ));
+
+        // Truncated record
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        writer
+            .write(&captured("10.0.0.1:1", "10.0.0.2:4048"))
+            .unwrap();
+        let file = writer.into_inner();
+        let mut reader = PcapReader::new(&file[..file.len() - 3]).unwrap();
+        assert!(matches!(
+            reader.next(),
+            Some(Err(DDPError::InvalidCapture(_)))
+        ));
+    }
+
+    #[test]
+    fn test_mixed_address_families() {
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        assert!(writer.write(&captured("10.0.0.1:1", "[::1]:4048")).is_err());
+    }
+}
@@ -0,0 +1,337 @@
+//! Power estimation and limiting for pixel frames.
+//!
+//! Addressable LEDs draw current roughly proportional to their channel values, and
+//! a long strip at full white can easily pull more than the supply can deliver.
+//! [`PowerLimiter`] estimates the draw of a frame from a [`PowerModel`] and scales
+//! the frame down before it is sent whenever it would exceed a budget.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use ddp_rs::connection::DDPConnection;
+//! use ddp_rs::power::PowerLimiter;
+//! use ddp_rs::protocol::{PixelConfig, ID};
+//! use std::net::UdpSocket;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut conn = DDPConnection::try_new(
+//!     "192.168.1.40:4048",
+//!     PixelConfig::default(),
+//!     ID::Default,
+//!     UdpSocket::bind("0.0.0.0:4048")?
+//! )?;
+//!
+//! // 5000 pixels on a 60A supply
+//! let limiter = PowerLimiter::new(60_000.0, conn.pixel_config);
+//!
+//! let mut frame = vec![255u8; 5000 * 3];
+//! let report = limiter.limit(&mut frame);
+//! println!("estimated {:.0}mA, sending {:.0}mA", report.estimated_ma, report.limited_ma);
+//!
+//! conn.write(&frame)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::protocol::{DataType, PixelConfig};
+
+/// Current drawn by a single LED.
+///
+/// `channel_ma` is the draw of each channel at full (255) output; the draw scales
+/// linearly with the channel value. `idle_ma` is drawn by every LED even when dark.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PowerModel {
+    /// Current per channel at full output, in milliamps
+    pub channel_ma: Vec<f32>,
+
+    /// Quiescent current per LED, in milliamps
+    pub idle_ma: f32,
+}
+
+impl PowerModel {
+    /// Creates a model from per-channel and idle currents.
+    pub fn new(channel_ma: &[f32], idle_ma: f32) -> Self {
+        PowerModel {
+            channel_ma: channel_ma.to_vec(),
+            idle_ma,
+        }
+    }
+
+    /// A typical model for the given data type, based on WS2812/SK6812 style LEDs
+    /// drawing 20mA per channel and 1mA idle.
+    pub fn for_data_type(data_type: DataType) -> Self {
+        PowerModel {
+            channel_ma: vec![20.0; data_type.channels()],
+            idle_ma: 1.0,
+        }
+    }
+
+    /// Number of channels per pixel.
+    pub fn channels(&self) -> usize {
+        self.channel_ma.len()
+    }
+
+    /// Estimated draw of `pixels`, split into the part that scales with brightness
+    /// and the fixed idle part.
+    fn split_estimate(&self, pixels: &[u8]) -> (f32, f32) {
+        let channels = self.channels().max(1);
+
+        let active: f32 = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, v)| *v as f32 / 255.0 * self.channel_ma.get(i % channels).unwrap_or(&0.0))
+            .sum();
+        let idle = pixels.len().div_ceil(channels) as f32 * self.idle_ma;
+
+        (active, idle)
+    }
+
+    /// Estimated draw of `pixels` in milliamps.
+    pub fn estimate(&self, pixels: &[u8]) -> f32 {
+        let (active, idle) = self.split_estimate(pixels);
+        active + idle
+    }
+}
+
+/// A range of pixels driven with its own [`PowerModel`].
+#[derive(Debug, PartialEq, Clone)]
+struct Strip {
+    start: usize,
+    len: usize,
+    model: PowerModel,
+}
+
+/// Result of running a frame through a [`PowerLimiter`].
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct PowerReport {
+    /// Estimated draw of the frame as it was passed in, in milliamps
+    pub estimated_ma: f32,
+
+    /// Estimated draw of the frame after limiting, in milliamps
+    pub limited_ma: f32,
+
+    /// Factor the frame was scaled by (1.0 when it was within budget)
+    pub scale: f32,
+}
+
+impl PowerReport {
+    /// Whether the frame had to be scaled down.
+    pub fn limited(&self) -> bool {
+        self.scale < 1.0
+    }
+}
+
+/// Scales frames down so their estimated draw stays within a current budget.
+///
+/// Pixels use the model for the limiter's data type unless they fall inside a
+/// range registered with [`strip`](Self::strip), which lets strips of different
+/// LED types share one frame.
+#[derive(Debug, PartialEq, Clone)]
+pub struct PowerLimiter {
+    budget_ma: f32,
+    channels: usize,
+    model: PowerModel,
+    strips: Vec<Strip>,
+}
+
+impl PowerLimiter {
+    /// Creates a limiter with a budget in milliamps, using the typical model for
+    /// the data type in `pixel_config`.
+    pub fn new(budget_ma: f32, pixel_config: PixelConfig) -> Self {
+        PowerLimiter {
+            budget_ma,
+            channels: pixel_config.data_type.channels(),
+            model: PowerModel::for_data_type(pixel_config.data_type),
+            strips: Vec::new(),
+        }
+    }
+
+    /// Replaces the model used for pixels not covered by a strip.
+    pub fn model(mut self, model: PowerModel) -> Self {
+        self.model = model;
+        self
+    }
+
+    /// Uses `model` for `len` pixels starting at pixel `start`.
+    pub fn strip(mut self, start: usize, len: usize, model: PowerModel) -> Self {
+        self.strips.push(Strip { start, len, model });
+        self
+    }
+
+    /// The configured budget in milliamps.
+    pub fn budget_ma(&self) -> f32 {
+        self.budget_ma
+    }
+
+    /// Estimated draw of `frame` in milliamps.
+    pub fn estimate(&self, frame: &[u8]) -> f32 {
+        let (active, idle) = self.split_estimate(frame);
+        active + idle
+    }
+
+    /// Scales `frame` in place so it fits the budget and reports the estimated draw.
+    ///
+    /// Idle current can't be reduced by dimming, so if the idle draw alone exceeds
+    /// the budget the frame is blanked.
+    pub fn limit(&self, frame: &mut [u8]) -> PowerReport {
+        let (active, idle) = self.split_estimate(frame);
+        let estimated_ma = active + idle;
+
+        if estimated_ma <= self.budget_ma || active == 0.0 {
+            return PowerReport {
+                estimated_ma,
+                limited_ma: estimated_ma,
+                scale: 1.0,
+            };
+        }
+
+        let scale = ((self.budget_ma - idle) / active).clamp(0.0, 1.0);
+        for v in frame.iter_mut() {
+            // Round down so we never end up above budget
+            *v = (*v as f32 * scale) as u8;
+        }
+
+        PowerReport {
+            estimated_ma,
+            limited_ma: self.estimate(frame),
+            scale,
+        }
+    }
+
+    fn split_estimate(&self, frame: &[u8]) -> (f32, f32) {
+        let (mut active, mut idle) = (0.0, 0.0);
+        let (mut pixel, mut cursor) = (0, 0);
+
+        // Walk the frame, switching models at strip boundaries. Strips can have a
+        // different number of channels than the rest of the frame, so track the
+        // byte position separately from the pixel.
+        while cursor < frame.len() {
+            let (model, channels, end) = match self
+                .strips
+                .iter()
+                .find(|s| pixel >= s.start && pixel < s.start + s.len)
+            {
+                Some(s) => (&s.model, s.model.channels(), s.start + s.len),
+                None => {
+                    let next = self
+                        .strips
+                        .iter()
+                        .filter(|s| s.start > pixel)
+                        .map(|s| s.start)
+                        .min()
+                        .unwrap_or(usize::MAX);
+                    (&self.model, self.channels, next)
+                }
+            };
+
+            let len = (end - pixel).saturating_mul(channels.max(1));
+            let bytes = &frame[cursor..cursor.saturating_add(len).min(frame.len())];
+            let (a, i) = model.split_estimate(bytes);
+            active += a;
+            idle += i;
+
+            pixel = end;
+            cursor += bytes.len();
+        }
+
+        (active, idle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::PixelFormat;
+
+    #[test]
+    fn test_model_estimate() {
+        let model = PowerModel::for_data_type(DataType::RGB);
+        assert_eq!(model.estimate(&[0, 0, 0]), 1.0);
+        assert_eq!(model.estimate(&[255, 255, 255]), 61.0);
+        assert_eq!(model.estimate(&[255, 0, 0, 0, 0, 0]), 22.0);
+    }
+
+    #[test]
+    fn test_within_budget_untouched() {
+        let limiter = PowerLimiter::new(1000.0, PixelConfig::default());
+        let mut frame = vec![255u8; 30];
+
+        let report = limiter.limit(&mut frame);
+        assert!(!report.limited());
+        assert_eq!(report.estimated_ma, 610.0);
+        assert_eq!(frame, vec![255u8; 30]);
+    }
+
+    #[test]
+    fn test_over_budget_scaled() {
+        // 10 pixels at full white is 610mA
+        let limiter = PowerLimiter::new(310.0, PixelConfig::default());
+        let mut frame = vec![255u8; 30];
+
+        let report = limiter.limit(&mut frame);
+        assert!(report.limited());
+        assert_eq!(report.estimated_ma, 610.0);
+        assert!((report.scale - 0.5).abs() < 1e-6);
+        assert!(report.limited_ma <= 310.0);
+        assert!(frame.iter().all(|&v| v == 127));
+    }
+
+    #[test]
+    fn test_idle_exceeds_budget() {
+        let limiter = PowerLimiter::new(5.0, PixelConfig::default());
+        let mut frame = vec![255u8; 30];
+
+        let report = limiter.limit(&mut frame);
+        assert_eq!(report.scale, 0.0);
+        assert!(frame.iter().all(|&v| v == 0));
+    }
+
+    #[test]
+    fn test_rgbw_data_type() {
+        let config = PixelConfig {
+            data_type: DataType::RGBW,
+            data_size: PixelFormat::Pixel32Bits,
+            customer_defined: false,
+        };
+        let limiter = PowerLimiter::new(1000.0, config);
+        assert_eq!(limiter.estimate(&[255, 255, 255, 255]), 81.0);
+    }
+
+    #[test]
+    fn test_per_strip_models() {
+        // Pixels 2..4 are a hungrier strip type
+        let limiter = PowerLimiter::new(1000.0, PixelConfig::default()).strip(
+            2,
+            2,
+            PowerModel::new(&[50.0, 50.0, 50.0], 0.0),
+        );
+
+        let frame = vec![255u8; 5 * 3];
+        // 3 default pixels at 61mA plus 2 strip pixels at 150mA
+        assert_eq!(limiter.estimate(&frame), 3.0 * 61.0 + 2.0 * 150.0);
+    }
+
+    #[test]
+    fn test_mixed_channel_strips() {
+        let rgbw = PowerModel::for_data_type(DataType::RGBW);
+
+        // An RGBW strip in the middle of an RGB frame: 2 RGB, 2 RGBW, 2 RGB pixels
+        let limiter = PowerLimiter::new(1000.0, PixelConfig::default()).strip(2, 2, rgbw);
+        let mut frame = vec![0u8; 2 * 3];
+        frame.extend_from_slice(&[0, 0, 0, 255, 0, 0, 0, 255]);
+        frame.extend_from_slice(&[255, 0, 0, 0, 0, 0]);
+        // Idle 1mA for each of 6 pixels, two white channels and one red one
+        assert_eq!(limiter.estimate(&frame), 6.0 + 3.0 * 20.0);
+
+        // And an RGB strip at the start of an RGBW frame
+        let config = PixelConfig {
+            data_type: DataType::RGBW,
+            data_size: PixelFormat::Pixel32Bits,
+            customer_defined: false,
+        };
+        let limiter =
+            PowerLimiter::new(1000.0, config).strip(0, 1, PowerModel::for_data_type(DataType::RGB));
+        let frame = [255, 255, 255, 0, 0, 0, 255];
+        assert_eq!(limiter.estimate(&frame), 2.0 + 3.0 * 20.0 + 20.0);
+    }
+}
@@ -0,0 +1,254 @@
+use super::{Header, PixelConfig, ID};
+use crate::error::DDPError;
+use crate::protocol::timecode::TimeCode;
+
+/// Builds a [`Header`], checking the fields make sense together.
+///
+/// Starts from a version 1 data header for the default ID with sequence number 0
+/// (unused) and RGB 24 bit pixels, and no flags set.
+///
+/// # Examples
+///
+/// ```
+/// use ddp_rs::protocol::{HeaderBuilder, ID};
+///
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let header = HeaderBuilder::new()
+///     .sequence_number(3)
+///     .offset(300)
+///     .length(9)
+///     .push(true)
+///     .timecode(65536)
+///     .build()?;
+///
+/// assert!(header.packet_type.timecode);
+/// let bytes: [u8; 14] = header.into();
+/// assert_eq!(bytes[0], 0x51);
+///
+/// // Query and reply at once is contradictory
+/// assert!(HeaderBuilder::new().id(ID::Status).query(true).reply(true).build().is_err());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub struct HeaderBuilder {
+    header: Header,
+}
+
+impl HeaderBuilder {
+    /// Starts a new header.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Protocol version, 1 to 3 as it is only two bits on the wire. Only version 1
+    /// is defined.
+    pub fn version(mut self, version: u8) -> Self {
+        self.header.packet_type.version = version;
+        self
+    }
+
+    /// Adds a timecode, in 1/65536ths of a second, and sets the timecode flag.
+    pub fn timecode(mut self, timecode: u32) -> Self {
+        self.header.packet_type.timecode = true;
+        self.header.time_code = TimeCode(Some(timecode));
+        self
+    }
+
+    /// Removes the timecode and clears the timecode flag.
+    pub fn no_timecode(mut self) -> Self {
+        self.header.packet_type.timecode = false;
+        self.header.time_code = TimeCode(None);
+        self
+    }
+
+    /// Sets the storage flag, asking the display to persist a config write.
+    pub fn storage(mut self, storage: bool) -> Self {
+        self.header.packet_type.storage = storage;
+        self
+    }
+
+    /// Sets the reply flag.
+    pub fn reply(mut self, reply: bool) -> Self {
+        self.header.packet_type.reply = reply;
+        self
+    }
+
+    /// Sets the query flag.
+    pub fn query(mut self, query: bool) -> Self {
+        self.header.packet_type.query = query;
+        self
+    }
+
+    /// Sets the push flag, marking the end of a frame.
+    pub fn push(mut self, push: bool) -> Self {
+        self.header.packet_type.push = push;
+        self
+    }
+
+    /// Sequence number, 1 to 15, or 0 when not used.
+    pub fn sequence_number(mut self, sequence_number: u8) -> Self {
+        self.header.sequence_number = sequence_number;
+        self
+    }
+
+    /// Pixel format of the data.
+    pub fn pixel_config(mut self, pixel_config: PixelConfig) -> Self {
+        self.header.pixel_config = pixel_config;
+        self
+    }
+
+    /// Destination ID.
+    pub fn id(mut self, id: ID) -> Self {
+        self.header.id = id;
+        self
+    }
+
+    /// Byte offset of the data in the display buffer.
+    pub fn offset(mut self, offset: u32) -> Self {
+        self.header.offset = offset;
+        self
+    }
+
+    /// Length of the data in bytes.
+    pub fn length(mut self, length: u16) -> Self {
+        self.header.length = length;
+        self
+    }
+
+    /// Checks the header and returns it.
+    ///
+    /// Fails with [`DDPError::InvalidHeader`] for versions outside 1 to 3, sequence
+    /// numbers above 15, custom IDs outside the custom ranges, query and reply set
+    /// together, or data that would run past the end of a 32 bit offset.
+    pub fn build(self) -> Result<Header, DDPError> {
+        let header = self.header;
+        let invalid = |reason: String| Err(DDPError::InvalidHeader(reason));
+
+        if !(1..=3).contains(&header.packet_type.version) {
+            return invalid(format!(
+                "version {} is not 1 to 3",
+                header.packet_type.version
+            ));
+        }
+        if header.sequence_number > 15 {
+            return invalid(format!(
+                "sequence number {} is above 15",
+                header.sequence_number
+            ));
+        }
+        if let ID::Custom(id) = header.id {
+            if !matches!(id, 2..=245 | 247..=249 | 252..=253) {
+                return invalid(format!("{} is not a custom ID", id));
+            }
+        }
+        if header.packet_type.query && header.packet_type.reply {
+            return invalid("query and reply are both set".to_string());
+        }
+        if header.packet_type.timecode != header.time_code.0.is_some() {
+            return invalid("timecode flag doesn't match the timecode".to_string());
+        }
+        if header.offset.checked_add(header.length as u32).is_none() {
+            return invalid(format!(
+                "{} bytes at offset {} overflow the offset",
+                header.length, header.offset
+            ));
+        }
+
+        Ok(header)
+    }
+}
+
+impl From<Header> for HeaderBuilder {
+    /// Starts from an existing header, to change some of its fields.
+    fn from(header: Header) -> Self {
+        HeaderBuilder { header }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{DataType, PacketType};
+
+    #[test]
+    fn test_defaults() {
+        let header = HeaderBuilder::new().build().unwrap();
+        assert_eq!(header.packet_type, PacketType::default());
+        assert_eq!(header.sequence_number, 0);
+        assert_eq!(header.pixel_config, PixelConfig::default());
+        assert_eq!(header.id, ID::Default);
+        assert_eq!(header.offset, 0);
+        assert_eq!(header.length, 0);
+        assert_eq!(header.time_code, TimeCode(None));
+    }
+
+    #[test]
+    fn test_every_field() {
+        let pixel_config = PixelConfig {
+            data_type: DataType::RGBW,
+            ..PixelConfig::default()
+        };
+        let header = HeaderBuilder::new()
+            .version(2)
+            .timecode(42)
+            .storage(true)
+            .reply(true)
+            .push(true)
+            .sequence_number(15)
+            .pixel_config(pixel_config)
+            .id(ID::Config)
+            .offset(100)
+            .length(50)
+            .build()
+            .unwrap();
+
+        let bytes: [u8; 14] = header.into();
+        assert_eq!(
+            bytes,
+            [0x9D, 15, 0x1D, 250, 0, 0, 0, 100, 0, 50, 0, 0, 0, 42]
+        );
+        assert_eq!(Header::from(&bytes[..]), header);
+
+        let query = HeaderBuilder::from(header)
+            .reply(false)
+            .query(true)
+            .no_timecode()
+            .build()
+            .unwrap();
+        assert!(query.packet_type.query);
+        assert!(!query.packet_type.timecode);
+        assert_eq!(query.offset, 100);
+    }
+
+    #[test]
+    fn test_validation() {
+        let invalid = [
+            HeaderBuilder::new().version(0),
+            HeaderBuilder::new().version(4),
+            HeaderBuilder::new().sequence_number(16),
+            HeaderBuilder::new().id(ID::Custom(250)),
+            HeaderBuilder::new().query(true).reply(true),
+            HeaderBuilder::new().offset(u32::MAX).length(1),
+        ];
+        for builder in invalid {
+            assert!(
+                matches!(builder.build(), Err(DDPError::InvalidHeader(_))),
+                "{:?}",
+                builder
+            );
+        }
+
+        // A timecode flag without a timecode can only come from an existing header
+        let mut header = Header::default();
+        header.packet_type.timecode = true;
+        assert!(HeaderBuilder::from(header).build().is_err());
+
+        assert!(HeaderBuilder::new().id(ID::Custom(2)).build().is_ok());
+        assert!(HeaderBuilder::new()
+            .offset(u32::MAX - 1)
+            .length(1)
+            .build()
+            .is_ok());
+    }
+}
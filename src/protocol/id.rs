@@ -72,9 +72,9 @@ impl From<u8> for ID {
     }
 }
 
-impl Into<u8> for ID {
-    fn into(self) -> u8 {
-        match self {
+impl From<ID> for u8 {
+    fn from(id: ID) -> Self {
+        match id {
             ID::Reserved => 0,
             ID::Default => 1,
             ID::Control => 246,
@@ -94,6 +94,23 @@ impl Into<u8> for ID {
     }
 }
 
+impl std::fmt::Display for ID {
+    /// Renders the ID name and its value on the wire, e.g. `status (251)`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ID::Reserved => "reserved",
+            ID::Default => "default",
+            ID::Custom(_) => "custom",
+            ID::Control => "control",
+            ID::Config => "config",
+            ID::Status => "status",
+            ID::DMX => "DMX",
+            ID::Broadcast => "broadcast",
+        };
+        write!(f, "{} ({})", name, u8::from(*self))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -116,4 +133,11 @@ mod tests {
         let id = ID::from(0);
         assert_eq!(id, ID::Reserved);
     }
+
+    #[test]
+    fn test_id_display() {
+        assert_eq!(ID::Status.to_string(), "status (251)");
+        assert_eq!(ID::Custom(42).to_string(), "custom (42)");
+        assert_eq!(ID::Default.to_string(), "default (1)");
+    }
 }
@@ -1,60 +1,596 @@
+use crate::error::DDPError;
 use crate::protocol::ID;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
+use std::net::Ipv4Addr;
+use std::ops::RangeInclusive;
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Hash, Clone)]
+// Every message struct keeps the fields it doesn't model in `extra`, and leaves
+// out the ones that weren't set, so a message that is read and written back
+// keeps a device's own fields. The known fields come first, and the rest are
+// sorted by key unless the `preserve_order` feature keeps the device's order.
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Hash, Clone, Default)]
 pub struct StatusRoot {
     pub status: Status,
+    /// Fields not modelled here
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Hash, Clone)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Hash, Clone, Default)]
 pub struct Status {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub update: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub man: Option<String>,
-    #[serde(rename = "mod")]
+    #[serde(rename = "mod", skip_serializing_if = "Option::is_none")]
     pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub ver: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub mac: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub push: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub ntp: Option<bool>,
+    /// Fields not modelled here
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Hash, Clone)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Hash, Clone, Default)]
 pub struct ConfigRoot {
     pub config: Config,
+    /// Fields not modelled here
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Hash, Clone)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Hash, Clone, Default)]
 pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub nm: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub gw: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub ports: Vec<Port>,
+    /// Fields not modelled here, such as vendor settings
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
+/// Output port settings from a display's config.
 #[derive(Debug, Serialize, Deserialize, PartialEq, Hash, Clone)]
 pub struct Port {
+    /// Port number
     pub port: u32,
+    /// Type of pixel string on the port, device specific
     pub ts: u32,
+    /// Length, in pixels
     pub l: u32,
+    /// Start pixel in the display buffer
     pub ss: u32,
+    /// Fields not modelled here
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Hash, Clone)]
+impl Port {
+    /// A port starting at pixel 0 with no pixels.
+    pub fn new(port: u32) -> Self {
+        Port {
+            port,
+            ts: 0,
+            l: 0,
+            ss: 0,
+            extra: Map::new(),
+        }
+    }
+
+    /// Sets the start pixel.
+    pub fn start(mut self, start: u32) -> Self {
+        self.ss = start;
+        self
+    }
+
+    /// Sets the length in pixels.
+    pub fn length(mut self, length: u32) -> Self {
+        self.l = length;
+        self
+    }
+
+    /// Sets the pixel string type.
+    pub fn string_type(mut self, ts: u32) -> Self {
+        self.ts = ts;
+        self
+    }
+}
+
+impl Config {
+    /// The IP address, if it is set and valid.
+    pub fn ip(&self) -> Option<Ipv4Addr> {
+        self.ip.as_deref().and_then(|ip| ip.parse().ok())
+    }
+
+    /// Sets the IP address.
+    pub fn set_ip(&mut self, ip: Ipv4Addr) {
+        self.ip = Some(ip.to_string());
+    }
+
+    /// The netmask, if it is set and valid.
+    pub fn netmask(&self) -> Option<Ipv4Addr> {
+        self.nm.as_deref().and_then(|nm| nm.parse().ok())
+    }
+
+    /// Sets the netmask.
+    pub fn set_netmask(&mut self, netmask: Ipv4Addr) {
+        self.nm = Some(netmask.to_string());
+    }
+
+    /// The gateway, if it is set and valid.
+    pub fn gateway(&self) -> Option<Ipv4Addr> {
+        self.gw.as_deref().and_then(|gw| gw.parse().ok())
+    }
+
+    /// Sets the gateway.
+    pub fn set_gateway(&mut self, gateway: Ipv4Addr) {
+        self.gw = Some(gateway.to_string());
+    }
+
+    /// The settings of output port `port`.
+    pub fn port(&self, port: u32) -> Option<&Port> {
+        self.ports.iter().find(|p| p.port == port)
+    }
+
+    /// The settings of output port `port`, added with [`Port::new`] if the config
+    /// doesn't list it.
+    pub fn port_mut(&mut self, port: u32) -> &mut Port {
+        match self.ports.iter().position(|p| p.port == port) {
+            Some(i) => &mut self.ports[i],
+            None => {
+                self.ports.push(Port::new(port));
+                self.ports.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Applies `changes` on top of this config.
+    ///
+    /// Addresses and other fields set in `changes` replace ours, and its ports
+    /// replace ours with the same number or are added. Nothing is removed.
+    pub fn merge(&mut self, changes: &Config) {
+        if changes.ip.is_some() {
+            self.ip = changes.ip.clone();
+        }
+        if changes.nm.is_some() {
+            self.nm = changes.nm.clone();
+        }
+        if changes.gw.is_some() {
+            self.gw = changes.gw.clone();
+        }
+        for port in &changes.ports {
+            *self.port_mut(port.port) = port.clone();
+        }
+        for (key, value) in &changes.extra {
+            self.extra.insert(key.clone(), value.clone());
+        }
+    }
+
+    /// What changed going from this config to `new`.
+    pub fn diff(&self, new: &Config) -> ConfigDiff {
+        let mut diff = ConfigDiff::default();
+        diff.compare("ip", &self.ip, &new.ip);
+        diff.compare("nm", &self.nm, &new.nm);
+        diff.compare("gw", &self.gw, &new.gw);
+        diff.compare_extra("", &self.extra, &new.extra);
+
+        for old in &self.ports {
+            let name = format!("ports[{}]", old.port);
+            match new.port(old.port) {
+                Some(p) => {
+                    diff.compare(&format!("{name}.ts"), &Some(old.ts), &Some(p.ts));
+                    diff.compare(&format!("{name}.l"), &Some(old.l), &Some(p.l));
+                    diff.compare(&format!("{name}.ss"), &Some(old.ss), &Some(p.ss));
+                    diff.compare_extra(&format!("{name}."), &old.extra, &p.extra);
+                }
+                None => diff.compare(&name, &Some(port_json(old)), &None),
+            }
+        }
+        for p in new.ports.iter().filter(|p| self.port(p.port).is_none()) {
+            diff.compare(&format!("ports[{}]", p.port), &None, &Some(port_json(p)));
+        }
+
+        diff
+    }
+}
+
+fn port_json(port: &Port) -> String {
+    serde_json::to_string(port).unwrap_or_default()
+}
+
+/// A changed field of a [`Config`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ConfigChange {
+    /// JSON name of the field, such as `ip` or `ports[1].l`
+    pub field: String,
+    /// The value before, or `None` if it wasn't set
+    pub old: Option<String>,
+    /// The value after, or `None` if it was removed
+    pub new: Option<String>,
+}
+
+impl std::fmt::Display for ConfigChange {
+    /// Renders as `field: old -> new`, with `-` for a missing value.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = |v: &Option<String>| v.clone().unwrap_or_else(|| "-".to_string());
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.field,
+            value(&self.old),
+            value(&self.new)
+        )
+    }
+}
+
+/// The differences between two [`Config`]s, from [`Config::diff`].
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct ConfigDiff {
+    /// Changed fields, addresses and other fields first and then ports in order
+    pub changes: Vec<ConfigChange>,
+}
+
+impl ConfigDiff {
+    /// Whether nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The change to `field`, if it changed.
+    pub fn get(&self, field: &str) -> Option<&ConfigChange> {
+        self.changes.iter().find(|c| c.field == field)
+    }
+
+    fn compare<T: ToString + PartialEq>(&mut self, field: &str, old: &Option<T>, new: &Option<T>) {
+        if old != new {
+            self.changes.push(ConfigChange {
+                field: field.to_string(),
+                old: old.as_ref().map(T::to_string),
+                new: new.as_ref().map(T::to_string),
+            });
+        }
+    }
+
+    fn compare_extra(&mut self, prefix: &str, old: &Map<String, Value>, new: &Map<String, Value>) {
+        let added = new.keys().filter(|k| !old.contains_key(*k));
+        for key in old.keys().chain(added) {
+            self.compare(&format!("{prefix}{key}"), &old.get(key), &new.get(key));
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigDiff {
+    /// Renders the changes separated by commas, or `no changes`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return f.write_str("no changes");
+        }
+        for (i, change) in self.changes.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Hash, Clone, Default)]
 pub struct ControlRoot {
     pub control: Control,
+    /// Fields not modelled here
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Hash, Clone)]
+/// A CONTROL message, which runs effects on displays such as Minleon's WiFi
+/// controller.
+///
+/// Only the fields that are set are sent. Fields this crate doesn't know are kept
+/// in [`extra`](Self::extra), so they survive a read and write back.
+///
+/// # Examples
+///
+/// ```
+/// use ddp_rs::protocol::message::{Color, Control, EffectName};
+///
+/// let control = Control::effect(EffectName::MultiChaser)
+///     .intensity(100)
+///     .colors([Color::from([255, 0, 0]), Color::from([0, 255, 0])]);
+/// assert!(control.validate().is_ok());
+///
+/// assert_eq!(
+///     serde_json::to_string(&control).unwrap(),
+///     r#"{"fx":"Multi Chaser","int":100,"colors":[{"r":255,"g":0,"b":0},{"r":0,"g":255,"b":0}]}"#
+/// );
+/// ```
+#[derive(Debug, Serialize, Deserialize, PartialEq, Hash, Clone, Default)]
 pub struct Control {
-    pub fx: Option<String>,
+    /// Effect to run, which also turns the power back on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fx: Option<EffectName>,
+    /// Intensity, 0-100
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub int: Option<u32>,
+    /// Speed, 1-100
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub spd: Option<u32>,
+    /// Direction, 0 for normal and 1 for reverse
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub dir: Option<u32>,
+    /// Up to 3 custom colors for the effect
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub colors: Option<Vec<Color>>,
+    /// 1 to keep the settings over a power cycle
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub save: Option<u32>,
+    /// Power, 0 for off and 1 for on. Sent on its own.
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub power: Option<u32>,
+    /// Entries of the favorites list to set, or the list read from the display
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub favorites: Option<Vec<Favorite>>,
+    /// Fields not modelled here
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Control {
+    /// Runs the effect `fx`.
+    pub fn effect(fx: impl Into<EffectName>) -> Self {
+        Control {
+            fx: Some(fx.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Turns the display on or off.
+    pub fn power(on: bool) -> Self {
+        Control {
+            power: Some(on as u32),
+            ..Default::default()
+        }
+    }
+
+    /// Sets entries of the favorites list.
+    pub fn favorites(favorites: Vec<Favorite>) -> Self {
+        Control {
+            favorites: Some(favorites),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the intensity, 0-100.
+    pub fn intensity(mut self, intensity: u32) -> Self {
+        self.int = Some(intensity);
+        self
+    }
+
+    /// Sets the speed, 1-100.
+    pub fn speed(mut self, speed: u32) -> Self {
+        self.spd = Some(speed);
+        self
+    }
+
+    /// Runs the effect in reverse.
+    pub fn reverse(mut self, reverse: bool) -> Self {
+        self.dir = Some(reverse as u32);
+        self
+    }
+
+    /// Sets up to 3 custom colors.
+    pub fn colors(mut self, colors: impl IntoIterator<Item = Color>) -> Self {
+        self.colors = Some(colors.into_iter().collect());
+        self
+    }
+
+    /// Keeps the settings over a power cycle.
+    pub fn save(mut self) -> Self {
+        self.save = Some(1);
+        self
+    }
+
+    /// Checks the values are in the ranges the specification gives.
+    ///
+    /// Returns [`DDPError::InvalidControl`] naming the first field out of range.
+    pub fn validate(&self) -> Result<(), DDPError> {
+        check_effect(self.int, self.spd, self.dir, &self.colors)?;
+        check("save", self.save, 0..=1)?;
+        check("power", self.power, 0..=1)?;
+
+        if self.power.is_some() && (self.fx.is_some() || self.int.is_some() || self.spd.is_some()) {
+            return Err(DDPError::InvalidControl(
+                "power should be sent on its own".to_string(),
+            ));
+        }
+
+        for favorite in self.favorites.iter().flatten() {
+            check("favorite index", Some(favorite.i), 1..=10)?;
+            check("favorite time", favorite.t, 0..=32500)?;
+            check_effect(favorite.int, favorite.spd, favorite.dir, &favorite.colors)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn check(name: &str, value: Option<u32>, range: RangeInclusive<u32>) -> Result<(), DDPError> {
+    match value {
+        Some(v) if !range.contains(&v) => Err(DDPError::InvalidControl(format!(
+            "{} {} is outside {}-{}",
+            name,
+            v,
+            range.start(),
+            range.end()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn check_effect(
+    int: Option<u32>,
+    spd: Option<u32>,
+    dir: Option<u32>,
+    colors: &Option<Vec<Color>>,
+) -> Result<(), DDPError> {
+    check("intensity", int, 0..=100)?;
+    check("speed", spd, 1..=100)?;
+    check("direction", dir, 0..=1)?;
+
+    let colors = colors.as_deref().unwrap_or_default();
+    if colors.len() > 3 {
+        return Err(DDPError::InvalidControl(format!(
+            "{} colors given, at most 3 are allowed",
+            colors.len()
+        )));
+    }
+    for color in colors {
+        check("red", Some(color.r), 0..=255)?;
+        check("green", Some(color.g), 0..=255)?;
+        check("blue", Some(color.b), 0..=255)?;
+    }
+    Ok(())
+}
+
+/// One entry of a display's favorites list, which the display cycles through.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Hash, Clone, Default)]
+pub struct Favorite {
+    /// Position in the list, 1-10
+    pub i: u32,
+    /// Effect to run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fx: Option<EffectName>,
+    /// Seconds to run the effect for, 0-32500, with 0 disabling the entry
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub t: Option<u32>,
+    /// Intensity, 0-100
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub int: Option<u32>,
+    /// Speed, 1-100
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spd: Option<u32>,
+    /// Direction, 0 for normal and 1 for reverse
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<u32>,
+    /// Up to 3 custom colors for the effect
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub colors: Option<Vec<Color>>,
+    /// Fields not modelled here
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Favorite {
+    /// Favorite number `index` running `fx`.
+    pub fn new(index: u32, fx: impl Into<EffectName>) -> Self {
+        Favorite {
+            i: index,
+            fx: Some(fx.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Runs the effect for `seconds` before moving to the next favorite.
+    pub fn time(mut self, seconds: u32) -> Self {
+        self.t = Some(seconds);
+        self
+    }
+
+    /// Sets the intensity, 0-100.
+    pub fn intensity(mut self, intensity: u32) -> Self {
+        self.int = Some(intensity);
+        self
+    }
+
+    /// Sets the speed, 1-100.
+    pub fn speed(mut self, speed: u32) -> Self {
+        self.spd = Some(speed);
+        self
+    }
+
+    /// Runs the effect in reverse.
+    pub fn reverse(mut self, reverse: bool) -> Self {
+        self.dir = Some(reverse as u32);
+        self
+    }
+
+    /// Sets up to 3 custom colors.
+    pub fn colors(mut self, colors: impl IntoIterator<Item = Color>) -> Self {
+        self.colors = Some(colors.into_iter().collect());
+        self
+    }
+}
+
+/// Name of an effect built into a display.
+///
+/// Effects are picked by name, so any name the display knows works through
+/// [`Other`](Self::Other). The named variants are the ones the specification uses.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone)]
+#[serde(from = "String", into = "String")]
+pub enum EffectName {
+    /// `Multi Chaser`
+    MultiChaser,
+    /// `Shift`
+    Shift,
+    /// Any other effect, by its exact name
+    Other(String),
+}
+
+impl EffectName {
+    /// The name as sent to the display.
+    pub fn as_str(&self) -> &str {
+        match self {
+            EffectName::MultiChaser => "Multi Chaser",
+            EffectName::Shift => "Shift",
+            EffectName::Other(name) => name,
+        }
+    }
+}
+
+impl From<String> for EffectName {
+    fn from(name: String) -> Self {
+        match name.as_str() {
+            "Multi Chaser" => EffectName::MultiChaser,
+            "Shift" => EffectName::Shift,
+            _ => EffectName::Other(name),
+        }
+    }
+}
+
+impl From<&str> for EffectName {
+    fn from(name: &str) -> Self {
+        EffectName::from(name.to_string())
+    }
+}
+
+impl From<EffectName> for String {
+    fn from(name: EffectName) -> Self {
+        match name {
+            EffectName::Other(name) => name,
+            name => name.as_str().to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for EffectName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Hash, Clone)]
@@ -62,6 +598,20 @@ pub struct Color {
     pub r: u32,
     pub g: u32,
     pub b: u32,
+    /// Fields not modelled here
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl From<[u8; 3]> for Color {
+    fn from([r, g, b]: [u8; 3]) -> Self {
+        Color {
+            r: r as u32,
+            g: g as u32,
+            b: b as u32,
+            extra: Map::new(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
@@ -99,9 +649,9 @@ impl Message {
     }
 }
 
-impl Into<ID> for Message {
-    fn into(self) -> ID {
-        match self {
+impl From<Message> for ID {
+    fn from(msg: Message) -> Self {
+        match msg {
             Message::Control(_) => crate::protocol::ID::Control,
             Message::Status(_) => crate::protocol::ID::Status,
             Message::Config(_) => crate::protocol::ID::Config,
@@ -111,6 +661,29 @@ impl Into<ID> for Message {
     }
 }
 
+impl std::fmt::Display for Message {
+    /// Renders the message as JSON, pretty printed in the alternate form (`{:#}`).
+    /// Unparsed messages are written as they were received.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn json<T: Serialize>(f: &mut std::fmt::Formatter<'_>, value: &T) -> std::fmt::Result {
+            let s = if f.alternate() {
+                serde_json::to_string_pretty(value)
+            } else {
+                serde_json::to_string(value)
+            };
+            f.write_str(&s.map_err(|_| std::fmt::Error)?)
+        }
+
+        match self {
+            Message::Control(c) => json(f, c),
+            Message::Status(s) => json(f, s),
+            Message::Config(c) => json(f, c),
+            Message::Parsed((_, v)) => json(f, v),
+            Message::Unparsed((_, s)) => f.write_str(s),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -124,4 +697,240 @@ mod tests {
         let vm: Vec<u8> = msg.try_into().unwrap();
         assert_eq!(vm, b"null");
     }
+
+    fn config() -> Config {
+        Config {
+            ip: Some("10.0.0.2".to_string()),
+            nm: Some("255.255.255.0".to_string()),
+            ports: vec![Port::new(0).length(100), Port::new(1).start(100).length(50)],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_config_typed_editing() {
+        let mut config = config();
+        assert_eq!(config.ip(), Some(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(config.gateway(), None);
+
+        config.set_ip(Ipv4Addr::new(10, 0, 0, 3));
+        config.set_netmask(Ipv4Addr::new(255, 255, 0, 0));
+        config.set_gateway(Ipv4Addr::new(10, 0, 0, 1));
+        config.port_mut(1).l = 60;
+        config.port_mut(2).ss = 160;
+
+        assert_eq!(config.ip.as_deref(), Some("10.0.0.3"));
+        assert_eq!(config.netmask(), Some(Ipv4Addr::new(255, 255, 0, 0)));
+        assert_eq!(config.gateway(), Some(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(config.port(1).unwrap().l, 60);
+        assert_eq!(config.port(2), Some(&Port::new(2).start(160)));
+    }
+
+    #[test]
+    fn test_config_merge() {
+        let mut config = config();
+        config.merge(&Config {
+            gw: Some("10.0.0.1".to_string()),
+            ports: vec![Port::new(1).start(0).length(10), Port::new(3)],
+            ..Default::default()
+        });
+
+        assert_eq!(config.ip.as_deref(), Some("10.0.0.2"));
+        assert_eq!(config.gw.as_deref(), Some("10.0.0.1"));
+        assert_eq!(config.port(0).unwrap().l, 100);
+        assert_eq!(config.port(1), Some(&Port::new(1).length(10)));
+        assert_eq!(config.ports.len(), 3);
+    }
+
+    #[test]
+    fn test_config_diff() {
+        let old = config();
+        assert!(old.diff(&old).is_empty());
+        assert_eq!(old.diff(&old).to_string(), "no changes");
+
+        let mut new = old.clone();
+        new.set_ip(Ipv4Addr::new(10, 0, 0, 3));
+        new.set_gateway(Ipv4Addr::new(10, 0, 0, 1));
+        new.port_mut(1).ss = 0;
+        new.ports.retain(|p| p.port != 0);
+        new.port_mut(2);
+
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff.to_string(),
+            "ip: 10.0.0.2 -> 10.0.0.3, gw: - -> 10.0.0.1, ports[0]: {\"port\":0,\"ts\":0,\"l\":100,\"ss\":0} -> -, \
+             ports[1].ss: 100 -> 0, ports[2]: - -> {\"port\":2,\"ts\":0,\"l\":0,\"ss\":0}"
+        );
+        assert_eq!(diff.get("ports[1].ss").unwrap().new.as_deref(), Some("0"));
+        assert!(diff.get("nm").is_none());
+    }
+
+    #[test]
+    fn test_control_vocabulary() {
+        let control: ControlRoot = serde_json::from_str(
+            r#"{"control":{"fx":"Multi Chaser","int":100,"spd":50,"dir":1,
+                "colors":[{"r":255,"g":0,"b":0}],"save":1,"brightness":7}}"#,
+        )
+        .unwrap();
+        let control = control.control;
+        assert_eq!(control.fx, Some(EffectName::MultiChaser));
+        assert_eq!(control.spd, Some(50));
+        assert_eq!(control.extra["brightness"], 7);
+
+        // Unknown fields go back out, unset ones don't
+        let json = serde_json::to_value(&control).unwrap();
+        assert_eq!(json["brightness"], 7);
+        assert!(json.get("power").is_none());
+
+        assert_eq!(
+            serde_json::to_string(&Control::power(false)).unwrap(),
+            r#"{"power":0}"#
+        );
+        assert_eq!(
+            EffectName::from("Sparkle"),
+            EffectName::Other("Sparkle".to_string())
+        );
+        assert_eq!(EffectName::Shift.to_string(), "Shift");
+    }
+
+    #[test]
+    fn test_control_favorites() {
+        let json = r#"{"control":{"favorites":[
+            {"i":1,"fx":"Multi Chaser","t":60,"int":100,"colors":[{"r":255,"g":0,"b":0},{"r":0,"g":255,"b":0}]},
+            {"i":2,"fx":"Shift","t":60,"int":100,"colors":[{"r":0,"g":255,"b":255},{"r":20,"g":20,"b":20}]}]}}"#;
+        let control: ControlRoot = serde_json::from_str(json).unwrap();
+        let favorites = control.control.favorites.as_ref().unwrap();
+        assert_eq!(favorites.len(), 2);
+        assert_eq!(
+            favorites[1],
+            Favorite::new(2, EffectName::Shift)
+                .time(60)
+                .intensity(100)
+                .colors([Color::from([0, 255, 255]), Color::from([20, 20, 20])])
+        );
+        assert!(control.control.validate().is_ok());
+    }
+
+    #[test]
+    fn test_control_validate() {
+        let invalid = |control: Control| match control.validate() {
+            Err(DDPError::InvalidControl(e)) => e,
+            r => panic!("expected an error, got {:?}", r),
+        };
+
+        assert!(Control::effect("Shift")
+            .speed(1)
+            .reverse(true)
+            .save()
+            .validate()
+            .is_ok());
+        assert_eq!(
+            invalid(Control::effect("Shift").intensity(101)),
+            "intensity 101 is outside 0-100"
+        );
+        assert_eq!(
+            invalid(Control::effect("Shift").speed(0)),
+            "speed 0 is outside 1-100"
+        );
+        assert_eq!(
+            invalid(Control {
+                dir: Some(2),
+                ..Default::default()
+            }),
+            "direction 2 is outside 0-1"
+        );
+        assert_eq!(
+            invalid(Control {
+                power: Some(1),
+                fx: Some(EffectName::Shift),
+                ..Default::default()
+            }),
+            "power should be sent on its own"
+        );
+        assert_eq!(
+            invalid(Control::effect("Shift").colors(vec![Color::from([0, 0, 0]); 4])),
+            "4 colors given, at most 3 are allowed"
+        );
+        assert_eq!(
+            invalid(Control::effect("Shift").colors([Color {
+                r: 256,
+                g: 0,
+                b: 0,
+                extra: Map::new()
+            }])),
+            "red 256 is outside 0-255"
+        );
+        assert_eq!(
+            invalid(Control::favorites(vec![Favorite::new(11, "Shift")])),
+            "favorite index 11 is outside 1-10"
+        );
+        assert_eq!(
+            invalid(Control::favorites(vec![
+                Favorite::new(1, "Shift").time(40000)
+            ])),
+            "favorite time 40000 is outside 0-32500"
+        );
+    }
+
+    #[test]
+    fn test_unknown_fields_round_trip() {
+        let messages = [
+            r#"{"status":{"man":"Minleon","mod":"NDB","ver":"2.1","uptime":3600,"wifi":{"rssi":-60}},"seq":4}"#,
+            r#"{"config":{"ip":"10.0.0.2","ports":[{"port":1,"ts":0,"l":50,"ss":0,"brightness":80}],"alpha":[1,2],"zeta":1}}"#,
+            r#"{"control":{"fx":"Shift","colors":[{"r":1,"g":2,"b":3,"w":4}],"mode":"auto"}}"#,
+        ];
+
+        for json in messages {
+            let message = if json.starts_with(r#"{"status""#) {
+                Message::Status(serde_json::from_str(json).unwrap())
+            } else if json.starts_with(r#"{"config""#) {
+                Message::Config(serde_json::from_str(json).unwrap())
+            } else {
+                Message::Control(serde_json::from_str(json).unwrap())
+            };
+            let bytes: Vec<u8> = message.try_into().unwrap();
+            assert_eq!(String::from_utf8(bytes).unwrap(), json);
+        }
+    }
+
+    #[cfg(feature = "preserve_order")]
+    #[test]
+    fn test_unknown_fields_keep_order() {
+        let json = r#"{"config":{"ip":"10.0.0.2","zeta":1,"alpha":[1,2]}}"#;
+        let message = Message::Config(serde_json::from_str(json).unwrap());
+        let bytes: Vec<u8> = message.try_into().unwrap();
+        assert_eq!(String::from_utf8(bytes).unwrap(), json);
+    }
+
+    #[test]
+    fn test_config_extra_merge_and_diff() {
+        let mut config = config();
+        config.extra.insert("name".into(), "tree".into());
+        config.ports[0].extra.insert("rgb".into(), "GRB".into());
+
+        let mut changes = Config::default();
+        changes.extra.insert("name".into(), "star".into());
+        changes.extra.insert("dhcp".into(), true.into());
+
+        let old = config.clone();
+        config.merge(&changes);
+        assert_eq!(config.extra["name"], "star");
+        assert_eq!(config.ports[0].extra["rgb"], "GRB");
+
+        config.ports[0].extra.remove("rgb");
+        assert_eq!(
+            old.diff(&config).to_string(),
+            r#"name: "tree" -> "star", dhcp: - -> true, ports[0].rgb: "GRB" -> -"#
+        );
+    }
+
+    #[test]
+    fn test_message_display() {
+        let msg = Message::Parsed((ID::Control, serde_json::json!({"power": 1})));
+        assert_eq!(msg.to_string(), r#"{"power":1}"#);
+        assert_eq!(format!("{:#}", msg), "{\n  \"power\": 1\n}");
+
+        let msg = Message::Unparsed((ID::Status, "not json".to_string()));
+        assert_eq!(msg.to_string(), "not json");
+    }
 }
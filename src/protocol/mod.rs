@@ -23,6 +23,9 @@
 pub mod packet_type;
 pub use packet_type::*;
 
+mod builder;
+pub use builder::HeaderBuilder;
+
 pub mod pixel_config;
 pub use pixel_config::{DataType, PixelConfig, PixelFormat};
 
@@ -31,6 +34,8 @@ pub use id::ID;
 
 pub mod message;
 
+pub mod profile;
+
 pub mod timecode;
 use timecode::TimeCode;
 
@@ -81,65 +86,65 @@ pub struct Header {
     pub time_code: TimeCode,
 }
 
-impl Into<[u8; 10]> for Header {
-    fn into(self) -> [u8; 10] {
+impl From<Header> for [u8; 10] {
+    fn from(header: Header) -> Self {
         // Define a byte array with the size of the header
         let mut buffer: [u8; 10] = [0u8; 10];
 
         // Write the packet type field to the buffer
 
-        let packet_type_byte: u8 = self.packet_type.into();
+        let packet_type_byte: u8 = header.packet_type.into();
         buffer[0] = packet_type_byte;
 
         // Write the sequence number field to the buffer
-        buffer[1] = self.sequence_number;
+        buffer[1] = header.sequence_number;
 
         // Write the pixel config field to the buffer
-        buffer[2] = self.pixel_config.into();
+        buffer[2] = header.pixel_config.into();
 
         // Write the id field to the buffer
-        buffer[3] = self.id.into();
+        buffer[3] = header.id.into();
 
         // Write the offset field to the buffer
-        let offset_bytes = self.offset.to_be_bytes();
+        let offset_bytes = header.offset.to_be_bytes();
         buffer[4..8].copy_from_slice(&offset_bytes);
 
         // Write the length field to the buffer
-        let length_bytes = self.length.to_be_bytes();
+        let length_bytes = header.length.to_be_bytes();
         buffer[8..10].copy_from_slice(&length_bytes);
 
         // Return a slice of the buffer representing the entire header
         buffer
     }
 }
-impl Into<[u8; 14]> for Header {
-    fn into(self) -> [u8; 14] {
+impl From<Header> for [u8; 14] {
+    fn from(header: Header) -> Self {
         // Define a byte array with the size of the header
         let mut buffer = [0u8; 14];
 
         // Write the packet type field to the buffer
 
-        let packet_type_byte: u8 = self.packet_type.into();
+        let packet_type_byte: u8 = header.packet_type.into();
         buffer[0] = packet_type_byte;
 
         // Write the sequence number field to the buffer
-        buffer[1] = self.sequence_number;
+        buffer[1] = header.sequence_number;
 
         // Write the pixel config field to the buffer
-        buffer[2] = self.pixel_config.into();
+        buffer[2] = header.pixel_config.into();
 
         // Write the id field to the buffer
-        buffer[3] = self.id.into();
+        buffer[3] = header.id.into();
 
         // Write the offset field to the buffer
-        let offset_bytes: [u8; 4] = self.offset.to_be_bytes();
+        let offset_bytes: [u8; 4] = header.offset.to_be_bytes();
         buffer[4..8].copy_from_slice(&offset_bytes);
 
         // Write the length field to the buffer
-        let length_bytes: [u8; 2] = self.length.to_be_bytes();
+        let length_bytes: [u8; 2] = header.length.to_be_bytes();
         buffer[8..10].copy_from_slice(&length_bytes);
 
-        let time_code: [u8; 4] = self.time_code.to_bytes();
+        let time_code: [u8; 4] = header.time_code.to_bytes();
         buffer[10..14].copy_from_slice(&time_code);
 
         // Return a slice of the buffer representing the entire header
@@ -193,6 +198,47 @@ impl<'a> From<&'a [u8]> for Header {
     }
 }
 
+impl std::fmt::Display for Header {
+    /// Renders every header field on one line, or one field per line in the
+    /// alternate form (`{:#}`).
+    ///
+    /// ```
+    /// use ddp_rs::protocol::Header;
+    ///
+    /// let header = Header::from(&[0x41, 0x01, 0x0D, 0x01, 0, 0, 0, 0, 0, 0x09][..]);
+    /// assert_eq!(
+    ///     header.to_string(),
+    ///     "v1 ----P seq=1 RGB 24 bits id=default (1) offset=0 len=9"
+    /// );
+    /// ```
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            writeln!(f, "flags:     {:#}", self.packet_type)?;
+            writeln!(f, "sequence:  {}", self.sequence_number)?;
+            writeln!(f, "pixels:    {}", self.pixel_config)?;
+            writeln!(f, "id:        {}", self.id)?;
+            writeln!(f, "offset:    {}", self.offset)?;
+            writeln!(f, "length:    {}", self.length)?;
+            write!(f, "timecode:  {}", self.time_code)
+        } else {
+            write!(
+                f,
+                "{} seq={} {} id={} offset={} len={}",
+                self.packet_type,
+                self.sequence_number,
+                self.pixel_config,
+                self.id,
+                self.offset,
+                self.length
+            )?;
+            if self.time_code.0.is_some() {
+                write!(f, " tc={}", self.time_code)?;
+            }
+            Ok(())
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -334,8 +380,10 @@ mod tests {
         fn test_header_offset_range(
             offset in 0u32..=0xFFFFFFFF,
         ) {
-            let mut header = Header::default();
-            header.offset = offset;
+            let header = Header {
+                offset,
+                ..Default::default()
+            };
 
             let bytes: [u8; 10] = header.into();
             let parsed = Header::from(&bytes[..]);
@@ -347,8 +395,10 @@ mod tests {
         fn test_header_length_range(
             length in 0u16..=1500,
         ) {
-            let mut header = Header::default();
-            header.length = length;
+            let header = Header {
+                length,
+                ..Default::default()
+            };
 
             let bytes: [u8; 10] = header.into();
             let parsed = Header::from(&bytes[..]);
@@ -356,4 +406,25 @@ mod tests {
             prop_assert_eq!(parsed.length, length);
         }
     }
+
+    #[test]
+    fn test_header_display_alternate() {
+        let bytes = [0x51, 0x03, 0x1E, 0xFB, 0, 0, 0, 3, 0, 4, 0, 1, 0x80, 0];
+        let header = Header::from(&bytes[..]);
+
+        assert_eq!(
+            header.to_string(),
+            "v1 T---P seq=3 RGBW 32 bits id=status (251) offset=3 len=4 tc=98304 (1.500000s)"
+        );
+        assert_eq!(
+            format!("{:#}", header),
+            "flags:     version 1, timecode yes, storage no, reply no, query no, push yes\n\
+             sequence:  3\n\
+             pixels:    RGBW 32 bits\n\
+             id:        status (251)\n\
+             offset:    3\n\
+             length:    4\n\
+             timecode:  98304 (1.500000s)"
+        );
+    }
 }
@@ -97,31 +97,31 @@ impl From<u8> for PacketType {
     }
 }
 
-impl Into<u8> for PacketType {
-    fn into(self) -> u8 {
+impl From<PacketType> for u8 {
+    fn from(packet_type: PacketType) -> Self {
         let mut byte: u8 = 0;
-        let v = match self.version {
-            1 => self.version,
-            2 => self.version,
-            3 => self.version,
-            4 => self.version,
+        let v = match packet_type.version {
+            1 => packet_type.version,
+            2 => packet_type.version,
+            3 => packet_type.version,
+            4 => packet_type.version,
             _ => 0,
         };
         byte |= v << 6;
         // Set the flag bits
-        if self.timecode {
+        if packet_type.timecode {
             byte |= TIMECODE
         };
-        if self.storage {
+        if packet_type.storage {
             byte |= STORAGE
         };
-        if self.reply {
+        if packet_type.reply {
             byte |= REPLY
         };
-        if self.query {
+        if packet_type.query {
             byte |= QUERY
         };
-        if self.push {
+        if packet_type.push {
             byte |= PUSH
         };
 
@@ -129,6 +129,36 @@ impl Into<u8> for PacketType {
     }
 }
 
+impl std::fmt::Display for PacketType {
+    /// Renders the version and every flag, e.g. `v1 ----P`.
+    ///
+    /// Flags are timecode, storage, reply, query and push, each shown by its initial
+    /// when set. The alternate form (`{:#}`) spells them out.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flags = [
+            (self.timecode, 'T', "timecode"),
+            (self.storage, 'S', "storage"),
+            (self.reply, 'R', "reply"),
+            (self.query, 'Q', "query"),
+            (self.push, 'P', "push"),
+        ];
+
+        if f.alternate() {
+            write!(f, "version {}", self.version)?;
+            for (set, _, name) in flags {
+                write!(f, ", {} {}", name, if set { "yes" } else { "no" })?;
+            }
+            Ok(())
+        } else {
+            write!(f, "v{} ", self.version)?;
+            for (set, initial, _) in flags {
+                write!(f, "{}", if set { initial } else { '-' })?;
+            }
+            Ok(())
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -165,4 +195,15 @@ mod tests {
         let byte: u8 = packet_type.into();
         assert_eq!(byte, 0x56);
     }
+
+    #[test]
+    fn test_packet_type_display() {
+        let packet_type = PacketType::from(0x45);
+        assert_eq!(packet_type.to_string(), "v1 --R-P");
+        assert_eq!(
+            format!("{:#}", packet_type),
+            "version 1, timecode no, storage no, reply yes, query no, push yes"
+        );
+        assert_eq!(PacketType::from(0x5f).to_string(), "v1 TSRQP");
+    }
 }
@@ -17,6 +17,19 @@ pub enum DataType {
     Grayscale,
 }
 
+impl DataType {
+    /// Number of channels (bytes at 8 bits per channel) that make up one pixel.
+    ///
+    /// Undefined data is treated as RGB, which is what most displays assume.
+    pub fn channels(&self) -> usize {
+        match self {
+            DataType::Undefined | DataType::RGB | DataType::HSL => 3,
+            DataType::RGBW => 4,
+            DataType::Grayscale => 1,
+        }
+    }
+}
+
 /// Number of bits per pixel.
 ///
 /// Defines the bit depth for each pixel's data.
@@ -107,11 +120,11 @@ impl From<u8> for PixelConfig {
     }
 }
 
-impl Into<u8> for PixelConfig {
-    fn into(self) -> u8 {
+impl From<PixelConfig> for u8 {
+    fn from(config: PixelConfig) -> Self {
         let mut byte = 0u8;
 
-        byte |= match self.data_type {
+        byte |= match config.data_type {
             DataType::Undefined => 0,
             DataType::RGB => 1,
             DataType::HSL => 2,
@@ -119,7 +132,7 @@ impl Into<u8> for PixelConfig {
             DataType::Grayscale => 4,
         } << 3;
 
-        byte |= match self.data_size {
+        byte |= match config.data_size {
             PixelFormat::Undefined => 0,
             PixelFormat::Pixel1Bits => 1,
             PixelFormat::Pixel4Bits => 2,
@@ -129,7 +142,7 @@ impl Into<u8> for PixelConfig {
             PixelFormat::Pixel32Bits => 6,
         };
 
-        if self.customer_defined {
+        if config.customer_defined {
             byte |= 0x80;
         }
 
@@ -147,6 +160,44 @@ impl Default for PixelConfig {
     }
 }
 
+impl std::fmt::Display for DataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DataType::Undefined => "undefined",
+            DataType::RGB => "RGB",
+            DataType::HSL => "HSL",
+            DataType::RGBW => "RGBW",
+            DataType::Grayscale => "grayscale",
+        })
+    }
+}
+
+impl std::fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bits = match self {
+            PixelFormat::Undefined => return f.write_str("undefined size"),
+            PixelFormat::Pixel1Bits => 1,
+            PixelFormat::Pixel4Bits => 4,
+            PixelFormat::Pixel8Bits => 8,
+            PixelFormat::Pixel16Bits => 16,
+            PixelFormat::Pixel24Bits => 24,
+            PixelFormat::Pixel32Bits => 32,
+        };
+        write!(f, "{} bits", bits)
+    }
+}
+
+impl std::fmt::Display for PixelConfig {
+    /// Renders the decoded configuration, e.g. `RGB 24 bits`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.data_type, self.data_size)?;
+        if self.customer_defined {
+            f.write_str(" (customer defined)")?;
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -183,4 +234,23 @@ mod tests {
             assert!(!pixel_config.customer_defined);
         }
     }
+
+    #[test]
+    fn test_data_type_channels() {
+        assert_eq!(DataType::RGB.channels(), 3);
+        assert_eq!(DataType::HSL.channels(), 3);
+        assert_eq!(DataType::RGBW.channels(), 4);
+        assert_eq!(DataType::Grayscale.channels(), 1);
+        assert_eq!(DataType::Undefined.channels(), 3);
+    }
+
+    #[test]
+    fn test_pixel_config_display() {
+        assert_eq!(PixelConfig::default().to_string(), "RGB 24 bits");
+        assert_eq!(
+            PixelConfig::from(0x9e).to_string(),
+            "RGBW 32 bits (customer defined)"
+        );
+        assert_eq!(PixelConfig::from(0).to_string(), "undefined undefined size");
+    }
 }
@@ -0,0 +1,508 @@
+//! Vendor profiles, which recognize a display from its STATUS reply and read the
+//! fields its vendor adds to the JSON messages.
+//!
+//! The specification only defines a few fields, and displays add their own next to
+//! them. Those end up in the `extra` maps of the [message](super::message) structs;
+//! a [`Profile`] turns them into a typed [`Extension`].
+//!
+//! A [`Registry`] holds the profiles to try. [`Registry::default`] has the built-in
+//! ones for WLED, Minleon, xLights and FPP, and more can be
+//! [registered](Registry::register).
+//!
+//! # Examples
+//!
+//! ```
+//! use ddp_rs::protocol::message::Message;
+//! use ddp_rs::protocol::profile::{Extension, Registry};
+//!
+//! let message = Message::Status(serde_json::from_str(
+//!     r#"{"status":{"man":"FPP","mod":"Raspberry Pi 4","HostName":"garage","mode_name":"remote"}}"#,
+//! ).unwrap());
+//! let Message::Status(status) = &message else { unreachable!() };
+//!
+//! let registry = Registry::default();
+//! assert_eq!(registry.identify(&status.status).unwrap().name(), "fpp");
+//!
+//! match registry.parse(&status.status, &message).unwrap() {
+//!     Some(Extension::Fpp(fpp)) => assert_eq!(fpp.host_name.as_deref(), Some("garage")),
+//!     other => panic!("unexpected {:?}", other),
+//! }
+//! ```
+
+use crate::error::DDPError;
+use crate::protocol::message::{Favorite, Message, Status};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::any::Any;
+use std::fmt::Debug;
+
+/// Recognizes one vendor's displays and parses their extensions.
+///
+/// # Examples
+///
+/// ```
+/// use ddp_rs::error::DDPError;
+/// use ddp_rs::protocol::message::{Message, Status};
+/// use ddp_rs::protocol::profile::{self, Extension, Profile, Registry};
+///
+/// #[derive(Debug)]
+/// struct Acme;
+///
+/// #[derive(Debug, serde::Deserialize)]
+/// struct AcmeFields {
+///     zones: Option<u32>,
+/// }
+///
+/// impl Profile for Acme {
+///     fn name(&self) -> &str {
+///         "acme"
+///     }
+///
+///     fn matches(&self, status: &Status) -> bool {
+///         status.man.as_deref() == Some("Acme")
+///     }
+///
+///     fn parse(&self, message: &Message) -> Result<Option<Extension>, DDPError> {
+///         let fields: AcmeFields = profile::parse_fields(message)?;
+///         Ok(Some(Extension::Custom(Box::new(fields))))
+///     }
+/// }
+///
+/// let mut registry = Registry::default();
+/// registry.register(Acme);
+///
+/// let message = Message::Status(serde_json::from_str(r#"{"status":{"man":"Acme","zones":4}}"#).unwrap());
+/// let Message::Status(status) = &message else { unreachable!() };
+/// let extension = registry.parse(&status.status, &message).unwrap().unwrap();
+/// assert_eq!(extension.downcast_ref::<AcmeFields>().unwrap().zones, Some(4));
+/// ```
+pub trait Profile: Debug + Send + Sync {
+    /// Short name of the profile, such as `wled`.
+    fn name(&self) -> &str;
+
+    /// Whether the display that sent `status` is one this profile handles.
+    fn matches(&self, status: &Status) -> bool;
+
+    /// Parses the vendor fields of a message from a matching display.
+    ///
+    /// Returns `None` for messages the vendor doesn't extend.
+    fn parse(&self, message: &Message) -> Result<Option<Extension>, DDPError>;
+}
+
+/// Vendor fields of a message, parsed by a [`Profile`].
+#[derive(Debug)]
+pub enum Extension {
+    Wled(WledExtension),
+    Minleon(MinleonExtension),
+    XLights(XLightsExtension),
+    Fpp(FppExtension),
+    /// From a profile outside this crate, read with [`downcast_ref`](Self::downcast_ref)
+    Custom(Box<dyn Any + Send + Sync>),
+}
+
+impl Extension {
+    /// The value of a [`Custom`](Self::Custom) extension, if it is a `T`.
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        match self {
+            Extension::Custom(value) => value.downcast_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// Fields WLED adds to its STATUS reply, following its JSON `info` object.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+#[serde(default)]
+pub struct WledExtension {
+    /// Name given to the display
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Chip the firmware runs on, such as `esp32`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+    /// Build number of the firmware
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vid: Option<u64>,
+    /// The LED setup
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub leds: Option<WledLeds>,
+    /// Fields not modelled here
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The `leds` object of a [`WledExtension`].
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+#[serde(default)]
+pub struct WledLeds {
+    /// Number of LEDs configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<u32>,
+    /// Frames per second currently being shown
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fps: Option<u32>,
+    /// Current limit in milliamps, 0 when unlimited
+    #[serde(rename = "maxpwr", skip_serializing_if = "Option::is_none")]
+    pub max_power: Option<u32>,
+    /// Whether any output has a white channel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rgbw: Option<bool>,
+    /// Fields not modelled here
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Fields Minleon controllers add, including the favorites list of a CONTROL reply.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+#[serde(default)]
+pub struct MinleonExtension {
+    /// The favorites list, from a CONTROL reply
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub favorites: Vec<Favorite>,
+    /// Fields not modelled here
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Fields xLights adds to its messages.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+#[serde(default)]
+pub struct XLightsExtension {
+    /// Name of the controller in the xLights setup
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Fields not modelled here
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Fields Falcon Player (FPP) adds to its STATUS reply.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+#[serde(default)]
+pub struct FppExtension {
+    /// Host name of the player
+    #[serde(rename = "HostName", skip_serializing_if = "Option::is_none")]
+    pub host_name: Option<String>,
+    /// Mode the player runs in, such as `player` or `remote`
+    #[serde(rename = "mode_name", skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    /// Hardware platform, such as `Raspberry Pi`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    /// Hardware model within the platform
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    /// Fields not modelled here
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// The fields of `message` that the specification doesn't define.
+///
+/// These are the `extra` fields of the status, config or control, or the whole
+/// object of any other parsed message.
+pub fn vendor_fields(message: &Message) -> Map<String, Value> {
+    match message {
+        Message::Status(s) => s.status.extra.clone(),
+        Message::Config(c) => c.config.extra.clone(),
+        Message::Control(c) => c.control.extra.clone(),
+        Message::Parsed((_, Value::Object(map))) => map.clone(),
+        Message::Parsed(_) | Message::Unparsed(_) => Map::new(),
+    }
+}
+
+/// Parses the [vendor fields](vendor_fields) of `message` into a `T`.
+pub fn parse_fields<T: DeserializeOwned>(message: &Message) -> Result<T, DDPError> {
+    Ok(serde_json::from_value(Value::Object(vendor_fields(
+        message,
+    )))?)
+}
+
+/// Whether the manufacturer or model of `status` contains one of `names`, ignoring
+/// case.
+fn names_any(status: &Status, names: &[&str]) -> bool {
+    [&status.man, &status.model]
+        .into_iter()
+        .flatten()
+        .map(|s| s.to_lowercase())
+        .any(|s| names.iter().any(|name| s.contains(name)))
+}
+
+/// WLED firmware.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Wled;
+
+impl Profile for Wled {
+    fn name(&self) -> &str {
+        "wled"
+    }
+
+    fn matches(&self, status: &Status) -> bool {
+        names_any(status, &["wled"])
+    }
+
+    fn parse(&self, message: &Message) -> Result<Option<Extension>, DDPError> {
+        match message {
+            Message::Status(_) => Ok(Some(Extension::Wled(parse_fields(message)?))),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Minleon controllers, which take effects and favorites over CONTROL messages.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Minleon;
+
+impl Profile for Minleon {
+    fn name(&self) -> &str {
+        "minleon"
+    }
+
+    fn matches(&self, status: &Status) -> bool {
+        names_any(status, &["minleon"])
+    }
+
+    fn parse(&self, message: &Message) -> Result<Option<Extension>, DDPError> {
+        let favorites = match message {
+            Message::Control(c) => c.control.favorites.clone().unwrap_or_default(),
+            Message::Status(_) | Message::Config(_) => Vec::new(),
+            _ => return Ok(None),
+        };
+        let mut extension: MinleonExtension = parse_fields(message)?;
+        extension.favorites = favorites;
+        Ok(Some(Extension::Minleon(extension)))
+    }
+}
+
+/// xLights.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct XLights;
+
+impl Profile for XLights {
+    fn name(&self) -> &str {
+        "xlights"
+    }
+
+    fn matches(&self, status: &Status) -> bool {
+        names_any(status, &["xlights"])
+    }
+
+    fn parse(&self, message: &Message) -> Result<Option<Extension>, DDPError> {
+        match message {
+            Message::Status(_) | Message::Config(_) => {
+                Ok(Some(Extension::XLights(parse_fields(message)?)))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Falcon Player (FPP).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fpp;
+
+impl Profile for Fpp {
+    fn name(&self) -> &str {
+        "fpp"
+    }
+
+    fn matches(&self, status: &Status) -> bool {
+        names_any(status, &["falcon player", "fpp"])
+    }
+
+    fn parse(&self, message: &Message) -> Result<Option<Extension>, DDPError> {
+        match message {
+            Message::Status(_) => Ok(Some(Extension::Fpp(parse_fields(message)?))),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// The profiles to recognize displays with, tried in order.
+#[derive(Debug)]
+pub struct Registry {
+    profiles: Vec<Box<dyn Profile>>,
+}
+
+impl Default for Registry {
+    /// A registry with the built-in profiles.
+    fn default() -> Self {
+        let mut registry = Registry::new();
+        registry.profiles.push(Box::new(Wled));
+        registry.profiles.push(Box::new(Minleon));
+        registry.profiles.push(Box::new(XLights));
+        registry.profiles.push(Box::new(Fpp));
+        registry
+    }
+}
+
+impl Registry {
+    /// A registry with no profiles.
+    pub fn new() -> Self {
+        Registry {
+            profiles: Vec::new(),
+        }
+    }
+
+    /// Adds a profile, tried before the ones already registered.
+    pub fn register<P: Profile + 'static>(&mut self, profile: P) {
+        self.profiles.insert(0, Box::new(profile));
+    }
+
+    /// The registered profiles, in the order they are tried.
+    pub fn profiles(&self) -> impl Iterator<Item = &dyn Profile> {
+        self.profiles.iter().map(|p| p.as_ref())
+    }
+
+    /// The first profile matching the display that sent `status`.
+    pub fn identify(&self, status: &Status) -> Option<&dyn Profile> {
+        self.profiles().find(|p| p.matches(status))
+    }
+
+    /// Parses `message` from the display that sent `status` with its profile.
+    ///
+    /// Returns `None` if no profile matches or it doesn't extend the message.
+    pub fn parse(&self, status: &Status, message: &Message) -> Result<Option<Extension>, DDPError> {
+        match self.identify(status) {
+            Some(profile) => profile.parse(message),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::message::{ControlRoot, StatusRoot};
+    use crate::protocol::ID;
+
+    fn status(json: &str) -> (Status, Message) {
+        let root: StatusRoot = serde_json::from_str(json).unwrap();
+        (root.status.clone(), Message::Status(root))
+    }
+
+    #[test]
+    fn test_identify() {
+        let registry = Registry::default();
+        let name = |json: &str| {
+            registry
+                .identify(&status(json).0)
+                .map(|p| p.name().to_string())
+        };
+
+        assert_eq!(
+            name(r#"{"status":{"man":"WLED","mod":"ESP32"}}"#).as_deref(),
+            Some("wled")
+        );
+        assert_eq!(
+            name(r#"{"status":{"man":"Minleon","mod":"NDB Pro"}}"#).as_deref(),
+            Some("minleon")
+        );
+        assert_eq!(
+            name(r#"{"status":{"mod":"xLights"}}"#).as_deref(),
+            Some("xlights")
+        );
+        assert_eq!(
+            name(r#"{"status":{"man":"Falcon Player"}}"#).as_deref(),
+            Some("fpp")
+        );
+        assert_eq!(name(r#"{"status":{"man":"Other"}}"#), None);
+    }
+
+    #[test]
+    fn test_parse_builtin() {
+        let registry = Registry::default();
+
+        // Fields from a WLED 0.14 info object
+        let (s, m) = status(
+            r#"{"status":{"man":"WLED","ver":"0.14.0","mac":"a0b1c2d3e4f5","name":"Porch",
+                "vid":2310130,"leds":{"count":30,"pwr":0,"fps":42,"maxpwr":850,"maxseg":32,
+                "seglc":[1],"lc":1,"rgbw":false,"wv":0,"cct":0},"str":false,"udpport":21324,
+                "live":false,"arch":"esp32","core":"v3.3.6-16-gcc5440f6a2","freeheap":198452,
+                "uptime":3600,"brand":"WLED","product":"FOSS"}}"#,
+        );
+        match registry.parse(&s, &m).unwrap() {
+            Some(Extension::Wled(wled)) => {
+                assert_eq!(wled.name.as_deref(), Some("Porch"));
+                assert_eq!(wled.arch.as_deref(), Some("esp32"));
+                assert_eq!(wled.vid, Some(2310130));
+                let leds = wled.leds.unwrap();
+                assert_eq!(leds.count, Some(30));
+                assert_eq!(leds.fps, Some(42));
+                assert_eq!(leds.max_power, Some(850));
+                assert_eq!(leds.rgbw, Some(false));
+                assert_eq!(leds.extra["maxseg"], 32);
+                assert_eq!(wled.extra["udpport"], 21324);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let (s, _) = status(r#"{"status":{"man":"Minleon"}}"#);
+        let control: ControlRoot =
+            serde_json::from_str(r#"{"control":{"favorites":[{"i":1,"fx":"Shift","t":60}]}}"#)
+                .unwrap();
+        match registry.parse(&s, &Message::Control(control)).unwrap() {
+            Some(Extension::Minleon(minleon)) => {
+                assert_eq!(minleon.favorites, vec![Favorite::new(1, "Shift").time(60)]);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // WLED doesn't extend CONTROL messages
+        let (s, _) = status(r#"{"status":{"man":"WLED"}}"#);
+        let control = Message::Control(ControlRoot::default());
+        assert!(registry.parse(&s, &control).unwrap().is_none());
+
+        // Nor are messages that aren't a known kind
+        let (s, _) = status(r#"{"status":{"man":"Minleon"}}"#);
+        let parsed = Message::Parsed((ID::Custom(250), Value::from(3)));
+        assert!(registry.parse(&s, &parsed).unwrap().is_none());
+        let unparsed = Message::Unparsed((ID::Custom(250), "{".to_string()));
+        assert!(registry.parse(&s, &unparsed).unwrap().is_none());
+
+        // Vendor fields of the wrong type are an error
+        let (s, m) = status(r#"{"status":{"man":"WLED","leds":"many"}}"#);
+        assert!(matches!(
+            registry.parse(&s, &m),
+            Err(DDPError::ParseError(_))
+        ));
+    }
+
+    #[derive(Debug)]
+    struct Custom;
+
+    impl Profile for Custom {
+        fn name(&self) -> &str {
+            "custom"
+        }
+
+        fn matches(&self, status: &Status) -> bool {
+            status.man.as_deref() == Some("WLED") && status.ver.as_deref() == Some("custom")
+        }
+
+        fn parse(&self, message: &Message) -> Result<Option<Extension>, DDPError> {
+            Ok(Some(Extension::Custom(Box::new(
+                vendor_fields(message).len(),
+            ))))
+        }
+    }
+
+    #[test]
+    fn test_register() {
+        let mut registry = Registry::default();
+        registry.register(Custom);
+        assert_eq!(registry.profiles().count(), 5);
+
+        // Registered profiles go before the built-in ones
+        let (s, m) = status(r#"{"status":{"man":"WLED","ver":"custom","a":1,"b":2}}"#);
+        let extension = registry.parse(&s, &m).unwrap().unwrap();
+        assert_eq!(extension.downcast_ref::<usize>(), Some(&2));
+        assert_eq!(extension.downcast_ref::<String>(), None);
+
+        let (s, _) = status(r#"{"status":{"man":"WLED"}}"#);
+        assert_eq!(registry.identify(&s).unwrap().name(), "wled");
+
+        assert!(Registry::new().identify(&s).is_none());
+    }
+}
@@ -11,6 +11,17 @@ impl TimeCode {
     }
 }
 
+impl std::fmt::Display for TimeCode {
+    /// Renders the raw value and its meaning in seconds (units of 1/65536 s), or
+    /// `none`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(t) => write!(f, "{} ({:.6}s)", t, t as f64 / 65536.0),
+            None => f.write_str("none"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -101,6 +112,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::clone_on_copy)]
     fn test_timecode_clone() {
         let tc1 = TimeCode(Some(12345));
         let tc2 = tc1.clone();
@@ -139,4 +151,10 @@ mod tests {
         assert_eq!(bytes[2], 0x56);
         assert_eq!(bytes[3], 0x78); // Least significant byte last
     }
+
+    #[test]
+    fn test_timecode_display() {
+        assert_eq!(TimeCode(None).to_string(), "none");
+        assert_eq!(TimeCode(Some(98304)).to_string(), "98304 (1.500000s)");
+    }
 }
@@ -0,0 +1,441 @@
+//! Receiving pixel data sent over DDP.
+//!
+//! This is the display side of the protocol: [`FrameAssembler`] collects the data
+//! packets of a frame into one buffer by their offset, and [`DDPReceiver`] does the
+//! same for packets arriving on a UDP socket or TCP connection. Frames are complete
+//! when a packet with the push flag arrives.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use ddp_rs::receiver::DDPReceiver;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut receiver = DDPReceiver::bind("0.0.0.0:4048")?;
+//!
+//! loop {
+//!     let frame = receiver.recv_frame()?;
+//!     println!("got {} bytes", frame.len());
+//! }
+//! # }
+//! ```
+
+use crate::connection::{self, MulticastInterface};
+use crate::error::DDPError;
+use crate::packet::Packet;
+use crate::protocol::ID;
+use crate::transport::{TcpTransport, Transport, UdpTransport};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+/// Largest frame assembled by default, in bytes.
+const DEFAULT_MAX_LEN: usize = 1 << 20;
+
+/// Collects DDP data packets into frames.
+///
+/// # Examples
+///
+/// ```
+/// use ddp_rs::packet::Packet;
+/// use ddp_rs::receiver::FrameAssembler;
+///
+/// let mut assembler = FrameAssembler::new();
+///
+/// // Offset 3, no push flag
+/// let first = Packet::from_bytes(&[0x40, 0x01, 0x0D, 0x01, 0, 0, 0, 3, 0, 3, 4, 5, 6]);
+/// assert!(!assembler.push(&first));
+///
+/// // Offset 0, push flag set
+/// let last = Packet::from_bytes(&[0x41, 0x02, 0x0D, 0x01, 0, 0, 0, 0, 0, 3, 1, 2, 3]);
+/// assert!(assembler.push(&last));
+///
+/// assert_eq!(assembler.frame(), &[1, 2, 3, 4, 5, 6]);
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FrameAssembler {
+    frame: Vec<u8>,
+    max_len: usize,
+}
+
+impl Default for FrameAssembler {
+    fn default() -> Self {
+        FrameAssembler::new()
+    }
+}
+
+impl FrameAssembler {
+    /// Creates an empty assembler.
+    pub fn new() -> Self {
+        FrameAssembler {
+            frame: Vec::new(),
+            max_len: DEFAULT_MAX_LEN,
+        }
+    }
+
+    /// Limits how large the frame may grow. Data past the limit is dropped.
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self.frame.truncate(max_len);
+        self
+    }
+
+    /// Copies the data of `packet` into the frame at its offset.
+    ///
+    /// Queries, replies and JSON messages are ignored. Returns `true` if the packet
+    /// completed a frame.
+    pub fn push(&mut self, packet: &Packet) -> bool {
+        let header = &packet.header;
+        if header.packet_type.query || header.packet_type.reply {
+            return false;
+        }
+        if matches!(
+            header.id,
+            ID::Reserved | ID::Control | ID::Config | ID::Status
+        ) {
+            return false;
+        }
+
+        let start = (header.offset as usize).min(self.max_len);
+        let len = (header.length as usize).min(packet.data.len());
+        let end = (start + len).min(self.max_len);
+
+        if self.frame.len() < end {
+            self.frame.resize(end, 0);
+        }
+        self.frame[start..end].copy_from_slice(&packet.data[..end - start]);
+
+        header.packet_type.push
+    }
+
+    /// The frame as assembled so far.
+    ///
+    /// Data is kept between frames, so a sender updating only part of the display
+    /// leaves the rest untouched.
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /// Clears the frame.
+    pub fn reset(&mut self) {
+        self.frame.clear();
+    }
+}
+
+/// Receives frames sent to a UDP socket or over a TCP connection.
+#[derive(Debug)]
+pub struct DDPReceiver {
+    transport: Box<dyn Transport>,
+    assembler: FrameAssembler,
+    // Sized for the largest datagram so jumbo frames aren't truncated
+    buffer: Vec<u8>,
+}
+
+impl DDPReceiver {
+    /// Binds a receiver to `addr`, usually `0.0.0.0:4048`.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self, DDPError> {
+        Ok(DDPReceiver::from_socket(UdpSocket::bind(addr)?))
+    }
+
+    /// Creates a receiver from an already bound socket.
+    pub fn from_socket(socket: UdpSocket) -> Self {
+        DDPReceiver::with_transport(UdpTransport::new(socket, None))
+    }
+
+    /// Waits for a sender to connect to `listener` and receives from it over TCP.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ddp_rs::receiver::DDPReceiver;
+    /// use std::net::TcpListener;
+    ///
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let listener = TcpListener::bind("0.0.0.0:4048")?;
+    /// loop {
+    ///     let mut receiver = DDPReceiver::accept(&listener)?;
+    ///     std::thread::spawn(move || while let Ok(frame) = receiver.recv_frame() {
+    ///         println!("got {} bytes", frame.len());
+    ///     });
+    /// }
+    /// # }
+    /// ```
+    pub fn accept(listener: &TcpListener) -> Result<Self, DDPError> {
+        let (stream, _) = listener.accept()?;
+        DDPReceiver::from_tcp(stream)
+    }
+
+    /// Receives from a connected TCP stream.
+    ///
+    /// Receiving fails with [`DDPError::Disconnect`] once the sender hangs up.
+    pub fn from_tcp(stream: TcpStream) -> Result<Self, DDPError> {
+        Ok(DDPReceiver::with_transport(TcpTransport::new(stream)?))
+    }
+
+    /// Receives packets arriving on `transport`.
+    pub fn with_transport<T: Transport + 'static>(transport: T) -> Self {
+        DDPReceiver {
+            transport: Box::new(transport),
+            assembler: FrameAssembler::new(),
+            buffer: vec![0u8; 65536],
+        }
+    }
+
+    /// Limits how large received frames may grow, see [`FrameAssembler::max_len`].
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.assembler = self.assembler.max_len(max_len);
+        self
+    }
+
+    /// Receives data sent to the multicast `group` as well.
+    ///
+    /// The receiver should be bound to an unspecified address (`0.0.0.0` or `[::]`)
+    /// on the port the data is sent to. IPv4 interfaces are picked by address and
+    /// IPv6 interfaces by index.
+    pub fn join_multicast(
+        &self,
+        group: IpAddr,
+        interface: MulticastInterface,
+    ) -> Result<(), DDPError> {
+        connection::join_multicast(self.udp_socket()?, group, interface)
+    }
+
+    /// Stops receiving data sent to the multicast `group`.
+    pub fn leave_multicast(
+        &self,
+        group: IpAddr,
+        interface: MulticastInterface,
+    ) -> Result<(), DDPError> {
+        connection::leave_multicast(self.udp_socket()?, group, interface)
+    }
+
+    fn udp_socket(&self) -> Result<&UdpSocket, DDPError> {
+        self.transport
+            .udp_socket()
+            .ok_or_else(|| DDPError::InvalidOption("multicast needs UDP".to_string()))
+    }
+
+    /// Address the receiver is bound to.
+    pub fn local_addr(&self) -> Result<SocketAddr, DDPError> {
+        Ok(self.transport.local_addr()?)
+    }
+
+    /// The frame as assembled so far.
+    pub fn frame(&self) -> &[u8] {
+        self.assembler.frame()
+    }
+
+    /// Blocks until a frame is complete.
+    pub fn recv_frame(&mut self) -> Result<&[u8], DDPError> {
+        loop {
+            if self.next_packet(None)?.1 {
+                return Ok(self.assembler.frame());
+            }
+        }
+    }
+
+    /// Waits up to `timeout` for each packet of a frame.
+    ///
+    /// Returns `Err(DDPError::NothingToReceive)` if nothing arrived in time. With a
+    /// zero timeout, only packets that are already waiting are read.
+    pub fn recv_frame_timeout(&mut self, timeout: Duration) -> Result<&[u8], DDPError> {
+        loop {
+            if self.next_packet(Some(timeout))?.1 {
+                return Ok(self.assembler.frame());
+            }
+        }
+    }
+
+    /// Blocks until a packet arrives and returns it.
+    ///
+    /// Pixel data in the packet is merged into [`frame`](Self::frame) as well; check
+    /// the push flag to know when the frame is complete.
+    pub fn recv_packet(&mut self) -> Result<Packet, DDPError> {
+        Ok(self.next_packet(None)?.0)
+    }
+
+    fn next_packet(&mut self, timeout: Option<Duration>) -> Result<(Packet, bool), DDPError> {
+        let size = self
+            .transport
+            .recv(&mut self.buffer, timeout)?
+            .ok_or(DDPError::NothingToReceive)?;
+        let packet = Packet::from_bytes(&self.buffer[..size]);
+        let complete = self.assembler.push(&packet);
+        Ok((packet, complete))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::DDPConnection;
+    use crate::protocol::{Header, PixelConfig};
+    use std::net::Ipv4Addr;
+
+    fn packet(offset: u32, data: &[u8], push: bool) -> Packet {
+        let mut header = Header {
+            offset,
+            length: data.len() as u16,
+            ..Default::default()
+        };
+        header.packet_type.push(push);
+        Packet::from_data(header, data)
+    }
+
+    #[test]
+    fn test_assemble_out_of_order() {
+        let mut assembler = FrameAssembler::new();
+        assert!(!assembler.push(&packet(3, &[4, 5, 6], false)));
+        assert!(assembler.push(&packet(0, &[1, 2, 3], true)));
+        assert_eq!(assembler.frame(), &[1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_partial_update_keeps_frame() {
+        let mut assembler = FrameAssembler::new();
+        assembler.push(&packet(0, &[1, 2, 3, 4, 5, 6], true));
+        assembler.push(&packet(3, &[9, 9, 9], true));
+        assert_eq!(assembler.frame(), &[1, 2, 3, 9, 9, 9]);
+
+        assembler.reset();
+        assert!(assembler.frame().is_empty());
+    }
+
+    #[test]
+    fn test_ignores_messages() {
+        let mut assembler = FrameAssembler::new();
+        let mut p = packet(0, b"{}", true);
+        p.header.id = ID::Status;
+        assert!(!assembler.push(&p));
+
+        let mut p = packet(0, &[1, 2, 3], true);
+        p.header.packet_type.query = true;
+        assert!(!assembler.push(&p));
+
+        assert!(assembler.frame().is_empty());
+    }
+
+    #[test]
+    fn test_max_len() {
+        let mut assembler = FrameAssembler::new().max_len(4);
+        assembler.push(&packet(2, &[1, 2, 3], true));
+        assert_eq!(assembler.frame(), &[0, 0, 1, 2]);
+
+        // Entirely out of range
+        assembler.push(&packet(10, &[1], true));
+        assert_eq!(assembler.frame().len(), 4);
+    }
+
+    #[test]
+    fn test_receive_from_connection() {
+        let mut receiver = DDPReceiver::bind("127.0.0.1:0").unwrap();
+        let mut conn = DDPConnection::try_new(
+            receiver.local_addr().unwrap(),
+            PixelConfig::default(),
+            ID::Default,
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+        )
+        .unwrap();
+
+        // Spans two packets
+        let data: Vec<u8> = (0..2000).map(|i| i as u8).collect();
+        conn.write(&data).unwrap();
+
+        let frame = receiver
+            .recv_frame_timeout(Duration::from_millis(500))
+            .unwrap();
+        assert_eq!(frame, &data[..]);
+
+        assert!(matches!(
+            receiver.recv_frame_timeout(Duration::from_millis(10)),
+            Err(DDPError::NothingToReceive)
+        ));
+        assert!(matches!(
+            receiver.recv_frame_timeout(Duration::ZERO),
+            Err(DDPError::NothingToReceive)
+        ));
+    }
+
+    #[test]
+    fn test_receive_multicast() {
+        let group: IpAddr = "239.255.40.49".parse().unwrap();
+        let loopback = MulticastInterface::Addr(Ipv4Addr::LOCALHOST);
+
+        let mut receiver = DDPReceiver::bind("0.0.0.0:0").unwrap();
+        if let Err(e) = receiver.join_multicast(group, loopback) {
+            eprintln!("skipping, no multicast on loopback: {}", e);
+            return;
+        }
+        let mut conn = DDPConnection::builder((group, receiver.local_addr().unwrap().port()))
+            .multicast_interface(loopback)
+            .build()
+            .unwrap();
+
+        conn.write(&[1, 2, 3]).unwrap();
+        let frame = receiver
+            .recv_frame_timeout(Duration::from_millis(500))
+            .unwrap();
+        assert_eq!(frame, &[1, 2, 3]);
+
+        receiver.leave_multicast(group, loopback).unwrap();
+        conn.write(&[4, 5, 6]).unwrap();
+        assert!(matches!(
+            receiver.recv_frame_timeout(Duration::from_millis(50)),
+            Err(DDPError::NothingToReceive)
+        ));
+    }
+
+    #[test]
+    fn test_receive_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut conn = DDPConnection::builder(listener.local_addr().unwrap())
+            .tcp(true)
+            .build()
+            .unwrap();
+        let mut receiver = DDPReceiver::accept(&listener).unwrap();
+        assert!(matches!(
+            receiver.join_multicast("239.1.2.3".parse().unwrap(), MulticastInterface::Any),
+            Err(DDPError::InvalidOption(_))
+        ));
+
+        // Several packets per frame, with timecodes for variable header sizes
+        conn.timecode = crate::connection::Timecode::Elapsed;
+        let data: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+        conn.write(&data).unwrap();
+        conn.write(&data[..30]).unwrap();
+
+        let frame = receiver
+            .recv_frame_timeout(Duration::from_millis(500))
+            .unwrap();
+        assert_eq!(frame, &data[..]);
+        let frame = receiver
+            .recv_frame_timeout(Duration::from_millis(500))
+            .unwrap();
+        assert_eq!(&frame[..30], &data[..30]);
+
+        drop(conn);
+        assert!(matches!(
+            receiver.recv_frame_timeout(Duration::from_millis(500)),
+            Err(DDPError::Disconnect(_))
+        ));
+    }
+
+    #[test]
+    fn test_receive_ipv6() {
+        let mut receiver = match DDPReceiver::bind("[::1]:0") {
+            Ok(receiver) => receiver,
+            Err(e) => {
+                eprintln!("skipping, no IPv6 loopback: {}", e);
+                return;
+            }
+        };
+        let mut conn = DDPConnection::builder(receiver.local_addr().unwrap())
+            .build()
+            .unwrap();
+
+        conn.write(&[1, 2, 3]).unwrap();
+        let frame = receiver
+            .recv_frame_timeout(Duration::from_millis(500))
+            .unwrap();
+        assert_eq!(frame, &[1, 2, 3]);
+    }
+}
@@ -0,0 +1,652 @@
+//! Recording DDP streams to disk and replaying them.
+//!
+//! A [`Recorder`] timestamps either raw packets or assembled frames into a compact
+//! file: a short header followed by one record per entry, each a variable length
+//! time delta, a variable length size and the bytes. A [`Player`] indexes such a
+//! file and replays it to a [`DDPConnection`] with the original timing, optionally
+//! faster or slower, looping, or starting from any point.
+//!
+//! # Examples
+//!
+//! Recording every frame sent to this machine for ten seconds:
+//!
+//! ```no_run
+//! use ddp_rs::receiver::DDPReceiver;
+//! use ddp_rs::record::{RecordKind, Recorder};
+//! use std::fs::File;
+//! use std::io::BufWriter;
+//! use std::time::{Duration, Instant};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut receiver = DDPReceiver::bind("0.0.0.0:4048")?;
+//! let file = BufWriter::new(File::create("rehearsal.ddprec")?);
+//! let mut recorder = Recorder::new(file, RecordKind::Frames)?;
+//!
+//! let start = Instant::now();
+//! while start.elapsed() < Duration::from_secs(10) {
+//!     let frame = receiver.recv_frame()?;
+//!     recorder.record_frame(frame)?;
+//! }
+//! recorder.finish()?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! And playing it back at half speed, forever:
+//!
+//! ```no_run
+//! use ddp_rs::connection::DDPConnection;
+//! use ddp_rs::protocol::{PixelConfig, ID};
+//! use ddp_rs::record::Player;
+//! use std::fs::File;
+//! use std::net::UdpSocket;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut conn = DDPConnection::try_new(
+//!     "192.168.1.40:4048",
+//!     PixelConfig::default(),
+//!     ID::Default,
+//!     UdpSocket::bind("0.0.0.0:4048")?
+//! )?;
+//!
+//! let mut player = Player::open(File::open("rehearsal.ddprec")?)?
+//!     .speed(0.5)
+//!     .looping(true);
+//! player.play(&mut conn)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::connection::DDPConnection;
+use crate::error::DDPError;
+use crate::packet::Packet;
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::time::{Duration, Instant};
+
+const MAGIC: &[u8; 6] = b"DDPREC";
+const VERSION: u8 = 1;
+const HEADER_LEN: u64 = 8;
+/// Shortest time one loop of a recording takes to play.
+const MIN_LOOP_PERIOD: Duration = Duration::from_millis(25);
+
+/// What a recording holds.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum RecordKind {
+    /// Raw DDP packets, headers included, replayed one by one
+    Packets,
+    /// Complete frames of pixel data, replayed with [`DDPConnection::write`]
+    Frames,
+}
+
+impl RecordKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            RecordKind::Packets => 0,
+            RecordKind::Frames => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(RecordKind::Packets),
+            1 => Some(RecordKind::Frames),
+            _ => None,
+        }
+    }
+}
+
+/// Writes timestamped packets or frames to a recording.
+///
+/// Timestamps are taken when an entry is recorded, relative to the first entry,
+/// with microsecond precision. Wrap files in a [`BufWriter`](std::io::BufWriter).
+#[derive(Debug)]
+pub struct Recorder<W: Write> {
+    writer: W,
+    kind: RecordKind,
+    start: Option<Instant>,
+    last: Duration,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Writes the recording header.
+    pub fn new(mut writer: W, kind: RecordKind) -> Result<Self, DDPError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION, kind.to_byte()])?;
+
+        Ok(Recorder {
+            writer,
+            kind,
+            start: None,
+            last: Duration::ZERO,
+        })
+    }
+
+    /// What this recorder holds.
+    pub fn kind(&self) -> RecordKind {
+        self.kind
+    }
+
+    /// Records a packet received now.
+    ///
+    /// Only valid for [`RecordKind::Packets`] recordings.
+    pub fn record_packet(&mut self, packet: &Packet) -> Result<(), DDPError> {
+        self.expect(RecordKind::Packets)?;
+        let at = self.now();
+        self.write_entry(at, &packet.to_bytes()?)
+    }
+
+    /// Records a frame received now.
+    ///
+    /// Only valid for [`RecordKind::Frames`] recordings.
+    pub fn record_frame(&mut self, frame: &[u8]) -> Result<(), DDPError> {
+        self.expect(RecordKind::Frames)?;
+        let at = self.now();
+        self.write_entry(at, frame)
+    }
+
+    /// Records an entry at an explicit time since the start of the recording, for
+    /// example when converting a capture.
+    ///
+    /// `data` is a serialized packet or a frame depending on the kind. Times earlier
+    /// than the previous entry are moved up to it.
+    pub fn record_at(&mut self, at: Duration, data: &[u8]) -> Result<(), DDPError> {
+        self.write_entry(at, data)
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W, DDPError> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn expect(&self, kind: RecordKind) -> Result<(), DDPError> {
+        if self.kind != kind {
+            return Err(DDPError::InvalidCapture(format!(
+                "can't record into a {:?} recording",
+                self.kind
+            )));
+        }
+        Ok(())
+    }
+
+    fn now(&mut self) -> Duration {
+        self.start.get_or_insert_with(Instant::now).elapsed()
+    }
+
+    fn write_entry(&mut self, at: Duration, data: &[u8]) -> Result<(), DDPError> {
+        let at = at.max(self.last);
+        let delta = (at - self.last).as_micros() as u64;
+        // Keep our clock at microsecond precision so rounding doesn't accumulate
+        self.last += Duration::from_micros(delta);
+
+        let mut record = Vec::with_capacity(data.len() + 8);
+        write_varint(&mut record, delta);
+        write_varint(&mut record, data.len() as u64);
+        record.extend_from_slice(data);
+        self.writer.write_all(&record)?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    at: Duration,
+    pos: u64,
+    len: usize,
+}
+
+/// Replays a recording to a [`DDPConnection`].
+///
+/// Opening a recording scans it once to build an index, so seeking is cheap and
+/// only one entry is held in memory at a time.
+#[derive(Debug)]
+pub struct Player<R> {
+    reader: R,
+    kind: RecordKind,
+    entries: Vec<Entry>,
+    cursor: usize,
+    speed: f32,
+    looping: bool,
+}
+
+impl<R: Read + Seek> Player<R> {
+    /// Reads the header and indexes the recording.
+    pub fn open(mut reader: R) -> Result<Self, DDPError> {
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut header = [0u8; HEADER_LEN as usize];
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| invalid("not a DDP recording"))?;
+        if &header[..6] != MAGIC {
+            return Err(invalid("not a DDP recording"));
+        }
+        if header[6] != VERSION {
+            return Err(invalid("unsupported recording version"));
+        }
+        let kind = RecordKind::from_byte(header[7]).ok_or_else(|| invalid("unknown kind"))?;
+
+        // The file is untrusted, so check every length against what is left of it
+        let end = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(HEADER_LEN))?;
+
+        let mut entries = Vec::new();
+        let mut at = Duration::ZERO;
+        let mut pos = HEADER_LEN;
+
+        while let Some((delta, n)) = read_varint(&mut reader)? {
+            let (len, m) = read_varint(&mut reader)?.ok_or_else(|| invalid("truncated record"))?;
+            pos += (n + m) as u64;
+            if len > end.saturating_sub(pos) {
+                return Err(invalid("truncated record"));
+            }
+
+            at = at
+                .checked_add(Duration::from_micros(delta))
+                .ok_or_else(|| invalid("timestamp overflow"))?;
+            entries.push(Entry {
+                at,
+                pos,
+                len: len as usize,
+            });
+
+            pos = reader.seek(SeekFrom::Current(len as i64))?;
+        }
+
+        Ok(Player {
+            reader,
+            kind,
+            entries,
+            cursor: 0,
+            speed: 1.0,
+            looping: false,
+        })
+    }
+
+    /// Plays back faster (> 1.0) or slower (< 1.0) than recorded.
+    pub fn speed(mut self, speed: f32) -> Self {
+        self.speed = speed.max(0.001);
+        self
+    }
+
+    /// Starts over from the beginning when the end is reached, after holding the
+    /// last entry for the average time between entries.
+    pub fn looping(mut self, looping: bool) -> Self {
+        self.looping = looping;
+        self
+    }
+
+    /// What the recording holds.
+    pub fn kind(&self) -> RecordKind {
+        self.kind
+    }
+
+    /// Number of recorded entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the recording is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Time of the last entry.
+    pub fn duration(&self) -> Duration {
+        self.entries.last().map(|e| e.at).unwrap_or_default()
+    }
+
+    /// Time of the next entry to be played, or the duration at the end.
+    pub fn position(&self) -> Duration {
+        self.entries
+            .get(self.cursor)
+            .map(|e| e.at)
+            .unwrap_or_else(|| self.duration())
+    }
+
+    /// Moves playback to the first entry at or after `at`.
+    pub fn seek(&mut self, at: Duration) {
+        self.cursor = self.entries.partition_point(|e| e.at < at);
+    }
+
+    /// Reads the next entry and its time, advancing the position. Returns `None` at
+    /// the end, even when looping.
+    pub fn next_entry(&mut self) -> Result<Option<(Duration, Vec<u8>)>, DDPError> {
+        let Some(entry) = self.entries.get(self.cursor).copied() else {
+            return Ok(None);
+        };
+        self.cursor += 1;
+
+        self.reader.seek(SeekFrom::Start(entry.pos))?;
+        let mut data = vec![0u8; entry.len];
+        self.reader.read_exact(&mut data)?;
+
+        Ok(Some((entry.at, data)))
+    }
+
+    /// Plays from the current position with the recorded timing until the end, or
+    /// forever when looping.
+    pub fn play(&mut self, conn: &mut DDPConnection) -> Result<(), DDPError> {
+        self.play_until(conn, None)
+    }
+
+    /// Plays like [`play`](Self::play) but stops after `duration` of wall clock time.
+    pub fn play_for(
+        &mut self,
+        conn: &mut DDPConnection,
+        duration: Duration,
+    ) -> Result<(), DDPError> {
+        self.play_until(conn, Some(Instant::now() + duration))
+    }
+
+    fn play_until(
+        &mut self,
+        conn: &mut DDPConnection,
+        deadline: Option<Instant>,
+    ) -> Result<(), DDPError> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+
+        let mut started = Instant::now();
+        let mut origin = self.position();
+
+        loop {
+            let Some((at, data)) = self.next_entry()? else {
+                if !self.looping {
+                    return Ok(());
+                }
+                // The next loop starts once the last entry has been held for a gap
+                let period = self
+                    .duration()
+                    .saturating_add(self.loop_gap())
+                    .max(MIN_LOOP_PERIOD)
+                    - origin;
+                started = started
+                    .checked_add(self.scaled(period)?)
+                    .ok_or_else(|| invalid("timestamp overflow"))?;
+                self.cursor = 0;
+                origin = Duration::ZERO;
+                continue;
+            };
+
+            let due = started
+                .checked_add(self.scaled(at - origin)?)
+                .ok_or_else(|| invalid("timestamp overflow"))?;
+            if deadline.is_some_and(|d| due > d) {
+                // Leave the entry to be played when playback resumes
+                self.cursor -= 1;
+                if let Some(d) = deadline {
+                    std::thread::sleep(d.saturating_duration_since(Instant::now()));
+                }
+                return Ok(());
+            }
+            std::thread::sleep(due.saturating_duration_since(Instant::now()));
+
+            match self.kind {
+                RecordKind::Packets => conn.write_packet(&Packet::from_bytes(&data))?,
+                RecordKind::Frames => conn.write(&data)?,
+            };
+        }
+    }
+
+    /// Recorded time `d` at the playback speed.
+    fn scaled(&self, d: Duration) -> Result<Duration, DDPError> {
+        Duration::try_from_secs_f64(d.as_secs_f64() / self.speed as f64)
+            .map_err(|_| invalid("timestamp overflow"))
+    }
+
+    /// How long the last entry is held before looping: the average time between
+    /// entries.
+    fn loop_gap(&self) -> Duration {
+        match self.entries.len() {
+            0 | 1 => Duration::ZERO,
+            n => self.duration().div_f64((n - 1) as f64),
+        }
+    }
+}
+
+fn invalid(reason: &str) -> DDPError {
+    DDPError::InvalidCapture(reason.to_string())
+}
+
+/// LEB128, 7 bits per byte, least significant first.
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Reads a varint, returning it with its size, or `None` at a clean end of stream.
+fn read_varint<R: Read>(reader: &mut R) -> Result<Option<(u64, usize)>, DDPError> {
+    let mut value = 0u64;
+    let mut byte = [0u8; 1];
+
+    for i in 0..10 {
+        match reader.read_exact(&mut byte) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof && i == 0 => return Ok(None),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                return Err(invalid("truncated record"))
+            }
+            Err(e) => return Err(e.into()),
+        }
+
+        value |= ((byte[0] & 0x7f) as u64) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some((value, i + 1)));
+        }
+    }
+
+    Err(invalid("bad varint"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{PixelConfig, ID};
+    use std::io::Cursor;
+    use std::net::UdpSocket;
+
+    fn frames_recording(times_ms: &[u64]) -> Vec<u8> {
+        let mut recorder = Recorder::new(Vec::new(), RecordKind::Frames).unwrap();
+        for (i, t) in times_ms.iter().enumerate() {
+            recorder
+                .record_at(Duration::from_millis(*t), &[i as u8; 3])
+                .unwrap();
+        }
+        recorder.finish().unwrap()
+    }
+
+    fn display() -> (DDPConnection, UdpSocket) {
+        let display = UdpSocket::bind("127.0.0.1:0").unwrap();
+        display
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let conn = DDPConnection::try_new(
+            display.local_addr().unwrap(),
+            PixelConfig::default(),
+            ID::Default,
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+        )
+        .unwrap();
+        (conn, display)
+    }
+
+    #[test]
+    fn test_varint() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, value);
+            let (read, len) = read_varint(&mut &bytes[..]).unwrap().unwrap();
+            assert_eq!(read, value);
+            assert_eq!(len, bytes.len());
+        }
+        assert_eq!(read_varint(&mut &[][..]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_roundtrip_and_index() {
+        let file = frames_recording(&[0, 40, 80, 120]);
+        // Header, then a one byte delta for the first record and three bytes for
+        // 40000us after that, a one byte length and the data
+        assert_eq!(file.len(), 8 + (1 + 1 + 3) + 3 * (3 + 1 + 3));
+
+        let mut player = Player::open(Cursor::new(file)).unwrap();
+        assert_eq!(player.kind(), RecordKind::Frames);
+        assert_eq!(player.len(), 4);
+        assert_eq!(player.duration(), Duration::from_millis(120));
+
+        assert_eq!(
+            player.next_entry().unwrap(),
+            Some((Duration::ZERO, vec![0, 0, 0]))
+        );
+        assert_eq!(player.position(), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn test_seek() {
+        let mut player = Player::open(Cursor::new(frames_recording(&[0, 40, 80, 120]))).unwrap();
+
+        player.seek(Duration::from_millis(50));
+        assert_eq!(
+            player.next_entry().unwrap(),
+            Some((Duration::from_millis(80), vec![2, 2, 2]))
+        );
+
+        player.seek(Duration::from_secs(10));
+        assert_eq!(player.next_entry().unwrap(), None);
+
+        player.seek(Duration::ZERO);
+        assert_eq!(player.position(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_rejects_bad_files() {
+        assert!(Player::open(Cursor::new(b"RIFF\0\0\0\0".to_vec())).is_err());
+
+        let mut file = frames_recording(&[0, 40]);
+        file.truncate(file.len() - 1);
+        assert!(matches!(
+            Player::open(Cursor::new(file)),
+            Err(DDPError::InvalidCapture(_))
+        ));
+    }
+
+    #[test]
+    fn test_rejects_hostile_files() {
+        let header = frames_recording(&[])[..HEADER_LEN as usize].to_vec();
+        let open = |entries: &[u64]| {
+            let mut file = header.clone();
+            for &v in entries {
+                write_varint(&mut file, v);
+            }
+            file.extend_from_slice(&[0; 3]);
+            Player::open(Cursor::new(file))
+        };
+
+        // A length far beyond the end of the file
+        let err = open(&[0, u64::MAX >> 1]).unwrap_err();
+        assert_eq!(err.to_string(), invalid("truncated record").to_string());
+
+        assert!(open(&[0, 3]).is_ok());
+    }
+
+    #[test]
+    fn test_wrong_kind() {
+        let mut recorder = Recorder::new(Vec::new(), RecordKind::Frames).unwrap();
+        assert!(recorder
+            .record_packet(&Packet::from_bytes(&[0x41, 1, 0x0D, 1, 0, 0, 0, 0, 0, 0]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_play_frames_with_timing() {
+        let (mut conn, display) = display();
+        let mut player = Player::open(Cursor::new(frames_recording(&[0, 60])))
+            .unwrap()
+            .speed(2.0);
+
+        let start = Instant::now();
+        player.play(&mut conn).unwrap();
+        let elapsed = start.elapsed();
+
+        // 60ms at double speed, with plenty of room for slow machines
+        assert!(elapsed >= Duration::from_millis(30), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(500), "{:?}", elapsed);
+
+        let mut buf = [0u8; 1500];
+        for i in 0..2u8 {
+            let (size, _) = display.recv_from(&mut buf).unwrap();
+            assert_eq!(&buf[10..size], &[i; 3]);
+        }
+    }
+
+    #[test]
+    fn test_play_packets_and_loop() {
+        let (mut conn, display) = display();
+
+        let mut recorder = Recorder::new(Vec::new(), RecordKind::Packets).unwrap();
+        // Offset 3, which frame mode would lose
+        let packet = Packet::from_bytes(&[0x41, 9, 0x0D, 1, 0, 0, 0, 3, 0, 3, 7, 8, 9]);
+        recorder.record_packet(&packet).unwrap();
+        let file = recorder.finish().unwrap();
+
+        let mut player = Player::open(Cursor::new(file)).unwrap().looping(true);
+        player
+            .play_for(&mut conn, Duration::from_millis(60))
+            .unwrap();
+
+        // Looped at least twice
+        let mut buf = [0u8; 1500];
+        for _ in 0..2 {
+            let (size, _) = display.recv_from(&mut buf).unwrap();
+            let received = Packet::from_bytes(&buf[..size]);
+            assert_eq!(received.header.offset, 3);
+            assert_eq!(received.data, vec![7, 8, 9]);
+        }
+    }
+
+    #[test]
+    fn test_loop_period() {
+        let (mut conn, display) = display();
+        display.set_nonblocking(true).unwrap();
+
+        // A single entry loops no faster than MIN_LOOP_PERIOD
+        let mut player = Player::open(Cursor::new(frames_recording(&[0])))
+            .unwrap()
+            .looping(true);
+        player
+            .play_for(&mut conn, Duration::from_millis(100))
+            .unwrap();
+        let mut buf = [0u8; 1500];
+        let received = std::iter::from_fn(|| display.recv_from(&mut buf).ok()).count();
+        assert!((2..=5).contains(&received), "{} frames", received);
+
+        // Entries 30ms apart are held 30ms before looping
+        let player = Player::open(Cursor::new(frames_recording(&[0, 30, 60]))).unwrap();
+        assert_eq!(player.loop_gap(), Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_scaled_overflow() {
+        let player = Player::open(Cursor::new(frames_recording(&[0])))
+            .unwrap()
+            .speed(0.5);
+        assert_eq!(
+            player.scaled(Duration::from_secs(60)).unwrap(),
+            Duration::from_secs(120)
+        );
+        assert_eq!(
+            player.scaled(Duration::MAX).unwrap_err().to_string(),
+            invalid("timestamp overflow").to_string()
+        );
+    }
+}
@@ -0,0 +1,698 @@
+//! Scenes, cue lists and a scheduler for running shows.
+//!
+//! A [`Show`] is a set of named [`Scene`]s, each a static frame or a built-in effect,
+//! and a list of [`Cue`]s that bring scenes up with a fade time and optionally
+//! follow on to the next cue after a hold. Shows are plain data and can be loaded
+//! from JSON.
+//!
+//! A [`Scheduler`] renders the show at a fixed tick and sends it to one or more
+//! [`DDPConnection`]s. It can be driven by hand with [`Scheduler::tick`], or moved to
+//! its own thread with [`Scheduler::spawn`] and operated through a [`ShowHandle`]
+//! with go, back and stop.
+//!
+//! # Examples
+//!
+//! ```no_run
+//! use ddp_rs::connection::DDPConnection;
+//! use ddp_rs::protocol::{PixelConfig, ID};
+//! use ddp_rs::show::{Scheduler, Show};
+//! use std::net::UdpSocket;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let show: Show = serde_json::from_str(r#"{
+//!     "scenes": [
+//!         { "name": "walk-in", "effect": { "pattern": "plasma", "speed": 0.3 } },
+//!         { "name": "warm", "frame": [255, 120, 40] },
+//!         { "name": "finale", "effect": { "pattern": "fire" } }
+//!     ],
+//!     "cues": [
+//!         { "scene": "walk-in", "fade": 2.0 },
+//!         { "scene": "warm", "fade": 5.0, "hold": 30.0 },
+//!         { "scene": "finale", "fade": 1.0 }
+//!     ]
+//! }"#)?;
+//!
+//! let conn = DDPConnection::try_new(
+//!     "192.168.1.40:4048",
+//!     PixelConfig::default(),
+//!     ID::Default,
+//!     UdpSocket::bind("0.0.0.0:4048")?
+//! )?;
+//!
+//! let mut scheduler = Scheduler::new(show, 300, PixelConfig::default())?;
+//! scheduler.add_output(conn);
+//!
+//! let handle = scheduler.spawn();
+//! handle.go(); // walk-in
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::compositor::{Compositor, Layer, Source};
+use crate::connection::DDPConnection;
+use crate::effects::{Generator, Palette, Pattern};
+use crate::error::DDPError;
+use crate::protocol::PixelConfig;
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Default scheduler tick rate in frames per second.
+pub const DEFAULT_FPS: f32 = 40.0;
+
+/// What a scene shows.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Content {
+    /// Fixed pixel data. A single pixel is repeated along the whole output.
+    Frame(Vec<u8>),
+    /// One of the built-in effects
+    Effect {
+        /// The pattern to run
+        pattern: Pattern,
+        /// Colors, or the pattern's default
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        palette: Option<Palette>,
+        /// Speed multiplier
+        #[serde(default = "default_speed")]
+        speed: f32,
+    },
+}
+
+fn default_speed() -> f32 {
+    1.0
+}
+
+/// A named look.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    /// Name cues refer to the scene by
+    pub name: String,
+    /// What the scene shows
+    #[serde(flatten)]
+    pub content: Content,
+}
+
+impl Scene {
+    /// A scene showing fixed pixel data.
+    pub fn frame(name: &str, data: Vec<u8>) -> Self {
+        Scene {
+            name: name.to_string(),
+            content: Content::Frame(data),
+        }
+    }
+
+    /// A scene running a built-in effect with its default palette.
+    pub fn effect(name: &str, pattern: Pattern) -> Self {
+        Scene {
+            name: name.to_string(),
+            content: Content::Effect {
+                pattern,
+                palette: None,
+                speed: 1.0,
+            },
+        }
+    }
+
+    fn source(&self, pixels: usize, channels: usize) -> Source {
+        match &self.content {
+            Content::Frame(data) if data.len() == channels => Source::Frame(data.repeat(pixels)),
+            Content::Frame(data) => Source::Frame(data.clone()),
+            Content::Effect {
+                pattern,
+                palette,
+                speed,
+            } => {
+                let mut generator = Generator::new(*pattern, pixels).speed(*speed);
+                if let Some(palette) = palette {
+                    generator = generator.palette(palette.clone());
+                }
+                Source::effect(generator)
+            }
+        }
+    }
+}
+
+/// A step in the cue list.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cue {
+    /// Name of the scene to bring up
+    pub scene: String,
+    /// Crossfade time from the previous look, in seconds in JSON
+    #[serde(default, with = "seconds")]
+    pub fade: Duration,
+    /// When set, the next cue goes automatically this long after this one, in
+    /// seconds in JSON. Otherwise the cue stays up until the next go.
+    #[serde(
+        default,
+        with = "optional_seconds",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub hold: Option<Duration>,
+}
+
+impl Cue {
+    /// A cue bringing up `scene` over `fade`, waiting for go.
+    pub fn new(scene: &str, fade: Duration) -> Self {
+        Cue {
+            scene: scene.to_string(),
+            fade,
+            hold: None,
+        }
+    }
+
+    /// Follows on to the next cue automatically after `hold`.
+    pub fn hold(mut self, hold: Duration) -> Self {
+        self.hold = Some(hold);
+        self
+    }
+}
+
+/// Scenes and the cue list that runs them.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Show {
+    /// Every scene cues can refer to
+    #[serde(default)]
+    pub scenes: Vec<Scene>,
+    /// The cue list, in order
+    #[serde(default)]
+    pub cues: Vec<Cue>,
+}
+
+impl Show {
+    /// Looks up a scene by name.
+    pub fn scene(&self, name: &str) -> Option<&Scene> {
+        self.scenes.iter().find(|s| s.name == name)
+    }
+
+    /// Checks every cue refers to a scene that exists.
+    pub fn validate(&self) -> Result<(), DDPError> {
+        for (i, cue) in self.cues.iter().enumerate() {
+            if self.scene(&cue.scene).is_none() {
+                return Err(DDPError::InvalidShow(format!(
+                    "cue {} refers to unknown scene {:?}",
+                    i, cue.scene
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Renders a [`Show`] and sends it to its outputs.
+#[derive(Debug)]
+pub struct Scheduler {
+    show: Show,
+    pixels: usize,
+    channels: usize,
+    compositor: Compositor,
+    outputs: Vec<DDPConnection>,
+    interval: Duration,
+    current: Option<usize>,
+    // Time of the last go, for holds
+    went: Duration,
+    now: Duration,
+}
+
+impl Scheduler {
+    /// Creates a scheduler rendering `pixels` pixels in `pixel_config`'s format, dark
+    /// until the first go.
+    pub fn new(show: Show, pixels: usize, pixel_config: PixelConfig) -> Result<Self, DDPError> {
+        show.validate()?;
+
+        let mut compositor = Compositor::new(pixels, pixel_config);
+        compositor.push(Layer::frame(Vec::new()));
+
+        Ok(Scheduler {
+            show,
+            pixels,
+            channels: pixel_config.data_type.channels(),
+            compositor,
+            outputs: Vec::new(),
+            interval: Duration::from_secs_f64(1.0 / DEFAULT_FPS as f64),
+            current: None,
+            went: Duration::ZERO,
+            now: Duration::ZERO,
+        })
+    }
+
+    /// Sets how many frames per second [`spawn`](Self::spawn) sends.
+    pub fn fps(mut self, fps: f32) -> Self {
+        self.interval = Duration::from_secs_f64(1.0 / fps.max(0.001) as f64);
+        self
+    }
+
+    /// Adds a connection every frame is sent to.
+    pub fn add_output(&mut self, conn: DDPConnection) {
+        self.outputs.push(conn);
+    }
+
+    /// The connections frames are sent to.
+    pub fn outputs(&mut self) -> &mut [DDPConnection] {
+        &mut self.outputs
+    }
+
+    /// The show being run.
+    pub fn show(&self) -> &Show {
+        &self.show
+    }
+
+    /// Index of the cue that last went, or `None` when stopped.
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    /// Runs the next cue. Does nothing after the last one.
+    pub fn go(&mut self) {
+        let next = self.current.map_or(0, |c| c + 1);
+        self.goto(next);
+    }
+
+    /// Runs the previous cue, with that cue's fade time.
+    pub fn back(&mut self) {
+        if let Some(previous) = self.current.and_then(|c| c.checked_sub(1)) {
+            self.goto(previous);
+        }
+    }
+
+    /// Runs cue `index`. Does nothing if there is no such cue.
+    pub fn goto(&mut self, index: usize) {
+        let Some(cue) = self.show.cues.get(index) else {
+            return;
+        };
+        // Validated when the scheduler was created
+        let Some(scene) = self.show.scene(&cue.scene) else {
+            return;
+        };
+
+        let source = scene.source(self.pixels, self.channels);
+        if let Some(layer) = self.compositor.layer_mut(0) {
+            layer.crossfade(source, cue.fade);
+        }
+        self.current = Some(index);
+        self.went = self.now;
+    }
+
+    /// Blacks out immediately and returns to before the first cue.
+    pub fn stop(&mut self) {
+        if let Some(layer) = self.compositor.layer_mut(0) {
+            layer.set_source(Source::Frame(Vec::new()));
+        }
+        self.current = None;
+    }
+
+    /// Renders the frame at `t` since the show started, following on from held cues,
+    /// and sends it to every output.
+    ///
+    /// An output that fails doesn't stop the frame going to the others; the first
+    /// error is returned once all of them have been tried.
+    pub fn tick(&mut self, t: Duration) -> Result<&[u8], DDPError> {
+        self.now = t;
+
+        let hold = self
+            .current
+            .and_then(|c| self.show.cues.get(c))
+            .and_then(|cue| cue.hold);
+        if let Some(hold) = hold {
+            if t.saturating_sub(self.went) >= hold {
+                let due = self.went + hold;
+                self.go();
+                // Time the next hold from when this one ended, not when it was noticed
+                self.went = due;
+            }
+        }
+
+        let frame = self.compositor.render(t);
+        let mut error = None;
+        for (i, conn) in self.outputs.iter_mut().enumerate() {
+            if let Err(e) = conn.write(frame) {
+                log::warn!("could not send show frame to output {}: {}", i, e);
+                error.get_or_insert(e);
+            }
+        }
+
+        match error {
+            Some(e) => Err(e),
+            None => Ok(frame),
+        }
+    }
+
+    /// Runs the scheduler on its own thread, ticking at the configured rate.
+    pub fn spawn(self) -> ShowHandle {
+        let (commands, rx) = unbounded();
+        let current = Arc::new(AtomicUsize::new(NOT_RUNNING));
+        let thread_current = current.clone();
+
+        let thread = std::thread::spawn(move || run(self, rx, thread_current));
+
+        ShowHandle {
+            commands,
+            current,
+            thread: Some(thread),
+        }
+    }
+}
+
+const NOT_RUNNING: usize = usize::MAX;
+
+#[derive(Debug)]
+enum Command {
+    Go,
+    Back,
+    Goto(usize),
+    Stop,
+    Shutdown,
+}
+
+fn run(
+    mut scheduler: Scheduler,
+    commands: Receiver<Command>,
+    current: Arc<AtomicUsize>,
+) -> Scheduler {
+    let start = Instant::now();
+    let mut due = start;
+
+    loop {
+        // Failed outputs are logged by tick
+        let _ = scheduler.tick(start.elapsed());
+        // Stored after ticking too, since holds can move on by themselves
+        current.store(scheduler.current.unwrap_or(NOT_RUNNING), Ordering::Relaxed);
+
+        due += scheduler.interval;
+        if due < Instant::now() {
+            // Fell behind, drop the missed ticks rather than bursting
+            due = Instant::now();
+        }
+
+        // Take commands while waiting for the next tick so go is applied right away
+        loop {
+            match commands.recv_timeout(due.saturating_duration_since(Instant::now())) {
+                Ok(Command::Go) => scheduler.go(),
+                Ok(Command::Back) => scheduler.back(),
+                Ok(Command::Goto(index)) => scheduler.goto(index),
+                Ok(Command::Stop) => scheduler.stop(),
+                Ok(Command::Shutdown) | Err(RecvTimeoutError::Disconnected) => return scheduler,
+                Err(RecvTimeoutError::Timeout) => break,
+            }
+            current.store(scheduler.current.unwrap_or(NOT_RUNNING), Ordering::Relaxed);
+        }
+    }
+}
+
+/// Operates a [`Scheduler`] running on its own thread.
+///
+/// Dropping the handle stops the thread.
+#[derive(Debug)]
+pub struct ShowHandle {
+    commands: Sender<Command>,
+    current: Arc<AtomicUsize>,
+    thread: Option<JoinHandle<Scheduler>>,
+}
+
+impl ShowHandle {
+    /// Runs the next cue.
+    pub fn go(&self) {
+        let _ = self.commands.send(Command::Go);
+    }
+
+    /// Runs the previous cue.
+    pub fn back(&self) {
+        let _ = self.commands.send(Command::Back);
+    }
+
+    /// Runs cue `index`.
+    pub fn goto(&self, index: usize) {
+        let _ = self.commands.send(Command::Goto(index));
+    }
+
+    /// Blacks out and returns to before the first cue.
+    pub fn stop(&self) {
+        let _ = self.commands.send(Command::Stop);
+    }
+
+    /// Index of the running cue, or `None` when stopped.
+    pub fn current(&self) -> Option<usize> {
+        match self.current.load(Ordering::Relaxed) {
+            NOT_RUNNING => None,
+            index => Some(index),
+        }
+    }
+
+    /// Stops the thread and returns the scheduler, outputs included.
+    pub fn shutdown(mut self) -> Scheduler {
+        let _ = self.commands.send(Command::Shutdown);
+        self.thread
+            .take()
+            .expect("show thread already joined")
+            .join()
+            .expect("show thread panicked")
+    }
+}
+
+impl Drop for ShowHandle {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = self.commands.send(Command::Shutdown);
+            let _ = thread.join();
+        }
+    }
+}
+
+mod seconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_f64(d.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        let secs = f64::deserialize(d)?;
+        Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom)
+    }
+}
+
+mod optional_seconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(d: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        match d {
+            Some(d) => s.serialize_some(&d.as_secs_f64()),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        Option::<f64>::deserialize(d)?
+            .map(|secs| Duration::try_from_secs_f64(secs).map_err(serde::de::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ID;
+    use std::net::UdpSocket;
+
+    fn secs(s: f32) -> Duration {
+        Duration::from_secs_f32(s)
+    }
+
+    fn show() -> Show {
+        Show {
+            scenes: vec![
+                Scene::frame("red", vec![200, 0, 0]),
+                Scene::frame("blue", vec![0, 0, 200]),
+                Scene::effect("fire", Pattern::Fire),
+            ],
+            cues: vec![
+                Cue::new("red", Duration::ZERO),
+                Cue::new("blue", secs(2.0)).hold(secs(5.0)),
+                Cue::new("red", secs(1.0)),
+            ],
+        }
+    }
+
+    fn scheduler() -> Scheduler {
+        Scheduler::new(show(), 2, PixelConfig::default()).unwrap()
+    }
+
+    #[test]
+    fn test_go_and_fade() {
+        let mut scheduler = scheduler();
+        assert_eq!(scheduler.tick(secs(0.0)).unwrap(), &[0; 6]);
+
+        scheduler.go();
+        assert_eq!(scheduler.current(), Some(0));
+        // A single pixel fills the output
+        assert_eq!(scheduler.tick(secs(0.1)).unwrap(), &[200, 0, 0, 200, 0, 0]);
+
+        scheduler.go();
+        scheduler.tick(secs(1.0)).unwrap();
+        assert_eq!(
+            scheduler.tick(secs(2.0)).unwrap(),
+            &[100, 0, 100, 100, 0, 100]
+        );
+        assert_eq!(scheduler.tick(secs(3.0)).unwrap(), &[0, 0, 200, 0, 0, 200]);
+    }
+
+    #[test]
+    fn test_hold_follows_on() {
+        let mut scheduler = scheduler();
+        scheduler.tick(secs(0.0)).unwrap();
+        scheduler.goto(1);
+
+        scheduler.tick(secs(4.9)).unwrap();
+        assert_eq!(scheduler.current(), Some(1));
+        scheduler.tick(secs(5.0)).unwrap();
+        assert_eq!(scheduler.current(), Some(2));
+
+        // The last cue has no hold and stays up
+        scheduler.tick(secs(60.0)).unwrap();
+        assert_eq!(scheduler.current(), Some(2));
+        scheduler.go();
+        assert_eq!(scheduler.current(), Some(2));
+    }
+
+    #[test]
+    fn test_back_and_stop() {
+        let mut scheduler = scheduler();
+        scheduler.back();
+        assert_eq!(scheduler.current(), None);
+
+        scheduler.goto(2);
+        scheduler.back();
+        assert_eq!(scheduler.current(), Some(1));
+
+        scheduler.stop();
+        assert_eq!(scheduler.current(), None);
+        assert_eq!(scheduler.tick(secs(1.0)).unwrap(), &[0; 6]);
+
+        scheduler.go();
+        assert_eq!(scheduler.current(), Some(0));
+    }
+
+    #[test]
+    fn test_effect_scene() {
+        let mut scheduler = scheduler();
+        scheduler.show.cues.push(Cue::new("fire", Duration::ZERO));
+        scheduler.goto(3);
+        let frame = scheduler.tick(secs(2.0)).unwrap();
+        assert!(frame.iter().any(|&c| c > 0));
+    }
+
+    #[test]
+    fn test_unknown_scene() {
+        let mut show = show();
+        show.cues.push(Cue::new("green", Duration::ZERO));
+        assert!(matches!(
+            Scheduler::new(show, 2, PixelConfig::default()),
+            Err(DDPError::InvalidShow(_))
+        ));
+    }
+
+    #[test]
+    fn test_json() {
+        let show: Show = serde_json::from_str(
+            r#"{
+                "scenes": [
+                    { "name": "warm", "frame": [255, 120, 40] },
+                    { "name": "sea", "effect": { "pattern": "plasma", "speed": 0.5 } }
+                ],
+                "cues": [
+                    { "scene": "warm" },
+                    { "scene": "sea", "fade": 2.5, "hold": 10 }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(show.cues[0].fade, Duration::ZERO);
+        assert_eq!(show.cues[1].fade, secs(2.5));
+        assert_eq!(show.cues[1].hold, Some(secs(10.0)));
+        assert_eq!(
+            show.scene("sea").unwrap().content,
+            Content::Effect {
+                pattern: Pattern::Plasma,
+                palette: None,
+                speed: 0.5
+            }
+        );
+
+        let json = serde_json::to_string(&show).unwrap();
+        assert_eq!(serde_json::from_str::<Show>(&json).unwrap(), show);
+    }
+
+    #[test]
+    fn test_failed_output_does_not_stop_others() {
+        use crate::transport::{MemoryTransport, Transport};
+
+        // The far end of the first output is gone
+        let (broken, _) = MemoryTransport::pair();
+        let (working, mut display) = MemoryTransport::pair();
+
+        let mut scheduler = scheduler();
+        scheduler.add_output(DDPConnection::with_transport(
+            broken,
+            PixelConfig::default(),
+            ID::Default,
+        ));
+        scheduler.add_output(DDPConnection::with_transport(
+            working,
+            PixelConfig::default(),
+            ID::Default,
+        ));
+        scheduler.go();
+
+        assert!(matches!(
+            scheduler.tick(secs(0.0)),
+            Err(DDPError::Disconnect(_))
+        ));
+
+        let mut buf = [0u8; 1500];
+        let size = display
+            .recv(&mut buf, Some(Duration::from_secs(1)))
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[10..size], &[200, 0, 0, 200, 0, 0]);
+    }
+
+    #[test]
+    fn test_spawn_sends_to_outputs() {
+        let display = UdpSocket::bind("127.0.0.1:0").unwrap();
+        display
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        let conn = DDPConnection::try_new(
+            display.local_addr().unwrap(),
+            PixelConfig::default(),
+            ID::Default,
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+        )
+        .unwrap();
+
+        let mut scheduler = scheduler().fps(100.0);
+        scheduler.add_output(conn);
+        let handle = scheduler.spawn();
+        handle.go();
+
+        let mut buf = [0u8; 1500];
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            let (size, _) = display.recv_from(&mut buf).unwrap();
+            if buf[10..size] == [200, 0, 0, 200, 0, 0] {
+                break;
+            }
+            assert!(Instant::now() < deadline, "cue never went");
+        }
+        assert_eq!(handle.current(), Some(0));
+
+        let scheduler = handle.shutdown();
+        assert_eq!(scheduler.current(), Some(0));
+    }
+}
@@ -0,0 +1,572 @@
+use crate::connection::{self, DDPConnection, MulticastInterface};
+use crate::error::DDPError;
+use crate::packet::{Packet, PacketBuilder};
+use crate::protocol::{HeaderBuilder, PixelConfig, ID};
+use crate::receiver::FrameAssembler;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How long the display thread blocks before checking whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// A frame completed by a packet with the push flag.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DisplayFrame {
+    /// The whole display buffer after the frame was pushed
+    pub data: Vec<u8>,
+    /// Timecode of the push packet, if it had one
+    pub timecode: Option<u32>,
+    /// When the push packet was processed
+    pub received: Instant,
+}
+
+/// Counters of what a [`VirtualDisplay`] has seen.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+pub struct DisplayStats {
+    /// Packets processed, not counting dropped ones
+    pub packets: usize,
+    /// Packets thrown away by the simulated packet loss
+    pub dropped: usize,
+    /// Frames shown
+    pub frames: usize,
+    /// Frames discarded because their timecode was older than the frame shown
+    pub late: usize,
+    /// Queries answered
+    pub replies: usize,
+}
+
+#[derive(Debug)]
+struct State {
+    assembler: FrameAssembler,
+    shown: Vec<u8>,
+    last_timecode: Option<u32>,
+    frames: VecDeque<DisplayFrame>,
+    packets: VecDeque<Packet>,
+    responses: HashMap<ID, Value>,
+    stats: DisplayStats,
+    loss: f32,
+    drop_next: usize,
+    latency: Duration,
+    rng: u32,
+}
+
+impl State {
+    fn random(&mut self) -> f32 {
+        // xorshift32
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // A panicking test thread shouldn't take the display down with it
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// An emulated display listening on localhost.
+///
+/// The display behaves roughly like WLED:
+///
+/// - Data packets are assembled by offset and shown when a packet with the push
+///   flag arrives. Pushed frames with a timecode older than the frame on show are
+///   discarded as late.
+/// - STATUS and CONFIG queries are answered with configurable JSON, and CONFIG
+///   writes replace the config that is reported.
+/// - Incoming packets can be dropped at random or delayed before processing,
+///   which also delays replies.
+///
+/// The display stops when dropped.
+#[derive(Debug)]
+pub struct VirtualDisplay {
+    addr: SocketAddr,
+    // Shares the display thread's socket, for multicast membership
+    socket: UdpSocket,
+    shared: Arc<Shared>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl VirtualDisplay {
+    /// Starts a display on an ephemeral port on 127.0.0.1.
+    pub fn new() -> Result<Self, DDPError> {
+        Self::bind("127.0.0.1:0")
+    }
+
+    /// Starts a display on a specific address.
+    pub fn bind(addr: &str) -> Result<Self, DDPError> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let addr = socket.local_addr()?;
+
+        let responses = HashMap::from([
+            (
+                ID::Status,
+                json!({"status": {
+                    "man": "ddp-rs",
+                    "mod": "virtual display",
+                    "ver": env!("CARGO_PKG_VERSION"),
+                    "push": true,
+                    "ntp": false,
+                }}),
+            ),
+            (
+                ID::Config,
+                json!({"config": {
+                    "ip": addr.ip().to_string(),
+                    "nm": "255.255.255.0",
+                    "gw": addr.ip().to_string(),
+                    "ports": [],
+                }}),
+            ),
+        ]);
+
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                assembler: FrameAssembler::new(),
+                shown: Vec::new(),
+                last_timecode: None,
+                frames: VecDeque::new(),
+                packets: VecDeque::new(),
+                responses,
+                stats: DisplayStats::default(),
+                loss: 0.0,
+                drop_next: 0,
+                latency: Duration::ZERO,
+                rng: 0x2545_f491,
+            }),
+            changed: Condvar::new(),
+        });
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let socket = socket.try_clone()?;
+            let shared = shared.clone();
+            let stop = stop.clone();
+            std::thread::spawn(move || run(socket, shared, stop))
+        };
+
+        Ok(VirtualDisplay {
+            addr,
+            socket,
+            shared,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// The address the display listens on.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Opens a connection to the display from a new ephemeral socket.
+    pub fn connect(&self, pixel_config: PixelConfig) -> Result<DDPConnection, DDPError> {
+        let bind = match self.addr {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::LOCALHOST, 0)),
+        };
+        DDPConnection::try_new(self.addr, pixel_config, ID::Default, UdpSocket::bind(bind)?)
+    }
+
+    /// Also listens to the multicast `group`, like a display in a multicast
+    /// setup. The display should be bound to an unspecified address.
+    pub fn join_multicast(
+        &self,
+        group: IpAddr,
+        interface: MulticastInterface,
+    ) -> Result<(), DDPError> {
+        connection::join_multicast(&self.socket, group, interface)
+    }
+
+    /// Stops listening to the multicast `group`.
+    pub fn leave_multicast(
+        &self,
+        group: IpAddr,
+        interface: MulticastInterface,
+    ) -> Result<(), DDPError> {
+        connection::leave_multicast(&self.socket, group, interface)
+    }
+
+    /// Sets the JSON the display answers queries for `id` with, such as
+    /// `{"status": {...}}` for [`ID::Status`].
+    pub fn set_response(&self, id: ID, response: Value) {
+        self.shared.lock().responses.insert(id, response);
+    }
+
+    /// Stops answering queries for `id`.
+    pub fn clear_response(&self, id: ID) {
+        self.shared.lock().responses.remove(&id);
+    }
+
+    /// The JSON queries for `id` are answered with, which for [`ID::Config`]
+    /// includes any config written to the display.
+    pub fn response(&self, id: ID) -> Option<Value> {
+        self.shared.lock().responses.get(&id).cloned()
+    }
+
+    /// Drops incoming packets with probability `loss`, from 0.0 to 1.0.
+    pub fn set_packet_loss(&self, loss: f32) {
+        self.shared.lock().loss = loss.clamp(0.0, 1.0);
+    }
+
+    /// Drops the next `count` incoming packets.
+    pub fn drop_next(&self, count: usize) {
+        self.shared.lock().drop_next = count;
+    }
+
+    /// Seeds the random packet loss for repeatable runs.
+    pub fn set_seed(&self, seed: u32) {
+        // xorshift gets stuck at zero
+        self.shared.lock().rng = seed.max(1);
+    }
+
+    /// Delays processing of every incoming packet by `latency`.
+    pub fn set_latency(&self, latency: Duration) {
+        self.shared.lock().latency = latency;
+    }
+
+    /// The frame on show, empty before the first push.
+    pub fn frame(&self) -> Vec<u8> {
+        self.shared.lock().shown.clone()
+    }
+
+    /// Counters of what the display has seen so far.
+    pub fn stats(&self) -> DisplayStats {
+        self.shared.lock().stats
+    }
+
+    /// Waits up to `timeout` for the next frame to be shown and takes it.
+    ///
+    /// Frames are queued, so none are missed between calls.
+    pub fn next_frame(&self, timeout: Duration) -> Option<DisplayFrame> {
+        self.wait(timeout, |state| state.frames.pop_front())
+    }
+
+    /// Waits up to `timeout` for the next processed packet and takes it. Every
+    /// packet that wasn't dropped is queued, queries and data alike.
+    pub fn next_packet(&self, timeout: Duration) -> Option<Packet> {
+        self.wait(timeout, |state| state.packets.pop_front())
+    }
+
+    /// Clears queued frames and packets.
+    pub fn clear(&self) {
+        let mut state = self.shared.lock();
+        state.frames.clear();
+        state.packets.clear();
+    }
+
+    fn wait<T>(
+        &self,
+        timeout: Duration,
+        mut take: impl FnMut(&mut State) -> Option<T>,
+    ) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+
+        loop {
+            if let Some(item) = take(&mut state) {
+                return Some(item);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return None;
+            }
+            state = self
+                .shared
+                .changed
+                .wait_timeout(state, remaining)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+}
+
+impl Drop for VirtualDisplay {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn run(socket: UdpSocket, shared: Arc<Shared>, stop: Arc<AtomicBool>) {
+    // Large enough for jumbo frames
+    let mut buf = vec![0u8; 65536];
+    // Packets waiting out the simulated latency
+    let mut pending: VecDeque<(Instant, SocketAddr, Vec<u8>)> = VecDeque::new();
+
+    while !stop.load(Ordering::Relaxed) {
+        let timeout = pending
+            .front()
+            .map(|(due, _, _)| due.saturating_duration_since(Instant::now()))
+            .unwrap_or(POLL_INTERVAL)
+            .clamp(Duration::from_millis(1), POLL_INTERVAL);
+        let _ = socket.set_read_timeout(Some(timeout));
+
+        match socket.recv_from(&mut buf) {
+            Ok((size, from)) => {
+                let mut state = shared.lock();
+                let dropped = if state.drop_next > 0 {
+                    state.drop_next -= 1;
+                    true
+                } else {
+                    state.loss > 0.0 && state.random() < state.loss
+                };
+
+                if dropped {
+                    state.stats.dropped += 1;
+                } else {
+                    let due = Instant::now() + state.latency;
+                    pending.push_back((due, from, buf[..size].to_vec()));
+                }
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => {
+                log::error!(
+                    "virtual display on {:?} stopped: {}",
+                    socket.local_addr(),
+                    e
+                );
+                return;
+            }
+        }
+
+        while pending
+            .front()
+            .is_some_and(|(due, _, _)| *due <= Instant::now())
+        {
+            if let Some((_, from, bytes)) = pending.pop_front() {
+                process(&socket, &shared, from, &bytes);
+            }
+        }
+    }
+}
+
+fn process(socket: &UdpSocket, shared: &Shared, from: SocketAddr, bytes: &[u8]) {
+    if bytes.len() < 10 {
+        return;
+    }
+    let packet = Packet::from_bytes(bytes);
+    let header = packet.header;
+
+    let mut state = shared.lock();
+    state.stats.packets += 1;
+    state.packets.push_back(packet.clone());
+
+    if header.packet_type.query {
+        if let Some(response) = state.responses.get(&header.id) {
+            let reply = PacketBuilder::new()
+                .header(HeaderBuilder::new().id(header.id).reply(true).push(true))
+                .data(response.to_string().as_bytes())
+                .build()
+                .and_then(|reply| reply.to_bytes());
+            if let Ok(bytes) = reply {
+                if socket.send_to(&bytes, from).is_ok() {
+                    state.stats.replies += 1;
+                }
+            }
+        }
+    } else if header.id == ID::Config {
+        if let Ok(config) = serde_json::from_slice::<Value>(&packet.data) {
+            state.responses.insert(ID::Config, config);
+        }
+    } else if state.assembler.push(&packet) {
+        let timecode = header.time_code.0;
+        // Timecodes wrap, so a frame is late if it is less than half the range
+        // behind the one on show
+        let late = matches!(
+            (timecode, state.last_timecode),
+            (Some(t), Some(last)) if (last.wrapping_sub(t) as i32) > 0
+        );
+
+        if late {
+            state.stats.late += 1;
+        } else {
+            if timecode.is_some() {
+                state.last_timecode = timecode;
+            }
+            let data = state.assembler.frame().to_vec();
+            state.shown.clone_from(&data);
+            state.stats.frames += 1;
+            state.frames.push_back(DisplayFrame {
+                data,
+                timecode,
+                received: Instant::now(),
+            });
+        }
+    }
+
+    drop(state);
+    shared.changed.notify_all();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WAIT: Duration = Duration::from_secs(1);
+
+    fn send(display: &VirtualDisplay, bytes: &[u8]) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.send_to(bytes, display.addr()).unwrap();
+    }
+
+    #[test]
+    fn test_frames_need_push() {
+        let display = VirtualDisplay::new().unwrap();
+
+        // Second half first, without push
+        send(&display, &[0x40, 1, 0x0D, 1, 0, 0, 0, 3, 0, 3, 4, 5, 6]);
+        assert!(display.next_packet(WAIT).is_some());
+        assert!(display.frame().is_empty());
+
+        send(&display, &[0x41, 2, 0x0D, 1, 0, 0, 0, 0, 0, 3, 1, 2, 3]);
+        let frame = display.next_frame(WAIT).unwrap();
+        assert_eq!(frame.data, vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(display.frame(), frame.data);
+        assert_eq!(display.stats().frames, 1);
+    }
+
+    #[test]
+    fn test_late_timecode_is_discarded() {
+        let display = VirtualDisplay::new().unwrap();
+
+        send(
+            &display,
+            &[0x51, 1, 0x0D, 1, 0, 0, 0, 0, 0, 3, 0, 0, 0, 10, 1, 1, 1],
+        );
+        assert_eq!(display.next_frame(WAIT).unwrap().timecode, Some(10));
+
+        send(
+            &display,
+            &[0x51, 2, 0x0D, 1, 0, 0, 0, 0, 0, 3, 0, 0, 0, 5, 2, 2, 2],
+        );
+        send(
+            &display,
+            &[0x51, 3, 0x0D, 1, 0, 0, 0, 0, 0, 3, 0, 0, 0, 20, 3, 3, 3],
+        );
+        assert_eq!(display.next_frame(WAIT).unwrap().data, vec![3, 3, 3]);
+        assert_eq!(display.stats().late, 1);
+    }
+
+    #[test]
+    fn test_timecode_wraps() {
+        let display = VirtualDisplay::new().unwrap();
+
+        send(
+            &display,
+            &[
+                0x51, 1, 0x0D, 1, 0, 0, 0, 0, 0, 3, 0xFF, 0xFF, 0xFF, 0xF0, 1, 1, 1,
+            ],
+        );
+        assert_eq!(
+            display.next_frame(WAIT).unwrap().timecode,
+            Some(0xFFFF_FFF0)
+        );
+
+        send(
+            &display,
+            &[0x51, 2, 0x0D, 1, 0, 0, 0, 0, 0, 3, 0, 0, 0, 5, 2, 2, 2],
+        );
+        assert_eq!(display.next_frame(WAIT).unwrap().timecode, Some(5));
+
+        send(
+            &display,
+            &[
+                0x51, 3, 0x0D, 1, 0, 0, 0, 0, 0, 3, 0xFF, 0xFF, 0xFF, 0xF8, 3, 3, 3,
+            ],
+        );
+        send(
+            &display,
+            &[0x51, 4, 0x0D, 1, 0, 0, 0, 0, 0, 3, 0, 0, 0, 6, 4, 4, 4],
+        );
+        assert_eq!(display.next_frame(WAIT).unwrap().data, vec![4, 4, 4]);
+        assert_eq!(display.stats().late, 1);
+    }
+
+    #[test]
+    fn test_status_and_config_queries() {
+        let display = VirtualDisplay::new().unwrap();
+        let mut conn = display.connect(PixelConfig::default()).unwrap();
+
+        let reply = conn.query(ID::Status, WAIT).unwrap();
+        assert!(reply.header.packet_type.reply);
+        let json: Value = serde_json::from_slice(&reply.data).unwrap();
+        assert_eq!(json["status"]["man"], "ddp-rs");
+
+        display.set_response(
+            ID::Config,
+            json!({"config": {"ip": "10.0.0.9", "ports": []}}),
+        );
+        let reply = conn.query(ID::Config, WAIT).unwrap();
+        let json: Value = serde_json::from_slice(&reply.data).unwrap();
+        assert_eq!(json["config"]["ip"], "10.0.0.9");
+
+        display.clear_response(ID::Status);
+        assert!(matches!(
+            conn.query(ID::Status, Duration::from_millis(50)),
+            Err(DDPError::NoReply)
+        ));
+    }
+
+    #[test]
+    fn test_config_write() {
+        let display = VirtualDisplay::new().unwrap();
+        let config = br#"{"config":{"ip":"10.0.0.2"}}"#;
+        let mut bytes = vec![0x41, 1, 0, 0xFA, 0, 0, 0, 0, 0, config.len() as u8];
+        bytes.extend_from_slice(config);
+        send(&display, &bytes);
+
+        display.next_packet(WAIT).unwrap();
+        assert_eq!(
+            display.response(ID::Config).unwrap()["config"]["ip"],
+            "10.0.0.2"
+        );
+    }
+
+    #[test]
+    fn test_packet_loss() {
+        let display = VirtualDisplay::new().unwrap();
+        let mut conn = display.connect(PixelConfig::default()).unwrap();
+
+        display.drop_next(1);
+        conn.write(&[1, 1, 1]).unwrap();
+        conn.write(&[2, 2, 2]).unwrap();
+        assert_eq!(display.next_frame(WAIT).unwrap().data, vec![2, 2, 2]);
+        assert_eq!(display.stats().dropped, 1);
+
+        display.set_packet_loss(1.0);
+        conn.write(&[3, 3, 3]).unwrap();
+        assert!(display.next_frame(Duration::from_millis(50)).is_none());
+        assert_eq!(display.stats().dropped, 2);
+    }
+
+    #[test]
+    fn test_latency() {
+        let display = VirtualDisplay::new().unwrap();
+        let mut conn = display.connect(PixelConfig::default()).unwrap();
+        display.set_latency(Duration::from_millis(50));
+
+        let sent = Instant::now();
+        conn.write(&[1, 2, 3]).unwrap();
+        let frame = display.next_frame(WAIT).unwrap();
+        assert!(frame.received - sent >= Duration::from_millis(50));
+    }
+}
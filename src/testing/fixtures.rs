@@ -0,0 +1,79 @@
+//! Test fixtures used by the crate's own unit tests
+//!
+//! This module provides helper functions for creating test fixtures,
+//! making it easier to write comprehensive tests across the codebase.
+
+use crate::protocol::*;
+
+/// Creates a simple RGB pixel data array for testing
+pub fn rgb_test_data(num_pixels: usize) -> Vec<u8> {
+    let mut data = Vec::with_capacity(num_pixels * 3);
+    for i in 0..num_pixels {
+        data.push((i % 256) as u8); // R
+        data.push(((i * 2) % 256) as u8); // G
+        data.push(((i * 3) % 256) as u8); // B
+    }
+    data
+}
+
+/// Creates a test packet with valid DDP header and optional data
+pub fn create_test_packet(data_length: usize) -> Vec<u8> {
+    let header = HeaderBuilder::new()
+        .sequence_number(1)
+        .push(true)
+        .length(data_length as u16)
+        .build()
+        .unwrap();
+
+    let header_bytes: [u8; 10] = header.into();
+    let mut packet = header_bytes.to_vec();
+    packet.extend_from_slice(&rgb_test_data(data_length / 3));
+    packet
+}
+
+/// Creates a test packet with timecode
+pub fn create_test_packet_with_timecode(data_length: usize, timecode: u32) -> Vec<u8> {
+    let header = HeaderBuilder::new()
+        .sequence_number(1)
+        .push(true)
+        .timecode(timecode)
+        .length(data_length as u16)
+        .build()
+        .unwrap();
+
+    let header_bytes: [u8; 14] = header.into();
+    let mut packet = header_bytes.to_vec();
+    packet.extend_from_slice(&rgb_test_data(data_length / 3));
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rgb_test_data() {
+        let data = rgb_test_data(2);
+        assert_eq!(data.len(), 6);
+        assert_eq!(data[0], 0); // First pixel R
+        assert_eq!(data[1], 0); // First pixel G
+        assert_eq!(data[2], 0); // First pixel B
+        assert_eq!(data[3], 1); // Second pixel R
+        assert_eq!(data[4], 2); // Second pixel G
+        assert_eq!(data[5], 3); // Second pixel B
+    }
+
+    #[test]
+    fn test_create_test_packet() {
+        let packet = create_test_packet(6);
+        assert!(packet.len() >= 10); // At least header size
+        assert_eq!(packet[0] & 0b01000000, 0b01000000); // Push bit set
+    }
+
+    #[test]
+    fn test_create_test_packet_with_timecode() {
+        let packet = create_test_packet_with_timecode(6, 42);
+        assert!(packet.len() >= 14); // Header with timecode is 14 bytes
+        assert_eq!(packet[0] & 0b00010000, 0b00010000); // Timecode bit set
+    }
+}
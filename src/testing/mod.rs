@@ -0,0 +1,34 @@
+//! Helpers for testing code that talks DDP.
+//!
+//! [`VirtualDisplay`] is a display running on an ephemeral localhost port. It
+//! assembles frames like a real controller, answers STATUS and CONFIG queries, and
+//! can drop or delay packets, so integration tests don't need hardware or fixed
+//! ports.
+//!
+//! Requires the `testing` feature.
+//!
+//! # Examples
+//!
+//! ```
+//! use ddp_rs::protocol::PixelConfig;
+//! use ddp_rs::testing::VirtualDisplay;
+//! use std::time::Duration;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let display = VirtualDisplay::new()?;
+//! let mut conn = display.connect(PixelConfig::default())?;
+//!
+//! conn.write(&[255, 0, 0, 0, 255, 0])?;
+//!
+//! let frame = display.next_frame(Duration::from_secs(1)).expect("no frame");
+//! assert_eq!(frame.data, vec![255, 0, 0, 0, 255, 0]);
+//! # Ok(())
+//! # }
+//! ```
+
+mod display;
+
+#[cfg(test)]
+pub(crate) mod fixtures;
+
+pub use display::{DisplayFrame, DisplayStats, VirtualDisplay};
@@ -0,0 +1,507 @@
+//! Moving encoded DDP packets between the two ends of a connection.
+//!
+//! [`DDPConnection`](crate::connection::DDPConnection) and
+//! [`DDPReceiver`](crate::receiver::DDPReceiver) deal in whole packets and leave
+//! the wire to a [`Transport`], so the same connection logic runs over anything
+//! that can carry a packet:
+//!
+//! - [`UdpTransport`], where every datagram is one packet. This is what
+//!   [`DDPConnection::try_new`](crate::connection::DDPConnection::try_new) uses.
+//! - [`TcpTransport`], which the specification allows on the same port. Packets
+//!   are sent back to back and split again using the length field of each header.
+//! - [`MemoryTransport`], a pair of in-process channels for tests and for wiring
+//!   a sender straight into a receiver.
+//!
+//! Implement [`Transport`] for anything else, such as a serial link to a
+//! controller or a writer that logs packets instead of sending them.
+//!
+//! # Examples
+//!
+//! ```
+//! use ddp_rs::connection::DDPConnection;
+//! use ddp_rs::protocol::{PixelConfig, ID};
+//! use ddp_rs::receiver::DDPReceiver;
+//! use ddp_rs::transport::MemoryTransport;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let (sender, display) = MemoryTransport::pair();
+//! let mut conn = DDPConnection::with_transport(sender, PixelConfig::default(), ID::Default);
+//! let mut receiver = DDPReceiver::with_transport(display);
+//!
+//! conn.write(&[255, 0, 0])?;
+//! assert_eq!(receiver.recv_frame()?, &[255, 0, 0]);
+//! # Ok(())
+//! # }
+//! ```
+
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use std::fmt::Debug;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+
+/// A way of sending and receiving whole DDP packets.
+///
+/// Each call to [`send`](Self::send) carries exactly one encoded packet, header
+/// included, and each [`recv`](Self::recv) returns exactly one.
+pub trait Transport: Debug + Send {
+    /// Sends one encoded packet, returning the number of bytes sent.
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize>;
+
+    /// Waits up to `timeout` (forever for `None`) for one packet from the other
+    /// end and copies it into `buf`. Returns its size, or `None` if nothing
+    /// arrived in time. A zero timeout only takes a packet that is already
+    /// waiting, without blocking.
+    fn recv(&mut self, buf: &mut [u8], timeout: Option<Duration>) -> io::Result<Option<usize>>;
+
+    /// The local address packets are sent from, if there is one.
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Err(ErrorKind::Unsupported.into())
+    }
+
+    /// The address of the other end, if there is one.
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Err(ErrorKind::Unsupported.into())
+    }
+
+    /// The underlying UDP socket, for socket options such as multicast membership.
+    fn udp_socket(&self) -> Option<&UdpSocket> {
+        None
+    }
+}
+
+/// Datagrams on a UDP socket.
+///
+/// With a peer, packets are sent to it and only packets from it are received,
+/// unless it is a multicast group or the socket broadcasts, since then displays
+/// answer from their own address. Without a peer, the transport receives from
+/// anyone and can't send.
+#[derive(Debug)]
+pub struct UdpTransport {
+    socket: UdpSocket,
+    peer: Option<SocketAddr>,
+    nonblocking: bool,
+}
+
+impl UdpTransport {
+    /// A transport on a bound `socket`, talking to `peer`.
+    pub fn new(socket: UdpSocket, peer: Option<SocketAddr>) -> Self {
+        UdpTransport {
+            socket,
+            peer,
+            nonblocking: false,
+        }
+    }
+
+    /// Marks the socket as nonblocking, so receiving knows to wait anyway.
+    pub(crate) fn nonblocking(mut self, nonblocking: bool) -> Self {
+        self.nonblocking = nonblocking;
+        self
+    }
+
+    fn accepts(&self, from: SocketAddr) -> bool {
+        match self.peer {
+            Some(peer) => {
+                from == peer
+                    || peer.ip().is_multicast()
+                    || matches!(peer.ip(), IpAddr::V4(ip) if ip.is_broadcast())
+                    // Subnet broadcast addresses can't be told apart from hosts
+                    || self.socket.broadcast().unwrap_or(false)
+            }
+            None => true,
+        }
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize> {
+        match self.peer {
+            Some(peer) => self.socket.send_to(packet, peer),
+            None => Err(io::Error::new(
+                ErrorKind::NotConnected,
+                "no peer to send to",
+            )),
+        }
+    }
+
+    fn recv(&mut self, buf: &mut [u8], timeout: Option<Duration>) -> io::Result<Option<usize>> {
+        // Timeouts only apply to blocking sockets, and a zero timeout is rejected
+        // by the socket, so it polls in nonblocking mode instead
+        let poll = timeout == Some(Duration::ZERO);
+        if poll != self.nonblocking {
+            self.socket.set_nonblocking(poll)?;
+        }
+        if !poll {
+            self.socket.set_read_timeout(timeout)?;
+        }
+
+        let received = loop {
+            match self.socket.recv_from(buf) {
+                Ok((size, from)) if self.accepts(from) => break Ok(Some(size)),
+                // Someone else talking to our socket
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                    break Ok(None)
+                }
+                Err(e) => break Err(e),
+            }
+        };
+
+        if poll != self.nonblocking {
+            self.socket.set_nonblocking(self.nonblocking)?;
+        }
+        received
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.peer.ok_or_else(|| ErrorKind::NotConnected.into())
+    }
+
+    fn udp_socket(&self) -> Option<&UdpSocket> {
+        Some(&self.socket)
+    }
+}
+
+/// Packets sent back to back over a TCP stream.
+#[derive(Debug)]
+pub struct TcpTransport {
+    stream: TcpStream,
+    // Bytes read past the end of the last packet, or a packet still arriving
+    pending: Vec<u8>,
+}
+
+impl TcpTransport {
+    /// A transport on a connected `stream`.
+    pub fn new(stream: TcpStream) -> io::Result<Self> {
+        // Frames are latency sensitive and usually small
+        stream.set_nodelay(true)?;
+        Ok(TcpTransport {
+            stream,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Reads until a whole packet is in `pending` and moves it into `buf`.
+    fn read_packet(&mut self, buf: &mut [u8]) -> io::Result<Option<usize>> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            if let Some(len) = packet_len(&self.pending) {
+                if len > buf.len() {
+                    // Throw the packet away so the next one can still be read
+                    self.pending.drain(..len);
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("{} byte packet doesn't fit in the buffer", len),
+                    ));
+                }
+                buf[..len].copy_from_slice(&self.pending[..len]);
+                self.pending.drain(..len);
+                return Ok(Some(len));
+            }
+
+            // A partly read packet is kept in `pending` for the next call
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.pending.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {
+                    return Ok(None)
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize> {
+        self.stream.write_all(packet)?;
+        Ok(packet.len())
+    }
+
+    fn recv(&mut self, buf: &mut [u8], timeout: Option<Duration>) -> io::Result<Option<usize>> {
+        // The stream rejects a zero timeout, so that polls in nonblocking mode
+        if timeout == Some(Duration::ZERO) {
+            self.stream.set_nonblocking(true)?;
+            let received = self.read_packet(buf);
+            self.stream.set_nonblocking(false)?;
+            return received;
+        }
+
+        self.stream.set_read_timeout(timeout)?;
+        self.read_packet(buf)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.local_addr()
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+}
+
+/// One end of an in-memory link, created in pairs with [`MemoryTransport::pair`].
+///
+/// Whatever one end sends the other receives, in order and without loss. Once one
+/// end is dropped, sending fails with `BrokenPipe` and receiving, after the
+/// packets already sent, with `UnexpectedEof`.
+#[derive(Debug)]
+pub struct MemoryTransport {
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+}
+
+impl MemoryTransport {
+    /// Creates two connected ends.
+    pub fn pair() -> (MemoryTransport, MemoryTransport) {
+        let (a_tx, b_rx) = unbounded();
+        let (b_tx, a_rx) = unbounded();
+        (
+            MemoryTransport { tx: a_tx, rx: a_rx },
+            MemoryTransport { tx: b_tx, rx: b_rx },
+        )
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn send(&mut self, packet: &[u8]) -> io::Result<usize> {
+        self.tx
+            .send(packet.to_vec())
+            .map_err(|_| io::Error::from(ErrorKind::BrokenPipe))?;
+        Ok(packet.len())
+    }
+
+    fn recv(&mut self, buf: &mut [u8], timeout: Option<Duration>) -> io::Result<Option<usize>> {
+        let packet = match timeout {
+            Some(timeout) => match self.rx.recv_timeout(timeout) {
+                Ok(packet) => packet,
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => return Err(ErrorKind::UnexpectedEof.into()),
+            },
+            None => self
+                .rx
+                .recv()
+                .map_err(|_| io::Error::from(ErrorKind::UnexpectedEof))?,
+        };
+
+        // Like a datagram, whatever doesn't fit is cut off
+        let len = packet.len().min(buf.len());
+        buf[..len].copy_from_slice(&packet[..len]);
+        Ok(Some(len))
+    }
+}
+
+/// Length of the packet at the start of `bytes`, once all of it is there.
+///
+/// The header is 10 bytes, or 14 with the timecode flag, and is followed by as
+/// many data bytes as its length field says.
+pub(crate) fn packet_len(bytes: &[u8]) -> Option<usize> {
+    if bytes.len() < 10 {
+        return None;
+    }
+    let header_len = if bytes[0] & 0x10 != 0 { 14 } else { 10 };
+    let len = header_len + u16::from_be_bytes([bytes[8], bytes[9]]) as usize;
+    (bytes.len() >= len).then_some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_packet_len() {
+        assert_eq!(packet_len(&[0x41, 0, 0, 0]), None);
+        assert_eq!(packet_len(&[0x41, 1, 0x0D, 1, 0, 0, 0, 0, 0, 0]), Some(10));
+
+        let packet = [0x41, 1, 0x0D, 1, 0, 0, 0, 0, 0, 3, 1, 2, 3, 0x41];
+        assert_eq!(packet_len(&packet[..12]), None);
+        assert_eq!(packet_len(&packet), Some(13));
+
+        // Timecode adds 4 bytes to the header
+        let packet = [0x51, 1, 0x0D, 1, 0, 0, 0, 0, 0, 1, 0, 0, 0, 9, 7];
+        assert_eq!(packet_len(&packet[..14]), None);
+        assert_eq!(packet_len(&packet), Some(15));
+    }
+
+    #[test]
+    fn test_udp_filters_peer() {
+        let display = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let stranger = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let mut transport = UdpTransport::new(socket, Some(display.local_addr().unwrap()));
+
+        stranger.send_to(&[1], addr).unwrap();
+        display.send_to(&[2], addr).unwrap();
+
+        let mut buf = [0u8; 16];
+        let timeout = Some(Duration::from_millis(500));
+        assert_eq!(transport.recv(&mut buf, timeout).unwrap(), Some(1));
+        assert_eq!(buf[0], 2);
+        assert_eq!(
+            transport
+                .recv(&mut buf, Some(Duration::from_millis(10)))
+                .unwrap(),
+            None
+        );
+
+        // Without a peer, anyone is heard but nothing can be sent
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let mut transport = UdpTransport::new(socket, None);
+        stranger.send_to(&[3], addr).unwrap();
+        assert_eq!(transport.recv(&mut buf, timeout).unwrap(), Some(1));
+        assert_eq!(
+            transport.send(&[0]).unwrap_err().kind(),
+            ErrorKind::NotConnected
+        );
+        assert_eq!(
+            transport.peer_addr().unwrap_err().kind(),
+            ErrorKind::NotConnected
+        );
+    }
+
+    #[test]
+    fn test_udp_broadcast_peer() {
+        let stranger: SocketAddr = "127.0.0.2:4048".parse().unwrap();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let transport = UdpTransport::new(socket, Some("255.255.255.255:4048".parse().unwrap()));
+        assert!(transport.accepts(stranger));
+
+        // A subnet broadcast address is only known as one by the socket option
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let transport = UdpTransport::new(socket, Some("192.168.1.255:4048".parse().unwrap()));
+        assert!(!transport.accepts(stranger));
+        transport.socket.set_broadcast(true).unwrap();
+        assert!(transport.accepts(stranger));
+    }
+
+    #[test]
+    fn test_zero_timeout_polls() {
+        let zero = Some(Duration::ZERO);
+        let mut buf = [0u8; 64];
+
+        let display = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let mut udp = UdpTransport::new(socket, Some(display.local_addr().unwrap()));
+        assert_eq!(udp.recv(&mut buf, zero).unwrap(), None);
+        display.send_to(&[1, 2], addr).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(udp.recv(&mut buf, zero).unwrap(), Some(2));
+        // Back to blocking with a timeout afterwards
+        assert_eq!(
+            udp.recv(&mut buf, Some(Duration::from_millis(10))).unwrap(),
+            None
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut tcp = TcpTransport::new(listener.accept().unwrap().0).unwrap();
+        assert_eq!(tcp.recv(&mut buf, zero).unwrap(), None);
+        client
+            .write_all(&[0x41, 1, 0x0D, 1, 0, 0, 0, 0, 0, 1, 9])
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(tcp.recv(&mut buf, zero).unwrap(), Some(11));
+        assert_eq!(
+            tcp.recv(&mut buf, Some(Duration::from_millis(10))).unwrap(),
+            None
+        );
+
+        let (mut a, _b) = MemoryTransport::pair();
+        assert_eq!(a.recv(&mut buf, zero).unwrap(), None);
+    }
+
+    #[test]
+    fn test_tcp_framing() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut server = TcpTransport::new(listener.accept().unwrap().0).unwrap();
+        assert_eq!(server.peer_addr().unwrap(), client.local_addr().unwrap());
+
+        // Two packets in one write, then one split over two writes
+        client
+            .write_all(&[
+                0x40, 1, 0x0D, 1, 0, 0, 0, 0, 0, 2, 1, 2, 0x41, 2, 0x0D, 1, 0, 0, 0, 2, 0, 1, 3,
+            ])
+            .unwrap();
+        client.write_all(&[0x41, 3, 0x0D, 1, 0, 0]).unwrap();
+
+        let mut buf = [0u8; 64];
+        let timeout = Some(Duration::from_millis(500));
+        assert_eq!(server.recv(&mut buf, timeout).unwrap(), Some(12));
+        assert_eq!(&buf[10..12], &[1, 2]);
+        assert_eq!(server.recv(&mut buf, timeout).unwrap(), Some(11));
+        assert_eq!(buf[10], 3);
+
+        // Half a packet times out without losing what was read
+        assert!(server
+            .recv(&mut buf, Some(Duration::from_millis(20)))
+            .unwrap()
+            .is_none());
+        client.write_all(&[0, 0, 0, 1, 4]).unwrap();
+        assert_eq!(server.recv(&mut buf, timeout).unwrap(), Some(11));
+        assert_eq!(buf[10], 4);
+
+        drop(client);
+        assert_eq!(
+            server.recv(&mut buf, timeout).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn test_tcp_oversized_packet() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut server = TcpTransport::new(listener.accept().unwrap().0).unwrap();
+
+        let mut packet = vec![0x41, 1, 0x0D, 1, 0, 0, 0, 0, 0, 100];
+        packet.extend_from_slice(&[7; 100]);
+        packet.extend_from_slice(&[0x41, 2, 0x0D, 1, 0, 0, 0, 0, 0, 1, 9]);
+        client.write_all(&packet).unwrap();
+
+        let mut buf = [0u8; 64];
+        let timeout = Some(Duration::from_millis(500));
+        assert_eq!(
+            server.recv(&mut buf, timeout).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+        assert_eq!(server.recv(&mut buf, timeout).unwrap(), Some(11));
+        assert_eq!(buf[10], 9);
+    }
+
+    #[test]
+    fn test_memory_pair() {
+        let (mut a, mut b) = MemoryTransport::pair();
+        let mut buf = [0u8; 4];
+
+        assert_eq!(a.send(&[1, 2, 3]).unwrap(), 3);
+        b.send(&[4, 5, 6, 7, 8]).unwrap();
+        assert_eq!(b.recv(&mut buf, None).unwrap(), Some(3));
+        assert_eq!(&buf[..3], &[1, 2, 3]);
+        assert_eq!(a.recv(&mut buf, None).unwrap(), Some(4));
+        assert_eq!(buf, [4, 5, 6, 7]);
+
+        assert_eq!(
+            a.recv(&mut buf, Some(Duration::from_millis(10))).unwrap(),
+            None
+        );
+        assert_eq!(a.local_addr().unwrap_err().kind(), ErrorKind::Unsupported);
+
+        drop(b);
+        assert_eq!(a.send(&[1]).unwrap_err().kind(), ErrorKind::BrokenPipe);
+        assert_eq!(
+            a.recv(&mut buf, None).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+    }
+}